@@ -0,0 +1,157 @@
+#ifndef MPIC_H
+#define MPIC_H
+
+/* Generated by cbindgen from src/capi.rs. Do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Result of a function of the C API.
+ */
+typedef enum {
+  /**
+   * Success.
+   */
+  MPIC_STATUS_OK = 0,
+  /**
+   * A pointer is null, or an argument is invalid.
+   */
+  MPIC_STATUS_INVALID_INPUT,
+  /**
+   * The output buffer is too small.
+   */
+  MPIC_STATUS_BUFFER_TOO_SMALL,
+  /**
+   * The data is corrupted or cannot be decoded.
+   */
+  MPIC_STATUS_INVALID_DATA,
+  /**
+   * The data is shorter than the file header.
+   */
+  MPIC_STATUS_TRUNCATED_HEADER,
+  /**
+   * The data is not an MPIC file.
+   */
+  MPIC_STATUS_BAD_MAGIC,
+  /**
+   * The version in the file header is not supported, or cannot represent the image.
+   */
+  MPIC_STATUS_UNSUPPORTED_VERSION,
+  /**
+   * The width or height is invalid, or too large for a row offset table.
+   */
+  MPIC_STATUS_INVALID_SIZE,
+  /**
+   * The metadata section is malformed.
+   */
+  MPIC_STATUS_INVALID_METADATA,
+  /**
+   * A chunk is truncated, has an invalid size or does not decompress to a block.
+   */
+  MPIC_STATUS_INVALID_CHUNK,
+  /**
+   * Unexpected bytes remain after the last chunk.
+   */
+  MPIC_STATUS_TRAILING_DATA,
+  /**
+   * The file has no checksum, or does not match it.
+   */
+  MPIC_STATUS_CHECKSUM_MISMATCH,
+} MpicStatus;
+
+/**
+ * Decoder of an MPIC file in memory.
+ *
+ * Created by [`mpic_decoder_new`]. The fields are private, and the data must outlive the decoder.
+ */
+typedef struct {
+  const uint8_t *data;
+  size_t len;
+} MpicDecoder;
+
+/**
+ * Image information from the file header.
+ */
+typedef struct {
+  /**
+   * Width of the image in pixels
+   */
+  uint32_t width;
+  /**
+   * Height of the image in pixels
+   */
+  uint32_t height;
+  /**
+   * Version of the file
+   */
+  uint8_t version;
+} MpicInfo;
+
+/**
+ * Create a decoder of the MPIC file of `len` bytes at `data`, checking its header and metadata section.
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes that outlive the decoder, and `decoder` must be null or writable.
+ */
+MpicStatus mpic_decoder_new(const uint8_t *data,
+                            size_t len,
+                            MpicDecoder *decoder);
+
+/**
+ * Get the image information of the file.
+ *
+ * # Safety
+ *
+ * `decoder` must be null or created by [`mpic_decoder_new`], and `info` must be null or writable.
+ */
+MpicStatus mpic_info(const MpicDecoder *decoder, MpicInfo *info);
+
+/**
+ * Decode the image to `len` RGB565 pixels at `output`, which must hold at least `width * height` pixels.
+ *
+ * If `dither` is true, ordered dithering is applied when reducing the red and blue channels to 5 bits.
+ *
+ * # Safety
+ *
+ * `decoder` must be null or created by [`mpic_decoder_new`], and `output` must point to `len` writable pixels.
+ */
+MpicStatus mpic_decode_rgb565(const MpicDecoder *decoder,
+                              uint16_t *output,
+                              size_t len,
+                              bool dither);
+
+/**
+ * Decode a single chunk of `len` bytes at `data`, excluding its size, to the Y, U and V planes of 64 values each.
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes, and `y`, `u` and `v` must each point to 64 writable bytes.
+ */
+MpicStatus mpic_decode_chunk(const uint8_t *data,
+                             size_t len,
+                             uint8_t *y,
+                             uint8_t *u,
+                             uint8_t *v);
+
+/**
+ * Encode an RGB888 image of `width * height` pixels (`len` bytes) at `data` to the buffer of `capacity` bytes at `output`.
+ *
+ * The size of the file is stored to `written`, even if the buffer is too small,
+ * so that the call can be repeated with a large enough buffer.
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes, `output` to `capacity` writable bytes, and `written` must be null or writable.
+ */
+MpicStatus mpic_encode(const uint8_t *data,
+                       size_t len,
+                       uint32_t width,
+                       uint32_t height,
+                       uint8_t *output,
+                       size_t capacity,
+                       size_t *written);
+
+#endif  /* MPIC_H */
@@ -0,0 +1,17 @@
+//! Static library of the C API of MPIC
+//!
+//! Link `libmpic.a` and include `include/mpic.h`.
+//! Build without the default `std` feature for firmware, which provides a panic handler that halts.
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+pub use mpic::capi::*;
+
+#[cfg(not(feature = "std"))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
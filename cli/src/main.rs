@@ -17,9 +17,16 @@ fn main() {
     let _ = args.next().unwrap();
 
     let input = match args.next() {
-        Some(v) => PathBuf::from(v),
+        Some(v) => v,
         None => usage(),
     };
+    if TRANSFORM_COMMANDS.contains(&input.as_str()) {
+        return transform(&input, args);
+    }
+    if input == "atlas" {
+        return atlas(args);
+    }
+    let input = PathBuf::from(input);
 
     let ext = input.extension().expect("unknown file extention");
     let input_format = match ext {
@@ -70,11 +77,11 @@ fn main() {
             let rgb = dynamic_image.to_rgb8();
             let raw_image = rgb.as_raw();
 
-            let output_buf = mpic::Encoder::encode2(
-                &raw_image,
+            let output_buf = mpic::Encoder::encode_with_options(
+                raw_image,
                 rgb.width(),
                 rgb.height(),
-                mpic::lz::CompressionLevel::Best,
+                &mpic::EncoderOptions::new().with_level(mpic::lz::CompressionLevel::Best),
             )
             .expect("cannot write output");
             std::fs::write(&output, output_buf).expect("cannot write output");
@@ -87,11 +94,107 @@ fn main() {
     }
 }
 
+const TRANSFORM_COMMANDS: [&str; 6] = [
+    "rotate90",
+    "rotate180",
+    "rotate270",
+    "flip-h",
+    "flip-v",
+    "crop",
+];
+
+/// Transform an MPIC file without decoding it to pixels.
+fn transform(command: &str, mut args: env::Args) {
+    let mut next_number = || -> u32 {
+        args.next()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| usage())
+    };
+    let rect =
+        (command == "crop").then(|| (next_number(), next_number(), next_number(), next_number()));
+    let (input, output) = match (args.next(), args.next()) {
+        (Some(input), Some(output)) => (PathBuf::from(input), PathBuf::from(output)),
+        _ => usage(),
+    };
+
+    let read_data = std::fs::read(&input).expect("cannot read input");
+    let result = match (command, rect) {
+        ("rotate90", _) => mpic::transform::rotate(&read_data, mpic::Rotation::Deg90),
+        ("rotate180", _) => mpic::transform::rotate(&read_data, mpic::Rotation::Deg180),
+        ("rotate270", _) => mpic::transform::rotate(&read_data, mpic::Rotation::Deg270),
+        ("flip-h", _) => mpic::transform::flip_horizontal(&read_data),
+        ("flip-v", _) => mpic::transform::flip_vertical(&read_data),
+        ("crop", Some((left, top, width, height))) => {
+            mpic::transform::crop(&read_data, left, top, width, height)
+        }
+        _ => unreachable!(),
+    };
+    let output_buf = result.unwrap_or_else(|err| {
+        eprintln!("cannot transform input: {}", err);
+        exit(1);
+    });
+    std::fs::write(&output, output_buf).expect("cannot write output");
+}
+
+/// Pack images into an atlas file, naming each entry after its file name without the extension.
+fn atlas(mut args: env::Args) {
+    let output = args.next().map(PathBuf::from).unwrap_or_else(|| usage());
+    let inputs = args.map(PathBuf::from).collect::<Vec<_>>();
+    if inputs.is_empty() {
+        usage();
+    }
+
+    let images = inputs
+        .iter()
+        .map(|input| {
+            let name = input
+                .file_stem()
+                .and_then(|v| v.to_str())
+                .expect("invalid file name")
+                .to_owned();
+            let read_data = std::fs::read(input).expect("cannot read input");
+            if input
+                .extension()
+                .is_some_and(|ext| ext == mpic::PREFERRED_FILE_EXT)
+            {
+                return (name, read_data);
+            }
+            let rgb = image::load_from_memory(&read_data)
+                .expect("cannot decode input file")
+                .to_rgb8();
+            let data = mpic::Encoder::encode_with_options(
+                rgb.as_raw(),
+                rgb.width(),
+                rgb.height(),
+                &mpic::EncoderOptions::new().with_level(mpic::lz::CompressionLevel::Best),
+            )
+            .expect("cannot encode input file");
+            (name, data)
+        })
+        .collect::<Vec<_>>();
+
+    let mut builder = mpic::AtlasBuilder::new();
+    for (name, data) in &images {
+        if let Err(err) = builder.add_image(name, data) {
+            eprintln!("cannot add {}: {}", name, err);
+            exit(1);
+        }
+    }
+    let output_buf = builder.build().expect("cannot write output");
+    std::fs::write(&output, output_buf).expect("cannot write output");
+}
+
 fn usage() -> ! {
     let mut args = env::args_os();
     let arg = args.next().unwrap();
     let path = Path::new(&arg);
-    let lpc = path.file_name().unwrap();
-    eprintln!("usage: {} INFILE [OUTFILE]", lpc.to_str().unwrap());
+    let lpc = path.file_name().unwrap().to_str().unwrap();
+    eprintln!("usage: {} INFILE [OUTFILE]", lpc);
+    eprintln!(
+        "       {} rotate90|rotate180|rotate270|flip-h|flip-v INFILE OUTFILE",
+        lpc
+    );
+    eprintln!("       {} crop LEFT TOP WIDTH HEIGHT INFILE OUTFILE", lpc);
+    eprintln!("       {} atlas OUTFILE INFILE...", lpc);
     exit(1);
 }
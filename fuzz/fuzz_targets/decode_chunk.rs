@@ -0,0 +1,19 @@
+//! Decode a single chunk of arbitrary bytes.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpic::{
+    Decoder,
+    color::{MpicRgb666, MpicYuv666},
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((buf_y, buf_u, buf_v)) = Decoder::<()>::decode_chunk(data) {
+        // Raw chunks pass their bytes through, so the conversion must cope with any value.
+        for (i, &y) in buf_y.iter().enumerate() {
+            let uv = (i / 8 / 2) * 4 + (i % 8) / 2;
+            let _ = MpicRgb666::from_yuv(MpicYuv666::new(y, buf_u[uv], buf_v[uv]));
+        }
+    }
+});
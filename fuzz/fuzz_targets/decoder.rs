@@ -0,0 +1,47 @@
+//! Parse and decode arbitrary bytes in every mode.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpic::{Decoder, Scale};
+
+/// Larger images are only validated, since a 9-byte header can claim 4 gigapixels.
+const MAX_PIXELS: u32 = 512 * 512;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Decoder::<()>::new_strict(data);
+    let Some(decoder) = Decoder::<()>::new(data) else {
+        return;
+    };
+    let _ = decoder.validate();
+    let _ = decoder.verify();
+    if let Some(thumbnail) = decoder.thumbnail() {
+        let _ = thumbnail.decode();
+    }
+    let info = decoder.info();
+    if info.width() * info.height() > MAX_PIXELS {
+        return;
+    }
+    for (verify, lenient) in [(false, false), (false, true), (true, true)] {
+        let decoder = Decoder::<()>::new(data)
+            .unwrap()
+            .with_verification(verify)
+            .with_lenient(lenient);
+        let rgb = decoder.decode();
+        let rgba = decoder.decode_rgba();
+        if let (Ok(rgb), Ok(rgba)) = (&rgb, &rgba) {
+            assert_eq!(rgb.len() / 3, rgba.len() / 4);
+        }
+        if lenient {
+            // Lenient mode fills damaged blocks instead of failing, except for missing checksums.
+            assert!(rgb.is_ok() || verify);
+        }
+        let _ = decoder.decode_rgb565(true);
+        let _ = decoder.decode_scaled(Scale::Half);
+    }
+    if let Some(animation) = decoder.animation() {
+        for index in 0..animation.frame_count().min(16) {
+            let _ = animation.decode_frame(index);
+        }
+    }
+});
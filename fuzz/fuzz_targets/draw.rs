@@ -0,0 +1,45 @@
+//! Draw arbitrary bytes through every `ImageDrawable`, clipped to a small display.
+
+#![no_main]
+
+use embedded_graphics::{
+    image::ImageDrawable, mock_display::MockDisplay, pixelcolor::Rgb565, prelude::*,
+    primitives::Rectangle,
+};
+use libfuzzer_sys::fuzz_target;
+use mpic::{Atlas, Decoder, Scale};
+
+fuzz_target!(|data: &[u8]| {
+    let mut display = MockDisplay::<Rgb565>::new();
+    // Blocks are drawn once per frame, and may be drawn over by the next one.
+    display.set_allow_overdraw(true);
+    let mut target = display.clipped(&Rectangle::new(Point::zero(), Size::new(64, 64)));
+
+    if let Some(atlas) = Atlas::<Rgb565>::new(data) {
+        for entry in atlas.entries() {
+            let _ = entry.draw(&mut target);
+        }
+    }
+    let Some(decoder) = Decoder::<Rgb565>::new(data) else {
+        return;
+    };
+    for (verify, lenient) in [(false, false), (false, true), (true, true)] {
+        let decoder = Decoder::<Rgb565>::new(data)
+            .unwrap()
+            .with_verification(verify)
+            .with_lenient(lenient);
+        let _ = decoder.draw(&mut target);
+        let _ = decoder.draw_sub_image(
+            &mut target,
+            &Rectangle::new(Point::new(-3, 5), Size::new(40, 30)),
+        );
+    }
+    let _ = decoder.dithered().draw(&mut target);
+    let _ = decoder.transformed().draw(&mut target);
+    let _ = decoder.scaled(Scale::Quarter).draw(&mut target);
+    if let Some(animation) = decoder.animation() {
+        for frame in animation.frames().take(16) {
+            let _ = frame.draw(&mut target);
+        }
+    }
+});
@@ -0,0 +1,88 @@
+//! Encode arbitrary pixels with arbitrary options, and check that the result decodes.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use mpic::{
+    ChromaFilter, Decoder, Dither, EdgePadding, Encoder, EncoderOptions, lz::CompressionLevel,
+};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    width: u8,
+    height: u8,
+    level: u8,
+    chroma_filter: u8,
+    edge_padding: u8,
+    dither: u8,
+    quality: u8,
+    thumbnail: bool,
+    row_offsets: bool,
+    checksum: bool,
+    pixels: Vec<u8>,
+    /// A block of 6-bit Y values and 4x4 U and V values for the chunk round trip
+    block: [u8; 96],
+}
+
+fuzz_target!(|input: Input| {
+    let (width, height) = (input.width as u32 + 1, input.height as u32 + 1);
+    let options = EncoderOptions::new()
+        .with_level(
+            [
+                CompressionLevel::Fast,
+                CompressionLevel::Default,
+                CompressionLevel::Best,
+            ][input.level as usize % 3],
+        )
+        .with_chroma_filter(
+            [
+                ChromaFilter::Box,
+                ChromaFilter::Average,
+                ChromaFilter::LumaWeighted,
+                ChromaFilter::Sharp,
+            ][input.chroma_filter as usize % 4],
+        )
+        .with_edge_padding(
+            [
+                EdgePadding::Replicate,
+                EdgePadding::Mirror,
+                EdgePadding::Flat,
+                EdgePadding::Smallest,
+            ][input.edge_padding as usize % 4],
+        )
+        .with_dither(
+            [Dither::None, Dither::Ordered, Dither::FloydSteinberg][input.dither as usize % 3],
+        )
+        .with_quality(input.quality)
+        .with_thumbnail(input.thumbnail)
+        .with_row_offsets(input.row_offsets)
+        .with_checksum(input.checksum);
+
+    let len = (width * height * 3) as usize;
+    let mut pixels = input.pixels;
+    let result = Encoder::encode_with_options(&pixels, width, height, &options);
+    if pixels.len() < len {
+        assert!(result.is_err());
+        pixels.resize(len, 0x80);
+    }
+    let encoded = Encoder::encode_with_options(&pixels, width, height, &options).unwrap();
+    let decoder = Decoder::<()>::new_strict(&encoded).unwrap();
+    if input.checksum {
+        decoder.verify().unwrap();
+    }
+    assert_eq!(decoder.has_row_offsets(), input.row_offsets);
+    assert_eq!(decoder.decode().unwrap().len(), len);
+
+    // Chunks are lossless for 6-bit values whose U and V are the same in each 2x2 pixels.
+    let buf_y: [u8; 64] = core::array::from_fn(|i| input.block[i] & 0x3F);
+    let quad = |offset: usize| -> [u8; 64] {
+        core::array::from_fn(|i| input.block[offset + (i / 16) * 4 + (i % 8) / 2] & 0x3F)
+    };
+    let (buf_u, buf_v) = (quad(64), quad(80));
+    let chunk = Encoder::encode_chunk(&buf_y, &buf_u, &buf_v, options.level());
+    assert_eq!(
+        Decoder::<()>::decode_chunk(&chunk),
+        Ok((buf_y, buf_u, buf_v))
+    );
+});
@@ -0,0 +1,117 @@
+//! Compile-time embedding of images in MPIC format
+//!
+//! [`include_mpic!`] encodes an image when the crate is built, and expands to a [`mpic::Decoder`]
+//! that is validated at compile time, so that an invalid asset fails the build instead of the device.
+//!
+//! ```ignore
+//! use mpic::{Decoder, ImageInfo};
+//! use mpic_macros::include_mpic;
+//!
+//! const LOGO: Decoder<'static, Rgb565> = include_mpic!("assets/logo.png", Best);
+//! const LOGO_INFO: ImageInfo = LOGO.info();
+//! ```
+
+use mpic::{Decoder, Encoder, EncoderOptions, lz::CompressionLevel};
+use proc_macro::TokenStream;
+use proc_macro2::Literal;
+use quote::quote;
+use std::path::PathBuf;
+use syn::{
+    Ident, LitStr, Token,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+};
+
+/// Arguments of [`include_mpic!`]: a path and an optional compression level.
+struct Input {
+    path: LitStr,
+    level: CompressionLevel,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let mut level = CompressionLevel::Default;
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let ident = input.parse::<Ident>()?;
+            level = match ident.to_string().as_str() {
+                "Fast" => CompressionLevel::Fast,
+                "Default" => CompressionLevel::Default,
+                "Best" => CompressionLevel::Best,
+                _ => {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        "expected a compression level: `Fast`, `Default` or `Best`",
+                    ));
+                }
+            };
+            input.parse::<Option<Token![,]>>()?;
+        }
+        Ok(Self { path, level })
+    }
+}
+
+/// Embed an image as a [`mpic::Decoder<'static, T>`](mpic::Decoder) that is validated at compile time.
+///
+/// The path is relative to the directory of the `Cargo.toml` of the crate being built.
+/// A `.mpic` file is embedded as it is, and any other file is decoded as PNG and encoded with the given
+/// [`CompressionLevel`] (`Fast`, `Default` or `Best`, `Default` if omitted).
+///
+/// The expansion is an inline `const` block made with [`Decoder::from_validated`],
+/// so it can initialize a `const` or a `static`, and its [`ImageInfo`](mpic::ImageInfo) is known at compile time.
+/// The file is tracked like with [`include_bytes!`], so that changing it rebuilds the crate.
+///
+/// Fails to compile if the file cannot be read, the image is too large, or a `.mpic` file does not pass
+/// [`Decoder::new_strict`].
+#[proc_macro]
+pub fn include_mpic(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as Input);
+    match embed(&input) {
+        Ok((path, blob)) => {
+            let path = path.to_string_lossy();
+            let blob = Literal::byte_string(&blob);
+            quote! {
+                {
+                    const _: &[u8] = include_bytes!(#path);
+                    const {
+                        match ::mpic::Decoder::from_validated(#blob) {
+                            Some(decoder) => decoder,
+                            None => panic!("invalid MPIC file"),
+                        }
+                    }
+                }
+            }
+            .into()
+        }
+        Err(message) => syn::Error::new(input.path.span(), message)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+/// Read or encode the file, and return its full path and the validated MPIC data.
+fn embed(input: &Input) -> Result<(PathBuf, Vec<u8>), String> {
+    let mut path = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    path.push(input.path.value());
+    let display = path.display();
+
+    let blob = if path.extension().is_some_and(|ext| ext == "mpic") {
+        std::fs::read(&path).map_err(|err| format!("cannot read {display}: {err}"))?
+    } else {
+        let image = image::open(&path)
+            .map_err(|err| format!("cannot load {display}: {err}"))?
+            .to_rgb8();
+        Encoder::encode_with_options(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            &EncoderOptions::new().with_level(input.level),
+        )
+        .map_err(|err| format!("cannot encode {display}: {err}"))?
+    };
+    Decoder::<()>::new_strict(&blob)
+        .map_err(|err| format!("invalid MPIC file {display}: {err}"))?;
+    Ok((path, blob))
+}
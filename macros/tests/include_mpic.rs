@@ -0,0 +1,42 @@
+use mpic::{Decoder, Encoder, EncoderOptions, ImageInfo, lz::CompressionLevel};
+use mpic_macros::include_mpic;
+
+const PARROTS: Decoder<'static, ()> = include_mpic!("../images/Parrots.png", Fast);
+const PARROTS_INFO: ImageInfo = PARROTS.info();
+static CHECKSUM: Decoder<'static, ()> = include_mpic!("../conformance/valid/v2-checksum.mpic");
+
+#[test]
+fn encode_png() {
+    let image = image::open(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../images/Parrots.png"
+    ))
+    .unwrap()
+    .to_rgb8();
+    assert_eq!(
+        (PARROTS_INFO.width(), PARROTS_INFO.height()),
+        image.dimensions()
+    );
+    let encoded = Encoder::encode_with_options(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        &EncoderOptions::new().with_level(CompressionLevel::Fast),
+    )
+    .unwrap();
+    assert_eq!(
+        PARROTS.decode(),
+        Decoder::<()>::new_strict(&encoded).unwrap().decode()
+    );
+}
+
+#[test]
+fn embed_mpic() {
+    let blob = include_bytes!("../../conformance/valid/v2-checksum.mpic");
+    assert_eq!(CHECKSUM.info(), Decoder::<()>::new(blob).unwrap().info());
+    assert_eq!(CHECKSUM.verify(), Ok(()));
+    assert_eq!(
+        CHECKSUM.decode().unwrap(),
+        include_bytes!("../../conformance/valid/v2-checksum.rgb")
+    );
+}
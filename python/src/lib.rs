@@ -0,0 +1,216 @@
+//! Python bindings for asset pipeline scripts
+//!
+//! Build and install into the current virtual environment with `maturin develop --release`, then in Python:
+//!
+//! ```python
+//! import mpic
+//! data = mpic.encode(pixels, level="best")  # pixels: numpy.ndarray of shape (height, width, 3) and dtype uint8
+//! print(mpic.info(data), mpic.metrics(pixels, data))
+//! pixels = mpic.decode(data)
+//! ```
+
+use mpic::{Decoder, Encoder, EncoderOptions, FileHeader, PixelFormat, lz::CompressionLevel};
+use numpy::{IntoPyArray, PyArray3, PyReadonlyArray3, PyUntypedArrayMethods, ndarray::Array3};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+/// Convert an error of the library to a Python `ValueError` with its description.
+fn value_error(err: impl ToString) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Return the compression level of its name, one of `fast`, `default` or `best`.
+fn compression_level(name: &str) -> PyResult<CompressionLevel> {
+    match name {
+        "fast" => Ok(CompressionLevel::Fast),
+        "default" => Ok(CompressionLevel::Default),
+        "best" => Ok(CompressionLevel::Best),
+        _ => Err(PyValueError::new_err(format!(
+            "unknown compression level {name:?}, expected \"fast\", \"default\" or \"best\""
+        ))),
+    }
+}
+
+/// Return the width, the height and the pixel format of an array of shape `(height, width, 3 or 4)`.
+fn image_shape(array: &PyReadonlyArray3<u8>) -> PyResult<(u32, u32, PixelFormat)> {
+    let [height, width, channels] = *array.shape() else {
+        unreachable!()
+    };
+    let pixel_format = match channels {
+        3 => PixelFormat::Rgb888,
+        4 => PixelFormat::Rgba8888,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "expected 3 (RGB) or 4 (RGBA) channels, got {channels}"
+            )));
+        }
+    };
+    match (u32::try_from(width), u32::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height, pixel_format)),
+        _ => Err(PyValueError::new_err(format!(
+            "invalid size {width}x{height}"
+        ))),
+    }
+}
+
+/// Encode an image to an MPIC file.
+///
+/// `array` is a `numpy.ndarray` of dtype `uint8` and shape `(height, width, 3)` for RGB,
+/// or `(height, width, 4)` for RGBA whose alpha is ignored.
+/// `level` is `"fast"`, `"default"` or `"best"`.
+#[pyfunction]
+#[pyo3(signature = (array, level = "default"))]
+fn encode<'py>(
+    py: Python<'py>,
+    array: PyReadonlyArray3<'py, u8>,
+    level: &str,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let (width, height, pixel_format) = image_shape(&array)?;
+    let options = EncoderOptions::new()
+        .with_level(compression_level(level)?)
+        .with_pixel_format(pixel_format);
+    let pixels = array.as_array();
+    let data = match pixels.as_slice() {
+        Some(slice) => py.detach(|| Encoder::encode_with_options(slice, width, height, &options)),
+        None => {
+            let pixels = pixels.iter().copied().collect::<Vec<_>>();
+            py.detach(|| Encoder::encode_with_options(&pixels, width, height, &options))
+        }
+    }
+    .map_err(value_error)?;
+    Ok(PyBytes::new(py, &data))
+}
+
+/// Decode an MPIC file to a `numpy.ndarray` of dtype `uint8` and shape `(height, width, 3)` in RGB.
+///
+/// Raises `ValueError` with the description of the error if the data is invalid.
+#[pyfunction]
+fn decode<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    let pixels = py.detach(|| decode_rgb(data))?;
+    Ok(pixels.into_pyarray(py))
+}
+
+/// Decode an MPIC file to an array of shape `(height, width, 3)`.
+fn decode_rgb(data: &[u8]) -> PyResult<Array3<u8>> {
+    let decoder = Decoder::<()>::new_strict(data).map_err(value_error)?;
+    let info = decoder.info();
+    let pixels = decoder.decode().map_err(value_error)?;
+    Array3::from_shape_vec((info.height() as usize, info.width() as usize, 3), pixels)
+        .map_err(value_error)
+}
+
+/// Image information of an MPIC file.
+#[pyclass(module = "mpic", frozen, get_all)]
+#[derive(Debug, Clone)]
+struct Info {
+    /// Width of the image in pixels
+    width: u32,
+    /// Height of the image in pixels
+    height: u32,
+    /// Version of the file
+    version: u8,
+    /// Whether the file has a checksum record
+    has_checksum: bool,
+}
+
+#[pymethods]
+impl Info {
+    fn __repr__(&self) -> String {
+        format!(
+            "Info(width={}, height={}, version={}, has_checksum={})",
+            self.width,
+            self.height,
+            self.version,
+            if self.has_checksum { "True" } else { "False" },
+        )
+    }
+}
+
+/// Return the image information of an MPIC file without decoding the image.
+///
+/// Only the file header and the metadata section are checked.
+#[pyfunction]
+fn info(data: &[u8]) -> PyResult<Info> {
+    let header = FileHeader::parse(data).map_err(value_error)?;
+    let decoder = Decoder::<()>::new(data)
+        .ok_or_else(|| PyValueError::new_err("invalid metadata section"))?;
+    Ok(Info {
+        width: decoder.info().width(),
+        height: decoder.info().height(),
+        version: header.version().0,
+        has_checksum: decoder.has_checksum(),
+    })
+}
+
+/// Quality and size of an encoded image compared with the original.
+#[pyclass(module = "mpic", frozen, get_all)]
+#[derive(Debug, Clone)]
+struct Metrics {
+    /// Size of the file in bytes
+    size: usize,
+    /// Size of the file in bits per pixel
+    bits_per_pixel: f64,
+    /// Peak signal-to-noise ratio in decibels, infinite if the image is lossless
+    psnr: f64,
+    /// Largest difference of a channel of a pixel
+    max_error: u8,
+}
+
+#[pymethods]
+impl Metrics {
+    fn __repr__(&self) -> String {
+        format!(
+            "Metrics(size={}, bits_per_pixel={:.3}, psnr={:.2}, max_error={})",
+            self.size, self.bits_per_pixel, self.psnr, self.max_error,
+        )
+    }
+}
+
+/// Compare an MPIC file with the original image it was encoded from.
+///
+/// `original` has the same shape as for [`encode`], and its alpha is ignored.
+#[pyfunction]
+fn metrics<'py>(
+    py: Python<'py>,
+    original: PyReadonlyArray3<'py, u8>,
+    data: &[u8],
+) -> PyResult<Metrics> {
+    let (width, height, _) = image_shape(&original)?;
+    let original = original.as_array();
+    py.detach(|| {
+        let decoded = decode_rgb(data)?;
+        let (decoded_height, decoded_width, _) = decoded.dim();
+        if (decoded_width, decoded_height) != (width as usize, height as usize) {
+            return Err(PyValueError::new_err(format!(
+                "size mismatch: the original is {width}x{height}, but the file is {decoded_width}x{decoded_height}"
+            )));
+        }
+        let mut sum = 0u64;
+        let mut max_error = 0u8;
+        for ((y, x, c), &value) in decoded.indexed_iter() {
+            let error = value.abs_diff(original[(y, x, c)]);
+            sum += u64::from(error) * u64::from(error);
+            max_error = max_error.max(error);
+        }
+        let pixels = decoded.len() / 3;
+        let mse = sum as f64 / decoded.len().max(1) as f64;
+        Ok(Metrics {
+            size: data.len(),
+            bits_per_pixel: data.len() as f64 * 8.0 / pixels.max(1) as f64,
+            psnr: 10.0 * (255.0 * 255.0 / mse).log10(),
+            max_error,
+        })
+    })
+}
+
+/// Simple Lossy Compression Image Format for Embedded Platforms
+#[pymodule]
+#[pyo3(name = "mpic")]
+fn mpic_module(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(encode, module)?)?;
+    module.add_function(wrap_pyfunction!(decode, module)?)?;
+    module.add_function(wrap_pyfunction!(info, module)?)?;
+    module.add_function(wrap_pyfunction!(metrics, module)?)?;
+    module.add_class::<Info>()?;
+    module.add_class::<Metrics>()?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+import mpic
+import numpy as np
+import pytest
+
+
+def gradient_image(width, height, channels=3):
+    y, x = np.mgrid[0:height, 0:width]
+    image = np.zeros((height, width, channels), dtype=np.uint8)
+    image[..., 0] = 64 + x * 8
+    image[..., 1] = 64 + y * 8
+    image[..., 2] = 128 + (x + y) * 4
+    if channels == 4:
+        image[..., 3] = 255
+    return image
+
+
+@pytest.mark.parametrize("level", ["fast", "default", "best"])
+def test_round_trip(level):
+    pixels = gradient_image(13, 10)
+    data = mpic.encode(pixels, level=level)
+    decoded = mpic.decode(data)
+    assert decoded.shape == (10, 13, 3)
+    assert decoded.dtype == np.uint8
+    assert np.abs(decoded.astype(int) - pixels.astype(int)).max() <= 16
+
+    info = mpic.info(data)
+    assert (info.width, info.height) == (13, 10)
+    assert info.version == 1
+    assert not info.has_checksum
+
+    metrics = mpic.metrics(pixels, data)
+    assert metrics.size == len(data)
+    assert metrics.bits_per_pixel == pytest.approx(len(data) * 8 / (13 * 10))
+    assert metrics.psnr > 30
+    assert metrics.max_error <= 16
+
+
+def test_rgba_ignores_alpha():
+    rgb = gradient_image(16, 8)
+    rgba = gradient_image(16, 8, channels=4)
+    assert mpic.encode(rgba) == mpic.encode(rgb)
+    assert mpic.encode(rgba[:, ::-1]) == mpic.encode(rgb[:, ::-1])
+
+
+def test_invalid_input():
+    pixels = gradient_image(8, 8)
+    with pytest.raises(ValueError):
+        mpic.encode(pixels, level="fastest")
+    with pytest.raises(ValueError):
+        mpic.encode(np.zeros((8, 8, 2), dtype=np.uint8))
+    with pytest.raises(ValueError):
+        mpic.decode(b"not an image")
+    with pytest.raises(ValueError):
+        mpic.info(b"not an image")
+    with pytest.raises(ValueError):
+        mpic.metrics(gradient_image(16, 8), mpic.encode(pixels))
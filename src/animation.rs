@@ -0,0 +1,341 @@
+use crate::{chunk::UNCOMPRESSED_SIZE, color::*, metadata::RECORD_HEADER_SIZE, *};
+use heapless::Vec;
+
+#[cfg(feature = "embedded")]
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Size of the loop count at the start of the frame table
+const LOOP_COUNT_SIZE: usize = 2;
+/// Size of the duration and the size of the chunks of a frame in the frame table
+const FRAME_ENTRY_SIZE: usize = 6;
+
+/// Animation stored in the file.
+///
+/// The first frame is the regular image data. Each following frame is a sequence of chunks
+/// in which a chunk of size zero marks a block that is the same as in the previous frame,
+/// and a delta chunk is compressed with the same block of the previous frame as a dictionary,
+/// which must be stored there as a regular chunk.
+///
+/// See [`Decoder::animation`].
+pub struct Animation<'a, 'b, T> {
+    decoder: &'b Decoder<'a, T>,
+    table: &'a [u8],
+}
+
+/// A frame of an animation.
+///
+/// Drawing a frame only draws the blocks that changed since the previous frame,
+/// so the frames must be drawn in order over the same area. The first frame draws every block.
+pub struct AnimationFrame<'a, 'b, T> {
+    decoder: &'b Decoder<'a, T>,
+    table: &'a [u8],
+    index: usize,
+    duration: u16,
+    offset: usize,
+    size: usize,
+}
+
+impl<'a, T> Decoder<'a, T> {
+    /// Return the animation stored in the file, if any.
+    ///
+    /// Returns `None` for a still image or if the frame table is malformed.
+    #[inline]
+    pub fn animation(&self) -> Option<Animation<'a, '_, T>> {
+        self.animation_table()
+            .ok()
+            .flatten()
+            .map(|table| Animation {
+                decoder: self,
+                table,
+            })
+    }
+
+    /// Find the frame table of the animation.
+    pub(crate) fn animation_table(&self) -> Result<Option<&'a [u8]>, DecodeError> {
+        let Some(table) = self.metadata().find_map(|record| match record {
+            Metadata::Animation(data) => Some(data),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        if table.len() < LOOP_COUNT_SIZE + FRAME_ENTRY_SIZE
+            || !(table.len() - LOOP_COUNT_SIZE).is_multiple_of(FRAME_ENTRY_SIZE)
+        {
+            return Err(DecodeError::InvalidMetadata {
+                offset: self.offset_of(table) - RECORD_HEADER_SIZE,
+            });
+        }
+        Ok(Some(table))
+    }
+
+    /// Validate the chunks of every frame and return the byte offset past the last frame.
+    pub(crate) fn validate_frames(&self, table: &'a [u8]) -> Result<usize, DecodeError> {
+        let animation = Animation {
+            decoder: self,
+            table,
+        };
+        let mut end = self.data_offset();
+        for frame in animation.frames() {
+            end = frame._decode_planes(|_, _, _, _, _, _, _| {})?;
+            if end != frame.offset.saturating_add(frame.size) {
+                return Err(DecodeError::InvalidFrame {
+                    index: frame.index,
+                    offset: frame.offset,
+                });
+            }
+        }
+        Ok(end)
+    }
+}
+
+/// Return the duration and the size of the chunks of each frame.
+fn frame_entries(table: &[u8]) -> impl Iterator<Item = (u16, usize)> + '_ {
+    table[LOOP_COUNT_SIZE..]
+        .chunks_exact(FRAME_ENTRY_SIZE)
+        .map(|entry| {
+            (
+                u16::from_le_bytes([entry[0], entry[1]]),
+                u32::from_le_bytes(entry[2..].try_into().unwrap()) as usize,
+            )
+        })
+}
+
+impl<'a, 'b, T> Animation<'a, 'b, T> {
+    /// Return the number of times to play the animation, or 0 to repeat it forever.
+    #[inline]
+    pub fn loop_count(&self) -> u16 {
+        u16::from_le_bytes([self.table[0], self.table[1]])
+    }
+
+    /// Return the number of frames.
+    #[inline]
+    pub fn frame_count(&self) -> usize {
+        (self.table.len() - LOOP_COUNT_SIZE) / FRAME_ENTRY_SIZE
+    }
+
+    /// Return the frame at the given index.
+    pub fn frame(&self, index: usize) -> Option<AnimationFrame<'a, 'b, T>> {
+        self.frames().nth(index)
+    }
+
+    /// Return an iterator over the frames in order.
+    pub fn frames(&self) -> impl Iterator<Item = AnimationFrame<'a, 'b, T>> + use<'a, 'b, T> {
+        let decoder = self.decoder;
+        let table = self.table;
+        let mut offset = decoder.data_offset();
+        frame_entries(table)
+            .enumerate()
+            .map(move |(index, (duration, size))| {
+                let frame = AnimationFrame {
+                    decoder,
+                    table,
+                    index,
+                    duration,
+                    offset,
+                    size,
+                };
+                offset = offset.saturating_add(size);
+                frame
+            })
+    }
+
+    /// Decode the frame at the given index to a vector of bytes in RGB888 format (3 bytes per pixel).
+    #[cfg(feature = "alloc")]
+    pub fn decode_frame(&self, index: usize) -> Result<alloc::vec::Vec<u8>, DecodeError> {
+        let mut vec = alloc::vec![0; self.decoder.info().buffer_len(3)?];
+        self.decode_frame_to_slice(index, vec.as_mut()).map(|_| vec)
+    }
+
+    /// Decode the frame at the given index to the given output buffer in RGB888 format (3 bytes per pixel).
+    ///
+    /// The frames before it are decoded too, since each frame only holds the blocks that changed.
+    /// The output buffer should have a length of at least `width * height * 3` bytes.
+    ///
+    /// Returns an error if the data is invalid, if there is no such frame, or if the output buffer is too small.
+    pub fn decode_frame_to_slice(
+        &self,
+        index: usize,
+        output: &mut [u8],
+    ) -> Result<(), DecodeError> {
+        let width = self.decoder.info().width() as usize;
+        if index >= self.frame_count() || output.len() < self.decoder.info().buffer_len(3)? {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        for frame in self.frames().take(index + 1) {
+            frame._decode_planes(|x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+                let buf_u = demosaic_uv(buf_u);
+                let buf_v = demosaic_uv(buf_v);
+                for y7 in 0..h7 {
+                    for x7 in 0..w7 {
+                        let i = y7 * 8 + x7;
+                        let rgb =
+                            MpicRgb666::from_yuv(MpicYuv666::new(buf_y[i], buf_u[i], buf_v[i]));
+                        let index = (x8 + x7 + (y8 + y7) * width) * 3;
+                        output[index] = rgb.r8();
+                        output[index + 1] = rgb.g8();
+                        output[index + 2] = rgb.b8();
+                    }
+                }
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> AnimationFrame<'_, '_, T> {
+    /// Return the index of the frame.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Return the time to show the frame, in milliseconds.
+    #[inline]
+    pub fn duration(&self) -> u16 {
+        self.duration
+    }
+
+    /// Return the size of the chunks of the frame in bytes.
+    #[inline]
+    pub fn data_len(&self) -> usize {
+        self.size
+    }
+
+    /// Decode the blocks that changed since the previous frame, in order, and return the byte offset past the frame.
+    ///
+    /// A delta chunk is decoded with the regular chunk of the same block in the previous frame.
+    fn _decode_planes<F>(&self, mut f: F) -> Result<usize, DecodeError>
+    where
+        F: FnMut(usize, usize, usize, usize, &[u8; 64], &[u8; 16], &[u8; 16]),
+    {
+        let decoder = self.decoder;
+        let width = decoder.info().width() as usize;
+        let height = decoder.info().height() as usize;
+
+        // Read the chunks of the previous frame in step, so that its block is at hand for a delta chunk.
+        let mut previous = None;
+        let mut offset = decoder.data_offset();
+        for (frame, (_, size)) in frame_entries(self.table).enumerate().take(self.index) {
+            if frame + 1 == self.index {
+                previous = Some(decoder.chunks_at(offset, frame, frame > 0));
+            }
+            offset = offset.saturating_add(size);
+        }
+        let mut reader = decoder.chunks_at(offset, self.index, self.index > 0);
+
+        let mut buf = Vec::<u8, UNCOMPRESSED_SIZE>::new();
+        let mut block = [0; UNCOMPRESSED_SIZE];
+        for y8 in (0..height).step_by(8) {
+            let h7 = (height - y8).min(8);
+            for x8 in (0..width).step_by(8) {
+                let w7 = (width - x8).min(8);
+                let reference = match previous.as_mut() {
+                    Some(previous) => Some(previous.next().ok_or(DecodeError::InvalidData)??),
+                    None => None,
+                };
+                let chunk = reader.next().ok_or(DecodeError::InvalidData)??;
+                if chunk.is_unchanged() {
+                    continue;
+                }
+                if chunk.delta {
+                    let reference = reference
+                        .filter(|reference| !reference.delta && !reference.is_unchanged())
+                        .ok_or(DecodeError::MissingReference {
+                            index: chunk.index,
+                            offset: chunk.offset,
+                        })?;
+                    reference.decompress(None, &mut buf)?;
+                    block.copy_from_slice(&buf);
+                }
+                chunk.decompress(Some(&block), &mut buf)?;
+                block.copy_from_slice(&buf);
+                f(
+                    x8,
+                    y8,
+                    w7,
+                    h7,
+                    block[0..64].try_into().unwrap(),
+                    block[64..80].try_into().unwrap(),
+                    block[80..96].try_into().unwrap(),
+                );
+            }
+        }
+        Ok(reader.cursor)
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> OriginDimensions for AnimationFrame<'_, '_, T> {
+    #[inline]
+    fn size(&self) -> Size {
+        self.decoder.size()
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> AnimationFrame<'_, '_, T> {
+    /// Draw the image to the target like [`ImageDrawable::draw`], but report the errors in the image data.
+    ///
+    /// Returns [`DrawError::Decode`] with the index and the byte offset of the first chunk that cannot be decoded.
+    pub fn try_draw<D>(&self, target: &mut D) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        let mut result = Ok(());
+        self._decode_planes(|x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+            if result.is_err() {
+                return;
+            }
+            let buf_u = demosaic_uv(buf_u);
+            let buf_v = demosaic_uv(buf_v);
+            result = target.fill_contiguous(
+                &Rectangle::new(
+                    Point::new(x8 as i32, y8 as i32),
+                    Size::new(w7 as u32, h7 as u32),
+                ),
+                (0..h7).flat_map(|y7| {
+                    (0..w7).map(move |x7| {
+                        let i = y7 * 8 + x7;
+                        MpicRgb666::from(MpicYuv666::new(buf_y[i], buf_u[i], buf_v[i])).into()
+                    })
+                }),
+            );
+        })?;
+        result.map_err(DrawError::Target)
+    }
+
+    /// Draw the given area of the image like [`ImageDrawable::draw_sub_image`], but report the errors in the image data.
+    pub fn try_draw_sub_image<D>(
+        &self,
+        target: &mut D,
+        area: &Rectangle,
+    ) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        self.try_draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> ImageDrawable for AnimationFrame<'_, '_, T> {
+    type Color = T;
+
+    /// Stops silently at the first chunk that cannot be decoded, see [`Self::try_draw`].
+    #[inline]
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw(target))
+    }
+
+    #[inline]
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw_sub_image(target, area))
+    }
+}
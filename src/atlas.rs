@@ -0,0 +1,529 @@
+use crate::*;
+use core::marker::PhantomData;
+
+#[cfg(feature = "embedded")]
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// A collection of named images in one file, such as the icons of a user interface.
+///
+/// Each entry is either a complete MPIC image stored in the atlas, or a region of one of those images.
+/// Entries are looked up by name or index directly from the atlas data, without copying it.
+pub struct Atlas<'a, T> {
+    blob: &'a [u8],
+    len: usize,
+    _phantom: PhantomData<T>,
+}
+
+/// An entry of an [`Atlas`], which draws like a [`Decoder`].
+pub struct AtlasEntry<'a, T> {
+    name: &'a str,
+    decoder: Decoder<'a, T>,
+    region: bool,
+    left: u16,
+    top: u16,
+    width: u16,
+    height: u16,
+}
+
+/// Magic number of an atlas file.
+pub const ATLAS_MAGIC: [u8; 4] = *b"\x00mpa";
+
+/// Size of the magic number, the version and the number of entries
+const ATLAS_HEADER_SIZE: usize = 7;
+/// Current version of the atlas format
+const ATLAS_VERSION: u8 = 0;
+/// Kind of an entry that holds an image
+const KIND_IMAGE: u8 = 0;
+/// Kind of an entry that refers to a region of an image
+const KIND_REGION: u8 = 1;
+
+/// An entry as stored in the table.
+#[derive(Clone, Copy)]
+struct RawEntry<'a> {
+    name: &'a str,
+    kind: RawKind<'a>,
+}
+
+#[derive(Clone, Copy)]
+enum RawKind<'a> {
+    Image(&'a [u8]),
+    Region {
+        image: usize,
+        left: u16,
+        top: u16,
+        width: u16,
+        height: u16,
+    },
+}
+
+/// Iterator over the entry table, which stops at the first malformed entry.
+struct RawEntries<'a> {
+    blob: &'a [u8],
+    cursor: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for RawEntries<'a> {
+    type Item = Option<RawEntry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self.read();
+        self.remaining = if entry.is_some() {
+            self.remaining - 1
+        } else {
+            0
+        };
+        Some(entry)
+    }
+}
+
+impl<'a> RawEntries<'a> {
+    fn read(&mut self) -> Option<RawEntry<'a>> {
+        let blob = self.blob;
+        let mut cursor = self.cursor;
+        let mut take = |len: usize| {
+            let bytes = blob.get(cursor..cursor.checked_add(len)?)?;
+            cursor += len;
+            Some(bytes)
+        };
+        let u16_at =
+            |bytes: &[u8], index: usize| u16::from_le_bytes([bytes[index], bytes[index + 1]]);
+        let u32_at = |bytes: &[u8], index: usize| {
+            u32::from_le_bytes(bytes[index..index + 4].try_into().unwrap()) as usize
+        };
+
+        let name_len = take(1)?[0] as usize;
+        let name = core::str::from_utf8(take(name_len)?).ok()?;
+        let kind = match take(1)?[0] {
+            KIND_IMAGE => {
+                let bytes = take(8)?;
+                let offset = u32_at(bytes, 0);
+                let len = u32_at(bytes, 4);
+                RawKind::Image(blob.get(offset..offset.checked_add(len)?)?)
+            }
+            KIND_REGION => {
+                let bytes = take(10)?;
+                RawKind::Region {
+                    image: u16_at(bytes, 0) as usize,
+                    left: u16_at(bytes, 2),
+                    top: u16_at(bytes, 4),
+                    width: u16_at(bytes, 6),
+                    height: u16_at(bytes, 8),
+                }
+            }
+            _ => return None,
+        };
+        self.cursor = cursor;
+        Some(RawEntry { name, kind })
+    }
+}
+
+impl<'a, T> Atlas<'a, T> {
+    /// Create a new atlas from the given data blob.
+    ///
+    /// Returns `None` if the data is not a valid atlas file.
+    /// The images are checked when their entries are looked up.
+    pub fn new(blob: &'a [u8]) -> Option<Self> {
+        let header = blob.get(..ATLAS_HEADER_SIZE)?;
+        if header[..4] != ATLAS_MAGIC || header[4] != ATLAS_VERSION {
+            return None;
+        }
+        let atlas = Self {
+            blob,
+            len: u16::from_le_bytes([header[5], header[6]]) as usize,
+            _phantom: PhantomData,
+        };
+        atlas
+            .raw_entries()
+            .all(|entry| entry.is_some())
+            .then_some(atlas)
+    }
+
+    /// Return the number of entries.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the atlas has no entries.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the entry at the given index.
+    ///
+    /// Returns `None` if there is no such entry or if its image is invalid.
+    pub fn get(&self, index: usize) -> Option<AtlasEntry<'a, T>> {
+        let entry = self.raw_entries().nth(index)??;
+        self.resolve(entry)
+    }
+
+    /// Return the first entry with the given name.
+    ///
+    /// Returns `None` if there is no such entry or if its image is invalid.
+    pub fn find(&self, name: &str) -> Option<AtlasEntry<'a, T>> {
+        let entry = self
+            .raw_entries()
+            .flatten()
+            .find(|entry| entry.name == name)?;
+        self.resolve(entry)
+    }
+
+    /// Return the index of the first entry with the given name.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.raw_entries()
+            .flatten()
+            .position(|entry| entry.name == name)
+    }
+
+    /// Return an iterator over the valid entries in order.
+    pub fn entries(&self) -> impl Iterator<Item = AtlasEntry<'a, T>> + '_ {
+        self.raw_entries()
+            .flatten()
+            .filter_map(|entry| self.resolve(entry))
+    }
+
+    #[inline]
+    fn raw_entries(&self) -> RawEntries<'a> {
+        RawEntries {
+            blob: self.blob,
+            cursor: ATLAS_HEADER_SIZE,
+            remaining: self.len,
+        }
+    }
+
+    fn resolve(&self, entry: RawEntry<'a>) -> Option<AtlasEntry<'a, T>> {
+        match entry.kind {
+            RawKind::Image(data) => {
+                let decoder = Decoder::new(data)?;
+                let info = decoder.info();
+                Some(AtlasEntry {
+                    name: entry.name,
+                    region: false,
+                    left: 0,
+                    top: 0,
+                    width: info.width,
+                    height: info.height,
+                    decoder,
+                })
+            }
+            RawKind::Region {
+                image,
+                left,
+                top,
+                width,
+                height,
+            } => {
+                let RawKind::Image(data) = self.raw_entries().nth(image)??.kind else {
+                    return None;
+                };
+                let decoder = Decoder::new(data)?;
+                let info = decoder.info();
+                if width == 0
+                    || height == 0
+                    || left as u32 + width as u32 > info.width()
+                    || top as u32 + height as u32 > info.height()
+                {
+                    return None;
+                }
+                Some(AtlasEntry {
+                    name: entry.name,
+                    decoder,
+                    region: true,
+                    left,
+                    top,
+                    width,
+                    height,
+                })
+            }
+        }
+    }
+}
+
+impl<'a, T> AtlasEntry<'a, T> {
+    /// Return the name of the entry.
+    #[inline]
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Return the decoder of the whole image that holds the entry.
+    #[inline]
+    pub fn decoder(&self) -> &Decoder<'a, T> {
+        &self.decoder
+    }
+
+    /// Returns true if the entry is a region of an image rather than a whole image.
+    #[inline]
+    pub fn is_region(&self) -> bool {
+        self.region
+    }
+
+    /// Return the left edge of the entry in the image.
+    #[inline]
+    pub fn left(&self) -> u32 {
+        self.left as u32
+    }
+
+    /// Return the top edge of the entry in the image.
+    #[inline]
+    pub fn top(&self) -> u32 {
+        self.top as u32
+    }
+
+    /// Return the width of the entry in pixels.
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    /// Return the height of the entry in pixels.
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    /// Decode the entry to a vector of bytes in RGB888 format (3 bytes per pixel).
+    #[cfg(feature = "alloc")]
+    pub fn decode(&self) -> Result<alloc::vec::Vec<u8>, DecodeError> {
+        let pixels = self.decoder.decode()?;
+        if !self.region {
+            return Ok(pixels);
+        }
+        let stride = self.decoder.info().width() as usize * 3;
+        let left = self.left() as usize * 3;
+        let width = self.width() as usize * 3;
+        Ok(pixels
+            .chunks_exact(stride)
+            .skip(self.top() as usize)
+            .take(self.height() as usize)
+            .flat_map(|row| &row[left..left + width])
+            .copied()
+            .collect())
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> AtlasEntry<'_, T> {
+    /// Area of the entry in the image.
+    #[inline]
+    fn area(&self) -> Rectangle {
+        Rectangle::new(
+            Point::new(self.left as i32, self.top as i32),
+            Size::new(self.width(), self.height()),
+        )
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> OriginDimensions for AtlasEntry<'_, T> {
+    #[inline]
+    fn size(&self) -> Size {
+        Size::new(self.width(), self.height())
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<color::MpicRgb666>> AtlasEntry<'_, T> {
+    /// Draw the image to the target like [`ImageDrawable::draw`], but report the errors in the image data.
+    ///
+    /// Returns [`DrawError::Decode`] with the index and the byte offset of the first chunk that cannot be decoded.
+    pub fn try_draw<D>(&self, target: &mut D) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        self.decoder.try_draw_sub_image(target, &self.area())
+    }
+
+    /// Draw the given area of the image like [`ImageDrawable::draw_sub_image`], but report the errors in the image data.
+    pub fn try_draw_sub_image<D>(
+        &self,
+        target: &mut D,
+        area: &Rectangle,
+    ) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        self.try_draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<color::MpicRgb666>> ImageDrawable for AtlasEntry<'_, T> {
+    type Color = T;
+
+    /// Stops silently at the first chunk that cannot be decoded, see [`Self::try_draw`].
+    #[inline]
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw(target))
+    }
+
+    #[inline]
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw_sub_image(target, area))
+    }
+}
+
+/// Builder of an atlas file.
+///
+/// See [`Atlas`].
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Default)]
+pub struct AtlasBuilder<'a> {
+    entries: alloc::vec::Vec<(&'a str, BuilderEntry<'a>)>,
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Copy)]
+enum BuilderEntry<'a> {
+    Image(&'a [u8]),
+    Region {
+        image: u16,
+        left: u16,
+        top: u16,
+        width: u16,
+        height: u16,
+    },
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> AtlasBuilder<'a> {
+    /// Create an empty builder.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            entries: alloc::vec::Vec::new(),
+        }
+    }
+
+    /// Add an MPIC image and return the index of its entry.
+    ///
+    /// Returns [`EncodeError::InvalidInput`] if the name is longer than 255 bytes or already used,
+    /// if the data is not a valid MPIC file, or if the atlas already has 65535 entries.
+    pub fn add_image(&mut self, name: &'a str, data: &'a [u8]) -> Result<usize, EncodeError> {
+        if Decoder::<()>::new(data).is_none() {
+            return Err(EncodeError::InvalidInput);
+        }
+        self.push(name, BuilderEntry::Image(data))
+    }
+
+    /// Add a region of the image at the given index and return the index of its entry.
+    ///
+    /// Returns [`EncodeError::InvalidInput`] if the name is longer than 255 bytes or already used,
+    /// if the index does not refer to an image added by [`Self::add_image`], if the region is empty or extends beyond the image,
+    /// or if the atlas already has 65535 entries.
+    pub fn add_region(
+        &mut self,
+        name: &'a str,
+        image: usize,
+        left: u32,
+        top: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<usize, EncodeError> {
+        let Some(&(_, BuilderEntry::Image(data))) = self.entries.get(image) else {
+            return Err(EncodeError::InvalidInput);
+        };
+        let info = Decoder::<()>::new(data).unwrap().info();
+        if width == 0
+            || height == 0
+            || left
+                .checked_add(width)
+                .is_none_or(|right| right > info.width())
+            || top
+                .checked_add(height)
+                .is_none_or(|bottom| bottom > info.height())
+        {
+            return Err(EncodeError::InvalidInput);
+        }
+        self.push(
+            name,
+            BuilderEntry::Region {
+                image: image as u16,
+                left: left as u16,
+                top: top as u16,
+                width: width as u16,
+                height: height as u16,
+            },
+        )
+    }
+
+    fn push(&mut self, name: &'a str, entry: BuilderEntry<'a>) -> Result<usize, EncodeError> {
+        if name.len() > u8::MAX as usize
+            || self.entries.len() >= u16::MAX as usize
+            || self.entries.iter().any(|(other, _)| *other == name)
+        {
+            return Err(EncodeError::InvalidInput);
+        }
+        self.entries.push((name, entry));
+        Ok(self.entries.len() - 1)
+    }
+
+    /// Build the atlas file.
+    ///
+    /// Returns [`EncodeError::InvalidInput`] if the file would be larger than 4 GiB.
+    pub fn build(&self) -> Result<alloc::vec::Vec<u8>, EncodeError> {
+        let table_len = self
+            .entries
+            .iter()
+            .map(|(name, entry)| {
+                2 + name.len()
+                    + match entry {
+                        BuilderEntry::Image(_) => 8,
+                        BuilderEntry::Region { .. } => 10,
+                    }
+            })
+            .sum::<usize>();
+        let mut offset = ATLAS_HEADER_SIZE + table_len;
+
+        let mut output = alloc::vec::Vec::new();
+        output.extend_from_slice(&ATLAS_MAGIC);
+        output.push(ATLAS_VERSION);
+        output.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        for (name, entry) in &self.entries {
+            output.push(name.len() as u8);
+            output.extend_from_slice(name.as_bytes());
+            match *entry {
+                BuilderEntry::Image(data) => {
+                    let start = u32::try_from(offset).map_err(|_| EncodeError::InvalidInput)?;
+                    let len = u32::try_from(data.len()).map_err(|_| EncodeError::InvalidInput)?;
+                    output.push(KIND_IMAGE);
+                    output.extend_from_slice(&start.to_le_bytes());
+                    output.extend_from_slice(&len.to_le_bytes());
+                    offset += data.len();
+                }
+                BuilderEntry::Region {
+                    image,
+                    left,
+                    top,
+                    width,
+                    height,
+                } => {
+                    output.push(KIND_REGION);
+                    for value in [image, left, top, width, height] {
+                        output.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+        }
+        if u32::try_from(offset).is_err() {
+            return Err(EncodeError::InvalidInput);
+        }
+        for (_, entry) in &self.entries {
+            if let BuilderEntry::Image(data) = entry {
+                output.extend_from_slice(data);
+            }
+        }
+        Ok(output)
+    }
+}
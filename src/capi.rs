@@ -0,0 +1,290 @@
+//! C API for firmware written in C
+//!
+//! Enabled by the `capi` feature. The functions do not allocate, and report every failure as an [`MpicStatus`].
+//! The header `capi/include/mpic.h` is generated from this module with cbindgen,
+//! and the `mpic-capi` crate in `/capi` builds it as a static library.
+
+use crate::{DecodeError, Decoder, EncodeError, Encoder, EncoderOptions, FileHeader};
+use core::slice;
+
+/// Result of a function of the C API.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpicStatus {
+    /// Success.
+    Ok = 0,
+    /// A pointer is null, or an argument is invalid.
+    InvalidInput,
+    /// The output buffer is too small.
+    BufferTooSmall,
+    /// The data is corrupted or cannot be decoded.
+    InvalidData,
+    /// The data is shorter than the file header.
+    TruncatedHeader,
+    /// The data is not an MPIC file.
+    BadMagic,
+    /// The version in the file header is not supported, or cannot represent the image.
+    UnsupportedVersion,
+    /// The width or height is invalid, or too large for a row offset table.
+    InvalidSize,
+    /// The metadata section is malformed.
+    InvalidMetadata,
+    /// A chunk is truncated, has an invalid size or does not decompress to a block.
+    InvalidChunk,
+    /// Unexpected bytes remain after the last chunk.
+    TrailingData,
+    /// The file has no checksum, or does not match it.
+    ChecksumMismatch,
+}
+
+impl From<DecodeError> for MpicStatus {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::InvalidInput => Self::InvalidInput,
+            DecodeError::InvalidData
+            | DecodeError::InvalidFrame { .. }
+            | DecodeError::MissingReference { .. } => Self::InvalidData,
+            DecodeError::TruncatedHeader => Self::TruncatedHeader,
+            DecodeError::BadMagic => Self::BadMagic,
+            DecodeError::UnsupportedVersion(_) => Self::UnsupportedVersion,
+            DecodeError::InvalidSize { .. } => Self::InvalidSize,
+            DecodeError::InvalidMetadata { .. } => Self::InvalidMetadata,
+            DecodeError::TruncatedChunk { .. }
+            | DecodeError::InvalidChunkLength { .. }
+            | DecodeError::MatchOutOfRange { .. }
+            | DecodeError::ReservedOpcode { .. }
+            | DecodeError::OutputOverflow { .. }
+            | DecodeError::OutputUnderflow { .. } => Self::InvalidChunk,
+            DecodeError::TrailingData { .. } => Self::TrailingData,
+            DecodeError::MissingChecksum
+            | DecodeError::HeaderChecksumMismatch { .. }
+            | DecodeError::ChecksumMismatch { .. } => Self::ChecksumMismatch,
+        }
+    }
+}
+
+impl From<EncodeError> for MpicStatus {
+    fn from(err: EncodeError) -> Self {
+        match err {
+            EncodeError::InvalidInput
+            | EncodeError::InvalidStride { .. }
+            | EncodeError::InsufficientData { .. }
+            | EncodeError::RequiresAlloc => Self::InvalidInput,
+            EncodeError::InvalidSize { .. } => Self::InvalidSize,
+            EncodeError::UnsupportedVersion(_) => Self::UnsupportedVersion,
+            EncodeError::InvalidMetadata => Self::InvalidMetadata,
+        }
+    }
+}
+
+/// Decoder of an MPIC file in memory.
+///
+/// Created by [`mpic_decoder_new`]. The fields are private, and the data must outlive the decoder.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MpicDecoder {
+    data: *const u8,
+    len: usize,
+}
+
+/// Image information from the file header.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpicInfo {
+    /// Width of the image in pixels
+    pub width: u32,
+    /// Height of the image in pixels
+    pub height: u32,
+    /// Version of the file
+    pub version: u8,
+}
+
+/// Convert a pointer and a length from C to a slice, allowing a null pointer for an empty slice.
+unsafe fn from_raw<'a, T>(ptr: *const T, len: usize) -> Option<&'a [T]> {
+    if ptr.is_null() {
+        (len == 0).then_some(&[])
+    } else {
+        Some(unsafe { slice::from_raw_parts(ptr, len) })
+    }
+}
+
+/// Convert a pointer and a length from C to a mutable slice, allowing a null pointer for an empty slice.
+unsafe fn from_raw_mut<'a, T>(ptr: *mut T, len: usize) -> Option<&'a mut [T]> {
+    if ptr.is_null() {
+        (len == 0).then_some(&mut [])
+    } else {
+        Some(unsafe { slice::from_raw_parts_mut(ptr, len) })
+    }
+}
+
+impl MpicDecoder {
+    /// Return the decoder of the file, which was checked by [`mpic_decoder_new`].
+    unsafe fn decoder(&self) -> Option<Decoder<'_, ()>> {
+        unsafe { from_raw(self.data, self.len) }.and_then(Decoder::new)
+    }
+}
+
+/// Create a decoder of the MPIC file of `len` bytes at `data`, checking its header and metadata section.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes that outlive the decoder, and `decoder` must be null or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpic_decoder_new(
+    data: *const u8,
+    len: usize,
+    decoder: *mut MpicDecoder,
+) -> MpicStatus {
+    let (Some(blob), Some(decoder)) = (unsafe { from_raw(data, len) }, unsafe { decoder.as_mut() })
+    else {
+        return MpicStatus::InvalidInput;
+    };
+    match Decoder::<()>::_new(blob) {
+        Ok(_) => {
+            *decoder = MpicDecoder { data, len };
+            MpicStatus::Ok
+        }
+        Err(err) => err.into(),
+    }
+}
+
+/// Get the image information of the file.
+///
+/// # Safety
+///
+/// `decoder` must be null or created by [`mpic_decoder_new`], and `info` must be null or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpic_info(decoder: *const MpicDecoder, info: *mut MpicInfo) -> MpicStatus {
+    let (Some(decoder), Some(info)) = (unsafe { decoder.as_ref() }, unsafe { info.as_mut() })
+    else {
+        return MpicStatus::InvalidInput;
+    };
+    let Some(header) =
+        (unsafe { from_raw(decoder.data, decoder.len) }).and_then(FileHeader::from_bytes)
+    else {
+        return MpicStatus::InvalidInput;
+    };
+    *info = MpicInfo {
+        width: header.info().width(),
+        height: header.info().height(),
+        version: header.version().0,
+    };
+    MpicStatus::Ok
+}
+
+/// Decode the image to `len` RGB565 pixels at `output`, which must hold at least `width * height` pixels.
+///
+/// If `dither` is true, ordered dithering is applied when reducing the red and blue channels to 5 bits.
+///
+/// # Safety
+///
+/// `decoder` must be null or created by [`mpic_decoder_new`], and `output` must point to `len` writable pixels.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpic_decode_rgb565(
+    decoder: *const MpicDecoder,
+    output: *mut u16,
+    len: usize,
+    dither: bool,
+) -> MpicStatus {
+    let (Some(decoder), Some(output)) = (
+        unsafe { decoder.as_ref() }.and_then(|decoder| unsafe { decoder.decoder() }),
+        unsafe { from_raw_mut(output, len) },
+    ) else {
+        return MpicStatus::InvalidInput;
+    };
+    match decoder.info().buffer_len(1) {
+        Ok(required) if output.len() >= required => {}
+        _ => return MpicStatus::BufferTooSmall,
+    }
+    match decoder.decode_rgb565_to_slice(output, dither) {
+        Ok(()) => MpicStatus::Ok,
+        Err(err) => err.into(),
+    }
+}
+
+/// Decode a single chunk of `len` bytes at `data`, excluding its size, to the Y, U and V planes of 64 values each.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, and `y`, `u` and `v` must each point to 64 writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpic_decode_chunk(
+    data: *const u8,
+    len: usize,
+    y: *mut u8,
+    u: *mut u8,
+    v: *mut u8,
+) -> MpicStatus {
+    let (Some(src), Some(y), Some(u), Some(v)) = (
+        unsafe { from_raw(data, len) },
+        unsafe { y.cast::<[u8; 64]>().as_mut() },
+        unsafe { u.cast::<[u8; 64]>().as_mut() },
+        unsafe { v.cast::<[u8; 64]>().as_mut() },
+    ) else {
+        return MpicStatus::InvalidInput;
+    };
+    match Decoder::<()>::decode_chunk(src) {
+        Ok((buf_y, buf_u, buf_v)) => {
+            *y = buf_y;
+            *u = buf_u;
+            *v = buf_v;
+            MpicStatus::Ok
+        }
+        Err(err) => err.into(),
+    }
+}
+
+/// Encode an RGB888 image of `width * height` pixels (`len` bytes) at `data` to the buffer of `capacity` bytes at `output`.
+///
+/// The size of the file is stored to `written`, even if the buffer is too small,
+/// so that the call can be repeated with a large enough buffer.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `output` to `capacity` writable bytes, and `written` must be null or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpic_encode(
+    data: *const u8,
+    len: usize,
+    width: u32,
+    height: u32,
+    output: *mut u8,
+    capacity: usize,
+    written: *mut usize,
+) -> MpicStatus {
+    let (Some(data), Some(output), Some(written)) = (
+        unsafe { from_raw(data, len) },
+        unsafe { from_raw_mut(output, capacity) },
+        unsafe { written.as_mut() },
+    ) else {
+        return MpicStatus::InvalidInput;
+    };
+    let mut size = 0usize;
+    let result = Encoder::encode_to_writer_with_options(
+        data,
+        width,
+        height,
+        &EncoderOptions::new(),
+        |bytes| {
+            let end = size.saturating_add(bytes.len());
+            if let Some(dest) = output.get_mut(size..end) {
+                dest.copy_from_slice(bytes);
+            }
+            size = end;
+        },
+    );
+    match result {
+        Ok(()) => {
+            *written = size;
+            if size <= capacity {
+                MpicStatus::Ok
+            } else {
+                MpicStatus::BufferTooSmall
+            }
+        }
+        Err(err) => {
+            *written = 0;
+            err.into()
+        }
+    }
+}
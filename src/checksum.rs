@@ -0,0 +1,137 @@
+use crate::{crc::crc32, metadata::RECORD_HEADER_SIZE, *};
+
+/// Size of a checksum
+const CHECKSUM_SIZE: usize = 4;
+
+/// Return the size of the payload of the checksum record for an image of the given height.
+#[inline]
+pub(crate) fn payload_len(height: u32) -> usize {
+    CHECKSUM_SIZE * (1 + height.div_ceil(8) as usize)
+}
+
+/// Checksums read from the [`Metadata::Checksum`] record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChecksumRecord<'a> {
+    /// Byte offset of the record, which is also the size of the data covered by the header checksum
+    offset: usize,
+    header: u32,
+    rows: &'a [u8],
+}
+
+impl ChecksumRecord<'_> {
+    /// Verify the file header and the metadata before the record.
+    pub fn verify_header(&self, blob: &[u8]) -> Result<(), DecodeError> {
+        if crc32(&blob[..self.offset]) != self.header {
+            return Err(DecodeError::HeaderChecksumMismatch {
+                offset: self.offset,
+            });
+        }
+        Ok(())
+    }
+
+    /// Verify the chunks of a row of blocks, whose first chunk starts at the given byte offset.
+    pub fn verify_row(
+        &self,
+        blob: &[u8],
+        row: usize,
+        offset: usize,
+        cols: usize,
+    ) -> Result<(), DecodeError> {
+        let mismatch = DecodeError::ChecksumMismatch { row, offset };
+        let mut cursor = offset;
+        for _ in 0..cols {
+            let len = *blob.get(cursor).ok_or(mismatch)? as usize;
+            if !chunk::is_valid_size(len) {
+                return Err(mismatch);
+            }
+            cursor += 1 + len;
+        }
+        let expected = self
+            .rows
+            .get(row * CHECKSUM_SIZE..(row + 1) * CHECKSUM_SIZE)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+        match blob.get(offset..cursor) {
+            Some(data) if Some(crc32(data)) == expected => Ok(()),
+            _ => Err(mismatch),
+        }
+    }
+}
+
+impl<'a, T> Decoder<'a, T> {
+    /// Create a new decoder from the given MPIC data blob, validating the whole data and verifying its checksums in advance.
+    ///
+    /// See [`Decoder::new_strict`] and [`Decoder::verify`].
+    pub fn new_verified(blob: &'a [u8]) -> Result<Self, DecodeError> {
+        let decoder = Self::new_strict(blob)?;
+        decoder.verify()?;
+        Ok(decoder)
+    }
+
+    /// Returns true if the file has a [`Metadata::Checksum`] record.
+    #[inline]
+    pub fn has_checksum(&self) -> bool {
+        self.metadata()
+            .any(|record| matches!(record, Metadata::Checksum(_)))
+    }
+
+    /// Verify the checksums of the file header, the metadata and each row of blocks.
+    ///
+    /// Only the chunks of the first frame of an animation are covered.
+    /// Returns [`DecodeError::MissingChecksum`] if the file was encoded without checksums,
+    /// and [`DecodeError::ChecksumMismatch`] with the first row that does not match.
+    pub fn verify(&self) -> Result<(), DecodeError> {
+        let mut reader = self.chunks();
+        reader.checksums = Some(self.checksum_record());
+        for raw in reader {
+            raw?;
+        }
+        Ok(())
+    }
+
+    /// Find the checksum record.
+    pub(crate) fn checksum_record(&self) -> Result<ChecksumRecord<'a>, DecodeError> {
+        let data = self
+            .metadata()
+            .find_map(|record| match record {
+                Metadata::Checksum(data) => Some(data),
+                _ => None,
+            })
+            .ok_or(DecodeError::MissingChecksum)?;
+        let offset = self.offset_of(data) - RECORD_HEADER_SIZE;
+        if data.len() != payload_len(self.info().height()) {
+            return Err(DecodeError::InvalidMetadata { offset });
+        }
+        let (header, rows) = data.split_at(CHECKSUM_SIZE);
+        Ok(ChecksumRecord {
+            offset,
+            header: u32::from_le_bytes(header.try_into().unwrap()),
+            rows,
+        })
+    }
+}
+
+/// Fill in the checksum record at the given byte offset of a file whose chunks have all been written.
+#[cfg(feature = "alloc")]
+pub(crate) fn write_checksums(
+    output: &mut [u8],
+    record: usize,
+    data_offset: usize,
+    width: u32,
+    height: u32,
+) {
+    let cols = width.div_ceil(8) as usize;
+    let rows = height.div_ceil(8) as usize;
+    let payload = record + RECORD_HEADER_SIZE;
+    let header = crc32(&output[..record]);
+    output[payload..payload + CHECKSUM_SIZE].copy_from_slice(&header.to_le_bytes());
+    let mut cursor = data_offset;
+    for row in 0..rows {
+        let start = cursor;
+        for _ in 0..cols {
+            cursor += 1 + output[cursor] as usize;
+        }
+        let crc = crc32(&output[start..cursor]);
+        let at = payload + CHECKSUM_SIZE * (1 + row);
+        output[at..at + CHECKSUM_SIZE].copy_from_slice(&crc.to_le_bytes());
+    }
+}
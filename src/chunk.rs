@@ -1,6 +1,8 @@
+#[cfg(feature = "alloc")]
 use crate::lz::lazy::*;
 use crate::lz::*;
 use crate::*;
+#[cfg(feature = "alloc")]
 use core::convert::Infallible;
 
 /// 8bit uncompressed chunk size
@@ -9,6 +11,8 @@ pub const UNCOMPRESSED_SIZE: usize = 96;
 pub const COMPACTED_SIZE: usize = 72;
 /// Theoretical Minimum Compressed Data: ANY VALUE + (SLIDE * 2) = 5
 pub const MINIMAL_COMPRESSED_SIZE: usize = 5;
+/// Flag in the size of a delta chunk, which may refer to the same block of the previous frame
+pub const DELTA_FLAG: u8 = 0x80;
 
 const MIN_LEN_SHORT: usize = 2;
 const MAX_LEN_SHORT: usize = 3 + MIN_LEN_SHORT;
@@ -16,6 +20,8 @@ const MAX_DIST_SHORT: u8 = 32;
 const MIN_LEN_LONG: usize = 3;
 const MAX_LEN_LONG: usize = 63 + MIN_LEN_LONG;
 const MAX_DIST: usize = 64;
+/// Maximum distance of a far match, which reaches into the previous frame in a delta chunk
+const MAX_DIST_FAR: usize = 64 + MAX_DIST;
 
 /// Compress a chunk of data.
 pub(crate) fn compress(
@@ -28,10 +34,12 @@ pub(crate) fn compress(
     //     return;
     // }
 
-    if cfg!(feature = "alloc") && level != CompressionLevel::Fast {
-        compress_lazy(src, output, level);
-    } else {
-        compress_fast(src, output);
+    match level {
+        #[cfg(feature = "alloc")]
+        CompressionLevel::Default | CompressionLevel::Best => {
+            compress_lazy(src, None, output, level)
+        }
+        _ => compress_fast(src, None, output),
     }
 
     // If compression does not reduce size much, switch to compaction
@@ -42,6 +50,45 @@ pub(crate) fn compress(
     compact(src, output);
 }
 
+/// Compress a chunk of data as a delta chunk, using the same block of the previous frame as a dictionary.
+///
+/// The result does not include [`DELTA_FLAG`]. It is only worth using if it is smaller than [`compress`].
+#[cfg(feature = "alloc")]
+pub(crate) fn compress_delta(
+    src: &[u8; UNCOMPRESSED_SIZE],
+    reference: &[u8; UNCOMPRESSED_SIZE],
+    output: &mut Vec<u8, 128>,
+    level: CompressionLevel,
+) {
+    if level != CompressionLevel::Fast {
+        compress_lazy(src, Some(reference), output, level);
+    } else {
+        compress_fast(src, Some(reference), output);
+    }
+}
+
+/// Place the reference block, if any, before the source so that matches can reach into it.
+///
+/// Returns the window, the position of the source in it, and the maximum distance of a match.
+#[inline]
+fn match_window(
+    src: &[u8; UNCOMPRESSED_SIZE],
+    reference: Option<&[u8; UNCOMPRESSED_SIZE]>,
+) -> ([u8; UNCOMPRESSED_SIZE * 2], usize, usize) {
+    let mut window = [0; UNCOMPRESSED_SIZE * 2];
+    match reference {
+        Some(reference) => {
+            window[..UNCOMPRESSED_SIZE].copy_from_slice(reference);
+            window[UNCOMPRESSED_SIZE..].copy_from_slice(src);
+            (window, UNCOMPRESSED_SIZE, MAX_DIST_FAR)
+        }
+        None => {
+            window[..UNCOMPRESSED_SIZE].copy_from_slice(src);
+            (window, 0, MAX_DIST)
+        }
+    }
+}
+
 /// 6bit compaction
 ///
 /// `(00aa_aaaa 00bb_bbbb 00cc_cccc 00dd_dddd) -> (bbaa_aaaa cccc_bbbb dddd_ddcc)`
@@ -65,17 +112,24 @@ pub(crate) fn compact(src: &[u8; UNCOMPRESSED_SIZE], output: &mut Vec<u8, 128>)
 
 /// Simple LZ compression
 #[inline]
-pub(crate) fn compress_fast(src: &[u8; UNCOMPRESSED_SIZE], output: &mut Vec<u8, 128>) {
+pub(crate) fn compress_fast(
+    src: &[u8; UNCOMPRESSED_SIZE],
+    reference: Option<&[u8; UNCOMPRESSED_SIZE]>,
+    output: &mut Vec<u8, 128>,
+) {
     output.clear();
 
+    let (window, base, max_dist) = match_window(src, reference);
+    let window = &window[..base + UNCOMPRESSED_SIZE];
     let mut current = 0;
     while let Some(&literal) = src.get(current) {
         let count = {
             let mut best_match = BestMatch::Empty;
 
             // In mpic format, size of src is very small (96 bytes), so we can afford to check all possible matches.
-            for distance in 1..=current.min(MAX_DIST) {
-                let len = matching_len(src, current, current - distance, MAX_LEN_LONG);
+            let position = base + current;
+            for distance in 1..=position.min(max_dist) {
+                let len = matching_len(window, position, position - distance, MAX_LEN_LONG);
                 if len >= MIN_LEN_SHORT && best_match.len() < len {
                     best_match = BestMatch::new(len, distance);
                 }
@@ -104,18 +158,22 @@ pub(crate) fn compress_fast(src: &[u8; UNCOMPRESSED_SIZE], output: &mut Vec<u8,
 #[inline]
 pub(crate) fn compress_lazy(
     src: &[u8; UNCOMPRESSED_SIZE],
+    reference: Option<&[u8; UNCOMPRESSED_SIZE]>,
     output: &mut Vec<u8, 128>,
     level: CompressionLevel,
 ) {
     output.clear();
 
+    let (window, base, max_dist) = match_window(src, reference);
+    let window = &window[..base + UNCOMPRESSED_SIZE];
     let mut lazy_match = LazyMatchSolver::new();
     for (current, &literal) in src.iter().enumerate() {
         let mut best_match = BestMatch::Empty;
 
         // In mpic format, size of src is very small (96 bytes), so we can afford to check all possible matches.
-        for distance in 1..=current.min(MAX_DIST) {
-            let len = matching_len(src, current, current - distance, MAX_LEN_LONG);
+        let position = base + current;
+        for distance in 1..=position.min(max_dist) {
+            let len = matching_len(window, position, position - distance, MAX_LEN_LONG);
             if len >= MIN_LEN_SHORT && best_match.len() < len {
                 best_match = BestMatch::new(len, distance);
             }
@@ -129,7 +187,7 @@ pub(crate) fn compress_lazy(
             best_match = BestMatch::Empty;
         } else {
             let position_weight = src.len() - current;
-            weight = weight + position_weight;
+            weight += position_weight;
         }
 
         lazy_match.push(LazyLzssItem::new(literal, best_match, weight));
@@ -196,6 +254,7 @@ pub(crate) fn compress_lazy(
 }
 
 /// Returns the best compressed data among candidates.
+#[cfg(feature = "alloc")]
 #[inline]
 fn best_size(candidates: &[Vec<u8, 128>]) -> Vec<u8, 128> {
     candidates
@@ -208,12 +267,17 @@ fn best_size(candidates: &[Vec<u8, 128>]) -> Vec<u8, 128> {
 #[inline]
 fn emit_match(output: &mut Vec<u8, 128>, matches: Match) -> Option<usize> {
     let len = matches.len.get();
-    let encoded_slide = matches.distance.get() as u8;
+    let distance = matches.distance.get();
+    let encoded_slide = distance as u8;
     if len <= MAX_LEN_SHORT && encoded_slide <= MAX_DIST_SHORT {
         output
             .push(0x80 | (((len - MIN_LEN_SHORT) as u8) << 5) | (encoded_slide - 1))
             .unwrap();
         Some(len)
+    } else if len >= MIN_LEN_LONG && distance > MAX_DIST {
+        output.push(0x40 | (len - MIN_LEN_LONG) as u8).unwrap();
+        output.push(0x40 | (distance - MAX_DIST - 1) as u8).unwrap();
+        Some(len)
     } else if len >= MIN_LEN_LONG {
         output.push(0x40 | (len - MIN_LEN_LONG) as u8).unwrap();
         output.push(encoded_slide - 1).unwrap();
@@ -223,11 +287,12 @@ fn emit_match(output: &mut Vec<u8, 128>, matches: Match) -> Option<usize> {
     }
 }
 
+#[cfg(feature = "alloc")]
 #[inline]
 fn weight(matches: &Match) -> usize {
     let len = matches.len.get();
     let encoded_slide = matches.distance.get() as u8;
-    if len >= MIN_LEN_SHORT && len <= MAX_LEN_SHORT && encoded_slide <= MAX_DIST_SHORT {
+    if (MIN_LEN_SHORT..=MAX_LEN_SHORT).contains(&len) && encoded_slide <= MAX_DIST_SHORT {
         len
     } else if len >= MIN_LEN_LONG {
         len - 1
@@ -236,79 +301,191 @@ fn weight(matches: &Match) -> usize {
     }
 }
 
-pub(crate) fn decompress(src: &[u8], output: &mut Vec<u8, UNCOMPRESSED_SIZE>) -> Option<()> {
+/// Errors found while decompressing a single chunk.
+///
+/// Positions are byte offsets from the start of the chunk payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChunkError {
+    /// The size of the chunk is not a valid chunk size.
+    InvalidLength,
+    /// The payload ends in the middle of a token.
+    Truncated(usize),
+    /// A match refers to data before the start of the block, or of the previous frame in a delta chunk.
+    MatchOutOfRange(usize),
+    /// A reserved opcode was found.
+    ReservedOpcode(usize),
+    /// The decompressed data exceeds 96 bytes.
+    OutputOverflow(usize),
+    /// The decompressed data is shorter than 96 bytes.
+    OutputUnderflow(usize),
+}
+
+impl ChunkError {
+    /// Converts into a [`DecodeError`] for the chunk at `index` whose payload starts at `payload`.
+    pub(crate) fn into_decode_error(self, index: usize, payload: usize) -> DecodeError {
+        match self {
+            Self::InvalidLength => DecodeError::InvalidChunkLength {
+                index,
+                offset: payload.saturating_sub(1),
+            },
+            Self::Truncated(pos) => DecodeError::TruncatedChunk {
+                index,
+                offset: payload + pos,
+            },
+            Self::MatchOutOfRange(pos) => DecodeError::MatchOutOfRange {
+                index,
+                offset: payload + pos,
+            },
+            Self::ReservedOpcode(pos) => DecodeError::ReservedOpcode {
+                index,
+                offset: payload + pos,
+            },
+            Self::OutputOverflow(pos) => DecodeError::OutputOverflow {
+                index,
+                offset: payload + pos,
+            },
+            Self::OutputUnderflow(pos) => DecodeError::OutputUnderflow {
+                index,
+                offset: payload + pos,
+            },
+        }
+    }
+}
+
+pub(crate) fn decompress(
+    src: &[u8],
+    output: &mut Vec<u8, UNCOMPRESSED_SIZE>,
+) -> Result<(), ChunkError> {
     let len = src.len();
     output.clear();
     if len == UNCOMPRESSED_SIZE {
         // 8bit uncompressed
-        output.extend_from_slice(src).ok()
+        output
+            .extend_from_slice(src)
+            .map_err(|_| ChunkError::OutputOverflow(0))
     } else if len == COMPACTED_SIZE {
         // 6bit compacted
-        let mut src = src.iter();
-        for _ in 0..24 {
-            let b1 = *src.next()? as u32;
-            let b2 = *src.next()? as u32;
-            let b3 = *src.next()? as u32;
-            let d0 = b1 | b2.wrapping_shl(8) | b3.wrapping_shl(16);
-            output.push((d0 & 0x3F) as u8).ok()?;
-            output.push((d0.wrapping_shr(6) & 0x3F) as u8).ok()?;
-            output.push((d0.wrapping_shr(12) & 0x3F) as u8).ok()?;
-            output.push((d0.wrapping_shr(18) & 0x3F) as u8).ok()?;
+        for (i, src) in src.chunks_exact(3).enumerate() {
+            let d0 = src[0] as u32 | (src[1] as u32) << 8 | (src[2] as u32) << 16;
+            for shift in [0, 6, 12, 18] {
+                output
+                    .push((d0.wrapping_shr(shift) & 0x3F) as u8)
+                    .map_err(|_| ChunkError::OutputOverflow(i * 3))?;
+            }
         }
-        Some(())
+        Ok(())
     } else if is_valid_compressed_size(len) {
         // compressed
-        let mut cursor = 0;
-        while cursor < len {
-            let data = *src.get(cursor)?;
-            match data {
-                0b0000_0000..=0b0011_1111 => {
-                    // 00vv_vvvv raw value
-                    output.push(data & 0x3F).ok()?;
-                }
-                0b0100_0000..=0b0111_1111 => {
-                    // 01nn_nnnn 00mm_mmmm slide long
-                    let slen = (data & 0x3F) as usize + 3;
-                    let slide = *src.get(cursor + 1)?;
-                    if (slide & 0xC0) != 0 {
+        decompress_lz(src, None, output)
+    } else {
+        // reserved
+        Err(ChunkError::InvalidLength)
+    }
+}
+
+/// Decompress the payload of a delta chunk, using the same block of the previous frame as a dictionary.
+pub(crate) fn decompress_delta(
+    src: &[u8],
+    reference: &[u8; UNCOMPRESSED_SIZE],
+    output: &mut Vec<u8, UNCOMPRESSED_SIZE>,
+) -> Result<(), ChunkError> {
+    output.clear();
+    if is_valid_delta_size(src.len()) {
+        decompress_lz(src, Some(reference), output)
+    } else {
+        Err(ChunkError::InvalidLength)
+    }
+}
+
+fn decompress_lz(
+    src: &[u8],
+    reference: Option<&[u8; UNCOMPRESSED_SIZE]>,
+    output: &mut Vec<u8, UNCOMPRESSED_SIZE>,
+) -> Result<(), ChunkError> {
+    let mut cursor = 0;
+    while let Some(&data) = src.get(cursor) {
+        match data {
+            0b0000_0000..=0b0011_1111 => {
+                // 00vv_vvvv raw value
+                output
+                    .push(data & 0x3F)
+                    .map_err(|_| ChunkError::OutputOverflow(cursor))?;
+            }
+            0b0100_0000..=0b0111_1111 => {
+                // 01nn_nnnn 00mm_mmmm slide long
+                // 01nn_nnnn 01mm_mmmm slide far (delta chunks only)
+                let slen = (data & 0x3F) as usize + 3;
+                let slide = *src.get(cursor + 1).ok_or(ChunkError::Truncated(cursor))?;
+                let slide = match slide & 0xC0 {
+                    0x00 => slide as usize + 1,
+                    0x40 if reference.is_some() => (slide & 0x3F) as usize + MAX_DIST + 1,
+                    _ => {
                         // RESERVED
-                        return None;
-                    }
-                    let slide = slide as usize + 1;
-                    if output.len() < slide || output.len() + slen > UNCOMPRESSED_SIZE {
-                        return None;
-                    }
-                    let base = output.len() - slide;
-                    for i in 0..slen {
-                        let v = *output.get(base + i)?;
-                        output.push(v).ok()?;
+                        return Err(ChunkError::ReservedOpcode(cursor));
                     }
-                    cursor += 1;
-                }
-                0b1000_0000..=0b1111_1111 => {
-                    // 1nnm_mmmm slide short
-                    let slen = 2 + ((data & 0x60) as usize >> 5);
-                    let slide = (data & 0x1F) as usize + 1;
-                    if output.len() < slide || output.len() + slen > UNCOMPRESSED_SIZE {
-                        return None;
-                    }
-                    let base = output.len() - slide;
-                    for i in 0..slen {
-                        let v = *output.get(base + i)?;
-                        output.push(v).ok()?;
-                    }
-                }
+                };
+                copy_match(output, reference, slide, slen, cursor)?;
+                cursor += 1;
+            }
+            0b1000_0000..=0b1111_1111 => {
+                // 1nnm_mmmm slide short
+                let slen = 2 + ((data & 0x60) as usize >> 5);
+                let slide = (data & 0x1F) as usize + 1;
+                copy_match(output, reference, slide, slen, cursor)?;
             }
-            cursor += 1;
         }
-        (output.len() == UNCOMPRESSED_SIZE).then(|| ())
+        cursor += 1;
+    }
+    if output.len() == UNCOMPRESSED_SIZE {
+        Ok(())
     } else {
-        // reserved
-        None
+        Err(ChunkError::OutputUnderflow(src.len()))
+    }
+}
+
+#[inline]
+fn copy_match(
+    output: &mut Vec<u8, UNCOMPRESSED_SIZE>,
+    reference: Option<&[u8; UNCOMPRESSED_SIZE]>,
+    slide: usize,
+    slen: usize,
+    cursor: usize,
+) -> Result<(), ChunkError> {
+    let available = output.len() + reference.map_or(0, |reference| reference.len());
+    if available < slide {
+        return Err(ChunkError::MatchOutOfRange(cursor));
+    }
+    if output.len() + slen > UNCOMPRESSED_SIZE {
+        return Err(ChunkError::OutputOverflow(cursor));
     }
+    for _ in 0..slen {
+        let v = match output.len().checked_sub(slide) {
+            Some(index) => output[index],
+            // Only reachable with a reference, since `slide` is within the available data.
+            None => reference.map_or(0, |reference| {
+                reference[UNCOMPRESSED_SIZE + output.len() - slide]
+            }),
+        };
+        output
+            .push(v)
+            .map_err(|_| ChunkError::OutputOverflow(cursor))?;
+    }
+    Ok(())
 }
 
 #[inline]
 pub(crate) fn is_valid_compressed_size(size: usize) -> bool {
-    size >= MINIMAL_COMPRESSED_SIZE && size < COMPACTED_SIZE
+    (MINIMAL_COMPRESSED_SIZE..COMPACTED_SIZE).contains(&size)
+}
+
+/// Size of a raw, compacted or compressed chunk.
+#[inline]
+pub(crate) fn is_valid_size(size: usize) -> bool {
+    size == UNCOMPRESSED_SIZE || size == COMPACTED_SIZE || is_valid_compressed_size(size)
+}
+
+/// A delta chunk can be as short as a few far matches, but must be smaller than a compacted chunk.
+#[inline]
+pub(crate) fn is_valid_delta_size(size: usize) -> bool {
+    (1..COMPACTED_SIZE).contains(&size)
 }
@@ -51,11 +51,9 @@ impl MpicRgb666 {
         let u = (u6_to_u8(yuv.u) as i32).wrapping_sub(128);
         let v = (u6_to_u8(yuv.v) as i32).wrapping_sub(128);
 
-        let r = ((298 * y + 409 * v + 128).wrapping_shr(10)).max(0).min(63);
-        let g = ((298 * y - 100 * u - 208 * v + 128).wrapping_shr(10))
-            .max(0)
-            .min(63);
-        let b = ((298 * y + 516 * u + 128).wrapping_shr(10)).max(0).min(63);
+        let r = ((298 * y + 409 * v + 128).wrapping_shr(10)).clamp(0, 63);
+        let g = ((298 * y - 100 * u - 208 * v + 128).wrapping_shr(10)).clamp(0, 63);
+        let b = ((298 * y + 516 * u + 128).wrapping_shr(10)).clamp(0, 63);
 
         Self {
             r: r as u8,
@@ -73,6 +71,23 @@ impl MpicRgb666 {
         }
     }
 
+    /// Convert to RGB565 packed in a 16-bit value.
+    #[inline]
+    pub const fn into_rgb565(self) -> u16 {
+        ((self.r as u16 >> 1) << 11) | ((self.g as u16) << 5) | (self.b as u16 >> 1)
+    }
+
+    /// Convert to RGB565 packed in a 16-bit value, with ordered dithering at the given pixel position.
+    #[inline]
+    pub const fn into_rgb565_dithered(self, x: usize, y: usize) -> u16 {
+        let bias = BAYER_8X8[y & 7][x & 7] as u16 * 2 + 1;
+        let r = (self.r as u16 * 64 + bias) >> 7;
+        let b = (self.b as u16 * 64 + bias) >> 7;
+        let r = if r > 31 { 31 } else { r };
+        let b = if b > 31 { 31 } else { b };
+        (r << 11) | ((self.g as u16) << 5) | b
+    }
+
     #[inline]
     pub const fn r8(&self) -> u8 {
         u6_to_u8(self.r)
@@ -168,6 +183,45 @@ from_rgb!(Bgr666, 2, 2, 2);
 from_rgb!(Rgb888);
 from_rgb!(Bgr888);
 
+/// Convert RGB888 to YUV with 8 fractional bits below the 6 bits of each channel.
+#[inline]
+pub(crate) const fn rgb_to_yuv_fine(rgb: MpicRgb888) -> [i32; 3] {
+    let r = rgb.r as i32;
+    let g = rgb.g as i32;
+    let b = rgb.b as i32;
+
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 2) + (4 << 8);
+    let u = (-38 * r - 74 * g + 112 * b + 128 + (128 << 8)) >> 2;
+    let v = (112 * r - 94 * g - 18 * b + 128 + (128 << 8)) >> 2;
+
+    [y, u, v]
+}
+
+/// Truncate a value with 8 fractional bits to 6 bits.
+#[inline]
+pub(crate) const fn quantize_fine(val: i32) -> u8 {
+    let val = val >> 8;
+    if val < 0 {
+        0
+    } else if val > 63 {
+        63
+    } else {
+        val as u8
+    }
+}
+
+/// 8x8 Bayer matrix for ordered dithering
+pub(crate) const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
 /// expand 6bit value to 8bit
 #[inline]
 #[allow(dead_code)]
@@ -0,0 +1,67 @@
+//! CRC-32 checksum (IEEE 802.3), the same as used by zlib and PNG
+
+/// Lookup table for the reflected polynomial `0xEDB88320`
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                (value >> 1) ^ 0xEDB8_8320
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+}
+
+/// Incremental CRC-32 calculation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// Start a new calculation.
+    #[inline]
+    pub const fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Add the given data.
+    #[inline]
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.state = TABLE[((self.state ^ byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    /// Return the checksum of the data added so far.
+    #[inline]
+    pub const fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Return the checksum of the given data.
+#[inline]
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
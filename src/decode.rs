@@ -1,14 +1,27 @@
-use crate::{chunk::UNCOMPRESSED_SIZE, color::*, *};
+use crate::{
+    checksum::ChecksumRecord,
+    chunk::{ChunkError, UNCOMPRESSED_SIZE},
+    color::*,
+    *,
+};
 use core::marker::PhantomData;
 use heapless::Vec;
 
 #[cfg(feature = "embedded")]
-use embedded_graphics::{prelude::*, primitives::Rectangle};
+use embedded_graphics::{
+    pixelcolor::{Rgb565, raw::RawU16},
+    prelude::*,
+    primitives::Rectangle,
+};
 
 /// Decoder for MPIC format.
 pub struct Decoder<'a, T> {
     blob: &'a [u8],
     info: ImageInfo,
+    metadata: &'a [u8],
+    data_offset: usize,
+    verify: bool,
+    lenient: bool,
     _phantom: PhantomData<T>,
 }
 
@@ -18,34 +31,196 @@ impl<'a, T> Decoder<'a, T> {
     /// Returns `None` if the data is not a valid MPIC file.
     #[inline]
     pub fn new(blob: &'a [u8]) -> Option<Self> {
-        let header = FileHeader::from_bytes(blob)?;
-        if !header.is_valid() {
+        Self::_new(blob).ok()
+    }
+
+    pub(crate) fn _new(blob: &'a [u8]) -> Result<Self, DecodeError> {
+        let header = FileHeader::parse(blob)?;
+        let (metadata, data_offset) = metadata::read_section(blob, header.version())?;
+        Ok(Self {
+            blob,
+            info: header.info(),
+            metadata,
+            data_offset,
+            verify: false,
+            lenient: false,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Create a decoder from data that was validated in advance, without checking the metadata and the image data again.
+    ///
+    /// This is a `const fn` for assets embedded with `mpic_macros::include_mpic!`,
+    /// which validates the file at compile time, so that the decoder and its [`ImageInfo`] are known at compile time.
+    /// For any other data, use [`Decoder::new`] or [`Decoder::new_strict`].
+    ///
+    /// Returns `None` if the file header is invalid or the metadata section is truncated,
+    /// which `include_mpic!` turns into a build failure.
+    /// Other invalid data makes decoding fail or draw garbage.
+    pub const fn from_validated(blob: &'a [u8]) -> Option<Self> {
+        let Ok(header) = FileHeader::parse(blob) else {
             return None;
-        }
-        let info = header.info();
+        };
+        let start = FileHeader::MINIMAL_SIZE;
+        let (metadata, data_offset) = if header.version().0 < Version::V2.0 {
+            (&[] as &[u8], start)
+        } else {
+            let Some((_, section)) = blob.split_at_checked(start) else {
+                return None;
+            };
+            let Some((len, records)) =
+                section.split_first_chunk::<{ metadata::SECTION_HEADER_SIZE }>()
+            else {
+                return None;
+            };
+            let len = u32::from_le_bytes(*len) as usize;
+            let Some((metadata, _)) = records.split_at_checked(len) else {
+                return None;
+            };
+            (metadata, start + metadata::SECTION_HEADER_SIZE + len)
+        };
         Some(Self {
             blob,
-            info,
+            info: header.info(),
+            metadata,
+            data_offset,
+            verify: false,
+            lenient: false,
             _phantom: PhantomData,
         })
     }
 
+    /// Create a new decoder from the given MPIC data blob, validating the whole data in advance.
+    ///
+    /// Unlike [`Decoder::new`], which only checks the file header, this checks every chunk as [`Decoder::validate`] does,
+    /// so that drawing never stops halfway because of corrupted data.
+    pub fn new_strict(blob: &'a [u8]) -> Result<Self, DecodeError> {
+        let decoder = Self::_new(blob)?;
+        decoder.validate()?;
+        Ok(decoder)
+    }
+
+    /// Validate the whole data.
+    ///
+    /// Checks that the size of a version `0` image is a multiple of 8,
+    /// that every chunk has a valid size, is not truncated, decompresses to exactly 96 bytes,
+    /// and that no bytes remain after the last chunk.
+    /// On failure, the error describes what is wrong and contains the index of the offending chunk
+    /// and the byte offset where the problem was found.
+    ///
+    /// For an animation, the chunks of every frame are checked, and the index of a chunk counts the chunks of the previous frames.
+    pub fn validate(&self) -> Result<(), DecodeError> {
+        FileHeader::parse(self.blob)?.validate_alignment()?;
+        let end = match self.animation_table()? {
+            Some(table) => self.validate_frames(table)?,
+            None => {
+                let mut reader = self.chunks();
+                let mut buf = Vec::<u8, UNCOMPRESSED_SIZE>::new();
+                for raw in reader.by_ref() {
+                    raw?.decompress(None, &mut buf)?;
+                }
+                reader.cursor
+            }
+        };
+        if end < self.blob.len() {
+            return Err(DecodeError::TrailingData { offset: end });
+        }
+        Ok(())
+    }
+
+    /// Set whether to verify the checksum of each row of blocks just before decoding it.
+    ///
+    /// This costs much less than [`Decoder::verify`] in advance, since the chunks are only read once more per row.
+    /// Decoding and drawing stop at the first row that does not match, after the rows above it have been drawn,
+    /// and decoding returns [`DecodeError::ChecksumMismatch`] with that row.
+    /// The file header and the metadata are checked before the first row.
+    /// A file without checksums fails with [`DecodeError::MissingChecksum`].
+    ///
+    /// Only the first frame of an animation is covered.
+    #[inline]
+    pub const fn with_verification(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Set whether to keep decoding past damaged data.
+    ///
+    /// In lenient mode, a block that cannot be decoded is filled with mid-gray instead of stopping with an error.
+    /// A missing or malformed checksum record and a file header that does not match its checksum are still errors.
+    /// If the file has a [`Metadata::RowOffsets`] record, reading resumes at the start of the next row of blocks,
+    /// so that a corrupted chunk size only affects the rest of its row. Otherwise, all the following blocks are filled.
+    /// Combined with [`Decoder::with_verification`], a row that does not match its checksum is filled as well,
+    /// which also catches damage that still decodes.
+    ///
+    /// Only the first frame of an animation is covered.
+    #[inline]
+    pub const fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Return an iterator over the chunks of the image data.
+    #[inline]
+    pub(crate) fn chunks(&self) -> ChunkReader<'a> {
+        self.chunks_at(self.data_offset, 0, false)
+    }
+
+    /// Return an iterator over the chunks of a frame starting at the given byte offset.
+    ///
+    /// If `inter` is true, unchanged chunks and delta chunks, which refer to the previous frame, are accepted.
+    #[inline]
+    pub(crate) fn chunks_at(&self, cursor: usize, frame: usize, inter: bool) -> ChunkReader<'a> {
+        let width = self.info().width() as usize;
+        let height = self.info().height() as usize;
+        let cols = width.div_ceil(8);
+        let count = cols * height.div_ceil(8);
+        ChunkReader {
+            blob: self.blob,
+            cursor,
+            index: frame * count,
+            count: (frame + 1) * count,
+            inter,
+            cols,
+            checksums: (self.verify && frame == 0).then(|| self.checksum_record()),
+            lenient: self.lenient && frame == 0,
+            row_offsets: self.lenient.then(|| self.row_offsets()).flatten(),
+            data_offset: self.data_offset,
+            failed: None,
+        }
+    }
+
+    /// Return the byte offset of the image data.
+    #[inline]
+    pub(crate) fn data_offset(&self) -> usize {
+        self.data_offset
+    }
+
+    /// Return the byte offset of the given slice of the data blob.
+    #[inline]
+    pub(crate) fn offset_of(&self, slice: &[u8]) -> usize {
+        slice.as_ptr() as usize - self.blob.as_ptr() as usize
+    }
+
     /// Get the image information (width and height).
     #[inline]
-    pub fn info(&self) -> ImageInfo {
+    pub const fn info(&self) -> ImageInfo {
         self.info
     }
 
+    /// Return an iterator over the metadata records.
+    ///
+    /// Files before [`Version::V2`] have no metadata.
+    #[inline]
+    pub fn metadata(&self) -> MetadataIter<'a> {
+        MetadataIter::new(self.metadata)
+    }
+
     /// Decode the MPIC data to a vector of bytes in RGB888 format (3 bytes per pixel).
     ///
     /// Returns an error if the data is invalid.
     #[cfg(feature = "alloc")]
     pub fn decode(&self) -> Result<alloc::vec::Vec<u8>, DecodeError> {
-        let width = self.info().width() as usize;
-        let height = self.info().height() as usize;
-        let vec_size = width * height * 3;
-        let mut vec = alloc::vec::Vec::with_capacity(vec_size);
-        vec.resize(vec_size, 0);
+        let mut vec = alloc::vec![0; self.info().buffer_len(3)?];
         self.decode_to_slice(vec.as_mut()).map(|_| vec)
     }
 
@@ -56,201 +231,201 @@ impl<'a, T> Decoder<'a, T> {
     /// Returns an error if the data is invalid or if the output buffer is too small.
     pub fn decode_to_slice(&self, output: &mut [u8]) -> Result<(), DecodeError> {
         let width = self.info().width() as usize;
-        let height = self.info().height() as usize;
-        let vec_size = width * height * 3;
-        if output.len() < vec_size {
+        if output.len() < self.info().buffer_len(3)? {
             return Err(DecodeError::InvalidInput);
         }
 
-        let w8 = width & !7;
-        let h8 = height & !7;
-        let w7 = width & 7;
-        let h7 = height & 7;
-
-        let mut cursor = size_of::<FileHeader>();
-        for y8 in (0..h8).step_by(8) {
-            for x8 in (0..w8).step_by(8) {
-                let len = *self.blob.get(cursor).ok_or(DecodeError::InvalidData)? as usize;
-                let src = self
-                    .blob
-                    .get(cursor + 1..cursor + len + 1)
-                    .ok_or(DecodeError::InvalidData)?;
-                Self::decode_chunk(src).map(|(buf_y, buf_u, buf_v)| {
-                    for y7 in 0..8 {
-                        for x7 in 0..8 {
-                            let y = buf_y[(y7 * 8 + x7) as usize];
-                            let u = buf_u[(y7 * 8 + x7) as usize];
-                            let v = buf_v[(y7 * 8 + x7) as usize];
-                            let rgb = MpicRgb666::from_yuv(MpicYuv666::new(y, u, v));
-
-                            let index = (x8 + x7 + (y8 + y7) * width) * 3;
-                            output[index] = rgb.r8();
-                            output[index + 1] = rgb.g8();
-                            output[index + 2] = rgb.b8();
-                        }
-                    }
-                })?;
-                cursor += len + 1;
-            }
-            if w7 > 0 {
-                cursor += self._decode_edge(output, cursor, width, w8, y8, w7, 8)?;
-            }
-        }
-        if h7 > 0 {
-            for x8 in (0..w8).step_by(8) {
-                cursor += self._decode_edge(output, cursor, width, x8, w8, 8, h7)?;
-            }
-            if w7 > 0 {
-                self._decode_edge(output, cursor, width, w8, h8, w7, h7)?;
-            }
-        }
-        Ok(())
-    }
-
-    #[inline]
-    fn _decode_edge(
-        &self,
-        output: &mut [u8],
-        cursor: usize,
-        width: usize,
-        x8: usize,
-        y8: usize,
-        w7: usize,
-        h7: usize,
-    ) -> Result<usize, DecodeError> {
-        let len = *self.blob.get(cursor).ok_or(DecodeError::InvalidData)? as usize;
-        let src = self
-            .blob
-            .get(cursor + 1..cursor + len + 1)
-            .ok_or(DecodeError::InvalidData)?;
-        Self::decode_chunk(src).map(|(buf_y, buf_u, buf_v)| {
-            for y7 in 0..h7 {
-                for x7 in 0..w7 {
-                    let y = buf_y[(y7 * 8 + x7) as usize];
-                    let u = buf_u[(y7 * 8 + x7) as usize];
-                    let v = buf_v[(y7 * 8 + x7) as usize];
-                    let rgb = MpicRgb666::from_yuv(MpicYuv666::new(y, u, v));
-
-                    let index = (x8 + x7 + (y8 + y7) * width) * 3;
-                    output[index] = rgb.r8();
-                    output[index + 1] = rgb.g8();
-                    output[index + 2] = rgb.b8();
-                }
-            }
-        })?;
-
-        Ok(len + 1)
+        self._decode_pixels(|x, y, rgb| {
+            let index = (x + y * width) * 3;
+            output[index] = rgb.r8();
+            output[index + 1] = rgb.g8();
+            output[index + 2] = rgb.b8();
+        })
     }
 
     /// Decode the MPIC data to a vector of bytes in RGBA8888 format (4 bytes per pixel).
     #[cfg(feature = "alloc")]
     pub fn decode_rgba(&self) -> Result<alloc::vec::Vec<u8>, DecodeError> {
         let width = self.info().width() as usize;
-        let height = self.info().height() as usize;
-        let vec_size = width * height * 4;
-        let mut vec = alloc::vec::Vec::with_capacity(vec_size);
-        vec.resize(vec_size, 0);
-
-        let w8 = width & !7;
-        let h8 = height & !7;
-        let w7 = width & 7;
-        let h7 = height & 7;
-
-        let mut cursor = size_of::<FileHeader>();
-        for y8 in (0..h8).step_by(8) {
-            for x8 in (0..w8).step_by(8) {
-                let len = *self.blob.get(cursor).ok_or(DecodeError::InvalidData)? as usize;
-                let src = self
-                    .blob
-                    .get(cursor + 1..cursor + len + 1)
-                    .ok_or(DecodeError::InvalidData)?;
-                Self::decode_chunk(src).map(|(buf_y, buf_u, buf_v)| {
-                    for y7 in 0..8 {
-                        for x7 in 0..8 {
-                            let y = buf_y[(y7 * 8 + x7) as usize];
-                            let u = buf_u[(y7 * 8 + x7) as usize];
-                            let v = buf_v[(y7 * 8 + x7) as usize];
-                            let rgb = MpicRgb666::from_yuv(MpicYuv666::new(y, u, v));
-
-                            let index = (x8 + x7 + (y8 + y7) * width) * 4;
-                            vec[index] = rgb.r8();
-                            vec[index + 1] = rgb.g8();
-                            vec[index + 2] = rgb.b8();
-                            vec[index + 3] = u8::MAX;
-                        }
-                    }
-                })?;
-                cursor += len + 1;
-            }
-            if w7 > 0 {
-                cursor +=
-                    self._decode_edge_rgba(vec.as_mut_slice(), cursor, width, w8, y8, w7, 8)?;
-            }
-        }
-        if h7 > 0 {
-            for x8 in (0..w8).step_by(8) {
-                cursor +=
-                    self._decode_edge_rgba(vec.as_mut_slice(), cursor, width, x8, h8, 8, h7)?;
-            }
-            if w7 > 0 {
-                self._decode_edge_rgba(vec.as_mut_slice(), cursor, width, w8, h8, w7, h7)?;
-            }
-        }
+        let mut vec = alloc::vec![0; self.info().buffer_len(4)?];
+
+        self._decode_pixels(|x, y, rgb| {
+            let index = (x + y * width) * 4;
+            vec[index] = rgb.r8();
+            vec[index + 1] = rgb.g8();
+            vec[index + 2] = rgb.b8();
+            vec[index + 3] = u8::MAX;
+        })
+        .map(|_| vec)
+    }
 
-        Ok(vec)
+    /// Decode the MPIC data to a vector of RGB565 pixels.
+    ///
+    /// If `dither` is true, ordered dithering is applied when reducing the red and blue channels to 5 bits.
+    #[cfg(feature = "alloc")]
+    pub fn decode_rgb565(&self, dither: bool) -> Result<alloc::vec::Vec<u16>, DecodeError> {
+        let mut vec = alloc::vec![0; self.info().buffer_len(1)?];
+        self.decode_rgb565_to_slice(vec.as_mut(), dither)
+            .map(|_| vec)
     }
 
-    fn _decode_edge_rgba(
+    /// Decode the MPIC data to the given output buffer of RGB565 pixels.
+    ///
+    /// The output buffer should have a length of at least `width * height` pixels.
+    /// If `dither` is true, ordered dithering is applied when reducing the red and blue channels to 5 bits.
+    ///
+    /// Returns an error if the data is invalid or if the output buffer is too small.
+    pub fn decode_rgb565_to_slice(
         &self,
-        output: &mut [u8],
-        cursor: usize,
-        width: usize,
-        x8: usize,
-        y8: usize,
-        w7: usize,
-        h7: usize,
-    ) -> Result<usize, DecodeError> {
-        let len = *self.blob.get(cursor).ok_or(DecodeError::InvalidData)? as usize;
-        let src = self
-            .blob
-            .get(cursor + 1..cursor + len + 1)
-            .ok_or(DecodeError::InvalidData)?;
-        Self::decode_chunk(src).map(|(buf_y, buf_u, buf_v)| {
+        output: &mut [u16],
+        dither: bool,
+    ) -> Result<(), DecodeError> {
+        let width = self.info().width() as usize;
+        if output.len() < self.info().buffer_len(1)? {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self._decode_pixels(|x, y, rgb| {
+            output[x + y * width] = if dither {
+                rgb.into_rgb565_dithered(x, y)
+            } else {
+                rgb.into_rgb565()
+            };
+        })
+    }
+
+    /// Decode all pixels in order, calling `f` with the position and the color of each pixel.
+    fn _decode_pixels<F>(&self, mut f: F) -> Result<(), DecodeError>
+    where
+        F: FnMut(usize, usize, MpicRgb666),
+    {
+        self._decode_blocks(|x8, y8, w7, h7, buf_y, buf_u, buf_v| {
             for y7 in 0..h7 {
                 for x7 in 0..w7 {
-                    let y = buf_y[(y7 * 8 + x7) as usize];
-                    let u = buf_u[(y7 * 8 + x7) as usize];
-                    let v = buf_v[(y7 * 8 + x7) as usize];
-                    let rgb = MpicRgb666::from_yuv(MpicYuv666::new(y, u, v));
-
-                    let index = (x8 + x7 + (y8 + y7) * width) * 4;
-                    output[index] = rgb.r8();
-                    output[index + 1] = rgb.g8();
-                    output[index + 2] = rgb.b8();
-                    output[index + 3] = u8::MAX;
+                    let index = y7 * 8 + x7;
+                    let rgb = MpicRgb666::from_yuv(MpicYuv666::new(
+                        buf_y[index],
+                        buf_u[index],
+                        buf_v[index],
+                    ));
+                    f(x8 + x7, y8 + y7, rgb);
                 }
             }
-        })?;
+        })
+    }
 
-        Ok(len + 1)
+    /// Decode all blocks in order, calling `f` with the position, the visible size and the YUV buffers of each block.
+    fn _decode_blocks<F>(&self, mut f: F) -> Result<(), DecodeError>
+    where
+        F: FnMut(usize, usize, usize, usize, &[u8; 64], &[u8; 64], &[u8; 64]),
+    {
+        self._decode_planes(|x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+            f(
+                x8,
+                y8,
+                w7,
+                h7,
+                buf_y,
+                &demosaic_uv(buf_u),
+                &demosaic_uv(buf_v),
+            );
+        })
     }
 
-    #[allow(dead_code)]
-    fn decode_sub_image<F, E>(
+    /// Decode all blocks in order, calling `f` with the position, the visible size
+    /// and the 8x8 Y plane and 4x4 U and V planes of each block.
+    pub(crate) fn _decode_planes<F>(&self, mut f: F) -> Result<(), DecodeError>
+    where
+        F: FnMut(usize, usize, usize, usize, &[u8; 64], &[u8; 16], &[u8; 16]),
+    {
+        let info = self.info();
+        self._decode_planes_in(
+            0,
+            0,
+            info.width(),
+            info.height(),
+            |x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+                f(
+                    x8 as usize,
+                    y8 as usize,
+                    w7 as usize,
+                    h7 as usize,
+                    buf_y,
+                    buf_u,
+                    buf_v,
+                );
+                Ok::<_, core::convert::Infallible>(())
+            },
+        )
+        .map_err(DrawError::into_decode)
+    }
+
+    /// Decode the blocks that overlap the area from (`left`, `top`) to (`right`, `bottom`) in order,
+    /// calling `f` with the position, the size clipped to the right and bottom of the area,
+    /// and the 8x8 Y plane and 4x4 U and V planes of each block.
+    ///
+    /// The blocks outside the area are skipped without decompressing them, and the chunks after it are not read.
+    /// Stops at the first error, whether it is in the data or returned by `f`.
+    pub(crate) fn _decode_planes_in<F, E>(
+        &self,
+        left: u32,
+        top: u32,
+        right: u32,
+        bottom: u32,
+        mut f: F,
+    ) -> Result<(), DrawError<E>>
+    where
+        F: FnMut(u32, u32, u32, u32, &[u8; 64], &[u8; 16], &[u8; 16]) -> Result<(), E>,
+    {
+        let image_width = self.info().width();
+        let right = right.min(image_width);
+        let bottom = bottom.min(self.info().height());
+        let block_left = left & !7;
+        let block_top = top & !7;
+
+        let mut chunks = self.chunks();
+        let mut buf = Vec::<u8, UNCOMPRESSED_SIZE>::new();
+        for y8 in (0..bottom).step_by(8) {
+            let h7 = (bottom - y8).min(8);
+            for x8 in (0..image_width).step_by(8) {
+                let raw = chunks.next().unwrap_or(Err(DecodeError::InvalidData));
+                if y8 >= block_top && x8 >= block_left && x8 < right {
+                    let w7 = (right - x8).min(8);
+                    self.decompress_or_fill(raw, &mut buf)?;
+                    f(
+                        x8,
+                        y8,
+                        w7,
+                        h7,
+                        buf[0..64].try_into().unwrap(),
+                        buf[64..80].try_into().unwrap(),
+                        buf[80..96].try_into().unwrap(),
+                    )
+                    .map_err(DrawError::Target)?;
+                } else if let Err(err) = raw
+                    && !self.can_skip(&err)
+                {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode the blocks that overlap the given area of the image in order, calling `draw_block` with the position,
+    /// the size clipped to the area and the YUV buffers of each block.
+    #[cfg(feature = "embedded")]
+    pub(crate) fn decode_sub_image<F, E>(
         &self,
         left: i32,
         top: i32,
         width: u32,
         height: u32,
         mut draw_block: F,
-    ) -> Result<(), E>
+    ) -> Result<(), DrawError<E>>
     where
         F: FnMut(u32, u32, u32, u32, &[u8; 64], &[u8; 64], &[u8; 64]) -> Result<(), E>,
     {
-        let mut cursor = size_of::<FileHeader>();
-        let image_width = self.info().width();
-        let image_height = self.info().height();
-
         let mut left = left;
         let mut top = top;
         let mut right = left + width as i32;
@@ -263,70 +438,66 @@ impl<'a, T> Decoder<'a, T> {
             bottom += top;
             top = 0;
         }
-        let right = image_width.min(right as u32);
-        let bottom = image_height.min(bottom as u32);
-        let w8 = right & !7;
-        let h8 = bottom & !7;
-        let block_right = ceil_8(right);
-        let block_bottom = ceil_8(bottom);
-        let block_left = left as u32 & !7;
-        let block_top = top as u32 & !7;
-
-        let mut result = Ok(());
-        for y8 in (0..block_bottom).step_by(8) {
-            let h7 = if y8 == h8 { bottom & 7 } else { 8 };
-            for x8 in (0..image_width).step_by(8) {
-                let len = match self.blob.get(cursor) {
-                    Some(v) => *v as usize,
-                    None => return result,
-                };
-                if y8 >= block_top && x8 >= block_left && x8 <= block_right {
-                    let src = match self.blob.get(cursor + 1..cursor + len + 1) {
-                        Some(v) => v,
-                        None => return result,
-                    };
-                    let w7 = if x8 == w8 { right & 7 } else { 8 };
-                    match Self::decode_chunk(src).map(|(buf_y, buf_u, buf_v)| {
-                        match draw_block(x8, y8, w7, h7, &buf_y, &buf_u, &buf_v) {
-                            Ok(_) => (),
-                            Err(e) => result = Err(e),
-                        }
-                    }) {
-                        Ok(_) => (),
-                        Err(_) => break,
-                    }
-                    if result.is_err() {
-                        break;
-                    }
-                }
-                cursor += len + 1;
+        self._decode_planes_in(
+            left as u32,
+            top as u32,
+            right.max(0) as u32,
+            bottom.max(0) as u32,
+            |x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+                draw_block(
+                    x8,
+                    y8,
+                    w7,
+                    h7,
+                    buf_y,
+                    &demosaic_uv(buf_u),
+                    &demosaic_uv(buf_v),
+                )
+            },
+        )
+    }
+
+    /// Decompress a chunk of the image data, or in lenient mode, fill the block with mid-gray if it is damaged.
+    #[inline]
+    fn decompress_or_fill(
+        &self,
+        raw: Result<RawChunk<'a>, DecodeError>,
+        buf: &mut Vec<u8, UNCOMPRESSED_SIZE>,
+    ) -> Result<(), DecodeError> {
+        match raw.and_then(|raw| raw.decompress(None, buf)) {
+            Err(err) if self.can_skip(&err) => {
+                buf.clear();
+                let _ = buf.extend_from_slice(&recovery::NEUTRAL_BLOCK);
+                Ok(())
             }
+            result => result,
         }
-        result
+    }
+
+    /// Returns true if decoding goes on past the given error.
+    #[inline]
+    fn can_skip(&self, err: &DecodeError) -> bool {
+        self.lenient && recovery::is_recoverable(err)
     }
 
     /// Decode a single chunk of MPIC data to YUV buffers. (intend for internal use)
+    ///
+    /// Errors are reported as chunk 0, with offsets relative to the start of `src`.
+    #[allow(clippy::type_complexity)]
     pub fn decode_chunk(src: &[u8]) -> Result<([u8; 64], [u8; 64], [u8; 64]), DecodeError> {
-        let mut vec = Vec::<u8, UNCOMPRESSED_SIZE>::new();
-        chunk::decompress(src, &mut vec).ok_or(DecodeError::InvalidData)?;
-
-        let buf_y: &[u8; 64] = &vec[0..64]
-            .try_into()
-            .map_err(|_| DecodeError::InvalidData)?;
+        Self::_decode_chunk(src).map_err(|e| e.into_decode_error(0, 0))
+    }
 
-        let buf_u = demosaic_uv(
-            &vec[64..80]
-                .try_into()
-                .map_err(|_| DecodeError::InvalidData)?,
-        );
+    #[allow(clippy::type_complexity)]
+    fn _decode_chunk(src: &[u8]) -> Result<([u8; 64], [u8; 64], [u8; 64]), ChunkError> {
+        let mut vec = Vec::<u8, UNCOMPRESSED_SIZE>::new();
+        chunk::decompress(src, &mut vec)?;
 
-        let buf_v = demosaic_uv(
-            &vec[80..96]
-                .try_into()
-                .map_err(|_| DecodeError::InvalidData)?,
-        );
+        let buf_y: [u8; 64] = vec[0..64].try_into().unwrap();
+        let buf_u = demosaic_uv(vec[64..80].try_into().unwrap());
+        let buf_v = demosaic_uv(vec[80..96].try_into().unwrap());
 
-        Ok((*buf_y, buf_u, buf_v))
+        Ok((buf_y, buf_u, buf_v))
     }
 }
 
@@ -339,12 +510,13 @@ impl<T> OriginDimensions for Decoder<'_, T> {
 }
 
 #[cfg(feature = "embedded")]
-impl<T: PixelColor + From<MpicRgb666>> ImageDrawable for Decoder<'_, T> {
-    type Color = T;
-
-    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+impl<T: PixelColor + From<MpicRgb666>> Decoder<'_, T> {
+    /// Draw the image to the target like [`ImageDrawable::draw`], but report the errors in the image data.
+    ///
+    /// Returns [`DrawError::Decode`] with the index and the byte offset of the first chunk that cannot be decoded.
+    pub fn try_draw<D>(&self, target: &mut D) -> Result<(), DrawError<D::Error>>
     where
-        D: DrawTarget<Color = Self::Color>,
+        D: DrawTarget<Color = T>,
     {
         let rect = target.bounding_box();
         self.decode_sub_image(
@@ -384,11 +556,294 @@ impl<T: PixelColor + From<MpicRgb666>> ImageDrawable for Decoder<'_, T> {
         )
     }
 
+    /// Draw the given area of the image like [`ImageDrawable::draw_sub_image`], but report the errors in the image data.
+    pub fn try_draw_sub_image<D>(
+        &self,
+        target: &mut D,
+        area: &Rectangle,
+    ) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        self.try_draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> ImageDrawable for Decoder<'_, T> {
+    type Color = T;
+
+    /// Draw the image to the target.
+    ///
+    /// Drawing stops silently at the first chunk that cannot be decoded, as the error cannot be returned here.
+    /// Validate the data up front with [`Decoder::new_strict`], or use [`Self::try_draw`] to get the error.
+    #[inline]
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw(target))
+    }
+
+    #[inline]
     fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
     where
         D: DrawTarget<Color = Self::Color>,
     {
-        self.draw(&mut target.translated(-area.top_left).clipped(area))
+        DrawError::ignore_decode(self.try_draw_sub_image(target, area))
+    }
+}
+
+/// A decoder that draws with ordered dithering to RGB565.
+///
+/// This reduces the banding in gradients on displays with 16 bits per pixel.
+#[cfg(feature = "embedded")]
+pub struct DitheredDecoder<'a, 'b, T> {
+    decoder: &'b Decoder<'a, T>,
+}
+
+#[cfg(feature = "embedded")]
+impl<'a, T> Decoder<'a, T> {
+    /// Return a wrapper that draws this image with ordered dithering to RGB565.
+    #[inline]
+    pub fn dithered(&self) -> DitheredDecoder<'a, '_, T> {
+        DitheredDecoder { decoder: self }
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> OriginDimensions for DitheredDecoder<'_, '_, T> {
+    #[inline]
+    fn size(&self) -> Size {
+        self.decoder.size()
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<Rgb565>> DitheredDecoder<'_, '_, T> {
+    /// Draw the image to the target like [`ImageDrawable::draw`], but report the errors in the image data.
+    ///
+    /// Returns [`DrawError::Decode`] with the index and the byte offset of the first chunk that cannot be decoded.
+    pub fn try_draw<D>(&self, target: &mut D) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        let rect = target.bounding_box();
+        self.decoder.decode_sub_image(
+            rect.top_left.x,
+            rect.top_left.y,
+            rect.size.width,
+            rect.size.height,
+            |x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+                let mut colors = heapless::Vec::<T, 64>::new();
+                for y7 in 0..h7 {
+                    for x7 in 0..w7 {
+                        let index = (y7 * 8 + x7) as usize;
+                        let rgb = MpicRgb666::from(MpicYuv666::new(
+                            buf_y[index],
+                            buf_u[index],
+                            buf_v[index],
+                        ));
+                        let raw = rgb.into_rgb565_dithered((x8 + x7) as usize, (y8 + y7) as usize);
+                        let _ = colors.push(Rgb565::from(RawU16::new(raw)).into());
+                    }
+                }
+                target.fill_contiguous(
+                    &Rectangle::new(Point::new(x8 as i32, y8 as i32), Size::new(w7, h7)),
+                    colors,
+                )
+            },
+        )
+    }
+
+    /// Draw the given area of the image like [`ImageDrawable::draw_sub_image`], but report the errors in the image data.
+    pub fn try_draw_sub_image<D>(
+        &self,
+        target: &mut D,
+        area: &Rectangle,
+    ) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        self.try_draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<Rgb565>> ImageDrawable for DitheredDecoder<'_, '_, T> {
+    type Color = T;
+
+    /// Stops silently at the first chunk that cannot be decoded, see [`Self::try_draw`].
+    #[inline]
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw(target))
+    }
+
+    #[inline]
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw_sub_image(target, area))
+    }
+}
+
+/// A chunk in the image data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RawChunk<'a> {
+    /// Index of the chunk, counted from the top-left block
+    pub index: usize,
+    /// Byte offset of the size of the chunk from the start of the file
+    pub offset: usize,
+    /// Compressed data of the chunk, excluding the size
+    pub payload: &'a [u8],
+    /// Whether the chunk is a delta chunk, which refers to the same block of the previous frame
+    pub delta: bool,
+}
+
+impl RawChunk<'_> {
+    /// Returns true if the chunk marks a block that is the same as in the previous frame.
+    #[inline]
+    pub fn is_unchanged(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    /// Decompress the chunk, reporting errors with the position in the file.
+    ///
+    /// A delta chunk needs the same block of the previous frame as `reference`.
+    #[inline]
+    pub fn decompress(
+        &self,
+        reference: Option<&[u8; UNCOMPRESSED_SIZE]>,
+        output: &mut Vec<u8, UNCOMPRESSED_SIZE>,
+    ) -> Result<(), DecodeError> {
+        let result = match (self.delta, reference) {
+            (false, _) => chunk::decompress(self.payload, output),
+            (true, Some(reference)) => chunk::decompress_delta(self.payload, reference, output),
+            (true, None) => {
+                return Err(DecodeError::MissingReference {
+                    index: self.index,
+                    offset: self.offset,
+                });
+            }
+        };
+        result.map_err(|e| e.into_decode_error(self.index, self.offset + 1))
+    }
+}
+
+/// Iterator over the chunks in the image data.
+///
+/// Stops after the first error.
+pub(crate) struct ChunkReader<'a> {
+    blob: &'a [u8],
+    /// Byte offset of the next chunk
+    pub cursor: usize,
+    index: usize,
+    count: usize,
+    /// Whether unchanged chunks and delta chunks are accepted
+    inter: bool,
+    /// Number of blocks in a row
+    cols: usize,
+    /// Checksums to verify each row against before reading its first chunk
+    pub checksums: Option<Result<ChecksumRecord<'a>, DecodeError>>,
+    /// Whether to keep yielding chunks after an error, as errors until the next row can be found
+    lenient: bool,
+    /// Table of the byte offset of each row, to resume at after an error in lenient mode
+    row_offsets: Option<&'a [u8]>,
+    data_offset: usize,
+    /// The error that stopped the reading in lenient mode
+    failed: Option<DecodeError>,
+}
+
+impl ChunkReader<'_> {
+    /// Verify the row of blocks that starts at the next chunk.
+    fn verify_row(&self, row: usize) -> Result<(), DecodeError> {
+        match self.checksums {
+            Some(checksums) => checksums.and_then(|record| {
+                if row == 0 {
+                    record.verify_header(self.blob)?;
+                }
+                record.verify_row(self.blob, row, self.cursor, self.cols)
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> Iterator for ChunkReader<'a> {
+    type Item = Result<RawChunk<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+        let index = self.index;
+        if index.is_multiple_of(self.cols) {
+            let row = index / self.cols;
+            if self.lenient
+                && let Some(offset) = self
+                    .row_offsets
+                    .and_then(|table| recovery::row_offset(table, row))
+            {
+                // Resume at the recorded start of the row, so that an error does not spread to the following rows.
+                self.cursor = self.data_offset.saturating_add(offset);
+                self.failed = None;
+            }
+            if self.failed.is_none()
+                && let Err(err) = self.verify_row(row)
+            {
+                if !recovery::is_recoverable(&err) {
+                    self.index = self.count;
+                    return Some(Err(err));
+                }
+                self.failed = Some(err);
+            }
+        }
+        if let Some(err) = self.failed {
+            self.index = if self.lenient { index + 1 } else { self.count };
+            return Some(Err(err));
+        }
+        let offset = self.cursor;
+        let result = match self.blob.get(offset) {
+            Some(&len) => {
+                let delta = self.inter && len & chunk::DELTA_FLAG != 0;
+                let len = (len & !if delta { chunk::DELTA_FLAG } else { 0 }) as usize;
+                let valid = if delta {
+                    chunk::is_valid_delta_size(len)
+                } else {
+                    (len == 0 && self.inter) || chunk::is_valid_size(len)
+                };
+                if !valid {
+                    Err(DecodeError::InvalidChunkLength { index, offset })
+                } else {
+                    match self.blob.get(offset + 1..offset + 1 + len) {
+                        Some(payload) => {
+                            self.cursor += 1 + len;
+                            Ok(RawChunk {
+                                index,
+                                offset,
+                                payload,
+                                delta,
+                            })
+                        }
+                        None => Err(DecodeError::TruncatedChunk { index, offset }),
+                    }
+                }
+            }
+            None => Err(DecodeError::TruncatedChunk { index, offset }),
+        };
+        match result {
+            Ok(_) => self.index = index + 1,
+            Err(err) if self.lenient => {
+                self.failed = Some(err);
+                self.index = index + 1;
+            }
+            Err(_) => self.index = self.count,
+        }
+        Some(result)
     }
 }
 
@@ -408,8 +863,3 @@ pub(crate) fn demosaic_uv(data: &[u8; 16]) -> [u8; 64] {
     }
     buf
 }
-
-#[inline(always)]
-const fn ceil_8(v: u32) -> u32 {
-    (v + 7) & !7
-}
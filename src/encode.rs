@@ -1,13 +1,366 @@
-#[cfg(feature = "alloc")]
 use crate::lz::CompressionLevel;
-use crate::{chunk::UNCOMPRESSED_SIZE, color::*, *};
+use crate::{chunk::UNCOMPRESSED_SIZE, color::*, crc::Crc32, *};
+use core::cell::Cell;
 use heapless::Vec;
 
+/// Options for encoding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderOptions<'a> {
+    level: CompressionLevel,
+    chroma_filter: ChromaFilter,
+    edge_padding: EdgePadding,
+    dither: Dither,
+    pixel_format: PixelFormat,
+    stride: Option<usize>,
+    quality: u8,
+    version: Option<Version>,
+    metadata: &'a [Metadata<'a>],
+    thumbnail: bool,
+    row_offsets: bool,
+    checksum: bool,
+}
+
+impl<'a> EncoderOptions<'a> {
+    /// Create a new set of options with the default values.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            level: CompressionLevel::Default,
+            chroma_filter: ChromaFilter::Box,
+            edge_padding: EdgePadding::Flat,
+            dither: Dither::None,
+            pixel_format: PixelFormat::Rgb888,
+            stride: None,
+            quality: 100,
+            version: None,
+            metadata: &[],
+            thumbnail: false,
+            row_offsets: false,
+            checksum: false,
+        }
+    }
+
+    /// Set the compression level.
+    #[inline]
+    pub const fn with_level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Set the method of thinning out the U and V channels.
+    #[inline]
+    pub const fn with_chroma_filter(mut self, chroma_filter: ChromaFilter) -> Self {
+        self.chroma_filter = chroma_filter;
+        self
+    }
+
+    /// Set the method of filling the area outside the image in the blocks on the right and bottom edges.
+    #[inline]
+    pub const fn with_edge_padding(mut self, edge_padding: EdgePadding) -> Self {
+        self.edge_padding = edge_padding;
+        self
+    }
+
+    /// Set the dithering applied when reducing the colors to 6 bits per channel.
+    #[inline]
+    pub const fn with_dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Set the pixel format of the input data.
+    #[inline]
+    pub const fn with_pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    /// Set the number of bytes from the start of one row of the input data to the next.
+    ///
+    /// By default, the rows are assumed to be packed without gaps.
+    #[inline]
+    pub const fn with_stride(mut self, stride: usize) -> Self {
+        self.stride = Some(stride);
+        self
+    }
+
+    /// Set the quality from 0 to 100.
+    ///
+    /// At 100 (default), the colors are only reduced to 6 bits per channel.
+    /// Lower values reduce the number of levels further, which makes the file smaller.
+    #[inline]
+    pub const fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = if quality > 100 { 100 } else { quality };
+        self
+    }
+
+    /// Set the version of the file format to write.
+    ///
+    /// By default, the oldest version that can represent the image and the metadata is chosen.
+    /// [`Version::V0`] cannot be chosen if the width or height is not a multiple of 8.
+    #[inline]
+    pub const fn with_version(mut self, version: Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Set the metadata records to write.
+    ///
+    /// Metadata requires [`Version::V2`] or later.
+    /// The records written by the encoder itself are rejected with [`EncodeError::InvalidMetadata`],
+    /// see [`Metadata::is_reserved`].
+    #[inline]
+    pub const fn with_metadata(mut self, metadata: &'a [Metadata<'a>]) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Return the compression level.
+    #[inline]
+    pub const fn level(&self) -> CompressionLevel {
+        self.level
+    }
+
+    /// Return the method of thinning out the U and V channels.
+    #[inline]
+    pub const fn chroma_filter(&self) -> ChromaFilter {
+        self.chroma_filter
+    }
+
+    /// Return the method of filling the area outside the image in the blocks on the right and bottom edges.
+    #[inline]
+    pub const fn edge_padding(&self) -> EdgePadding {
+        self.edge_padding
+    }
+
+    /// Return the dithering applied when reducing the colors to 6 bits per channel.
+    #[inline]
+    pub const fn dither(&self) -> Dither {
+        self.dither
+    }
+
+    /// Return the pixel format of the input data.
+    #[inline]
+    pub const fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// Return the number of bytes from the start of one row of the input data to the next, for an image of the given width.
+    #[inline]
+    pub const fn stride(&self, width: u32) -> usize {
+        match self.stride {
+            Some(stride) => stride,
+            None => width as usize * self.pixel_format.bytes_per_pixel(),
+        }
+    }
+
+    /// Return the quality from 0 to 100.
+    #[inline]
+    pub const fn quality(&self) -> u8 {
+        self.quality
+    }
+
+    /// Return the version of the file format to write, or `None` to choose automatically.
+    #[inline]
+    pub const fn version(&self) -> Option<Version> {
+        self.version
+    }
+
+    /// Set whether to write a thumbnail with the average color of each block.
+    ///
+    /// The thumbnail is 1/8 the size of the image and can be read without decoding the image data.
+    /// It requires [`Version::V2`] or later.
+    #[inline]
+    pub const fn with_thumbnail(mut self, thumbnail: bool) -> Self {
+        self.thumbnail = thumbnail;
+        self
+    }
+
+    /// Set whether to write a table of the byte offset of each row of blocks.
+    ///
+    /// The table is stored in a [`Metadata::RowOffsets`] record, so that a decoder in lenient mode
+    /// can skip a damaged row and continue with the next one. See [`Decoder::with_lenient`].
+    /// Since the table precedes the image data, [`Encoder::encode_to_writer_with_options`] encodes the image
+    /// one more time to fill it in.
+    /// It requires [`Version::V2`] or later, and an image small enough for every offset to fit in 32 bits.
+    #[inline]
+    pub const fn with_row_offsets(mut self, row_offsets: bool) -> Self {
+        self.row_offsets = row_offsets;
+        self
+    }
+
+    /// Set whether to write CRC-32 checksums of the file header, the metadata and each row of blocks.
+    ///
+    /// The checksums are stored in a [`Metadata::Checksum`] record after the other records,
+    /// so that a decoder can detect corrupted data before drawing it. See [`Decoder::verify`].
+    /// Since the checksums precede the image data, [`Encoder::encode_to_writer_with_options`] encodes the image
+    /// one more time to fill them in.
+    /// It requires [`Version::V2`] or later.
+    #[inline]
+    pub const fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Return the metadata records to write.
+    #[inline]
+    pub const fn metadata(&self) -> &'a [Metadata<'a>] {
+        self.metadata
+    }
+
+    /// Return whether to write a thumbnail.
+    #[inline]
+    pub const fn thumbnail(&self) -> bool {
+        self.thumbnail
+    }
+
+    /// Return whether to write a row offset table.
+    #[inline]
+    pub const fn row_offsets(&self) -> bool {
+        self.row_offsets
+    }
+
+    /// Return whether to write checksums.
+    #[inline]
+    pub const fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    /// Step between the levels of 6-bit values derived from the quality.
+    #[inline]
+    const fn quantization_step(&self) -> u8 {
+        1 + (100 - self.quality) / 25
+    }
+}
+
+impl Default for EncoderOptions<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Pixel format of the input data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    /// 3 bytes per pixel in the order of red, green and blue.
+    #[default]
+    Rgb888,
+    /// 3 bytes per pixel in the order of blue, green and red.
+    Bgr888,
+    /// 4 bytes per pixel in the order of red, green, blue and alpha. Alpha is ignored.
+    Rgba8888,
+    /// 4 bytes per pixel in the order of blue, green, red and alpha. Alpha is ignored.
+    Bgra8888,
+    /// 2 bytes per pixel of RGB565 in little-endian.
+    Rgb565,
+    /// 1 byte per pixel of grayscale.
+    L8,
+}
+
+impl PixelFormat {
+    /// Return the number of bytes per pixel.
+    #[inline]
+    pub const fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::Rgb888 | Self::Bgr888 => 3,
+            Self::Rgba8888 | Self::Bgra8888 => 4,
+            Self::Rgb565 => 2,
+            Self::L8 => 1,
+        }
+    }
+
+    /// Read the pixel at the start of the given slice.
+    #[inline]
+    fn read(&self, data: &[u8]) -> MpicRgb888 {
+        match self {
+            Self::Rgb888 | Self::Rgba8888 => MpicRgb888::new(data[0], data[1], data[2]),
+            Self::Bgr888 | Self::Bgra8888 => MpicRgb888::new(data[2], data[1], data[0]),
+            Self::Rgb565 => {
+                let raw = u16::from_le_bytes([data[0], data[1]]);
+                let r = (raw >> 11) as u8 & 0x1F;
+                let g = (raw >> 5) as u8 & 0x3F;
+                let b = raw as u8 & 0x1F;
+                MpicRgb888::new(
+                    (r << 3) | (r >> 2),
+                    (g << 2) | (g >> 4),
+                    (b << 3) | (b >> 2),
+                )
+            }
+            Self::L8 => MpicRgb888::new(data[0], data[0], data[0]),
+        }
+    }
+}
+
+/// Method of thinning out the U and V channels to 1/4.
+///
+/// The file format leaves this to the encoder, so files encoded with any filter can be read by any decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChromaFilter {
+    /// Average of 2x2 pixels with truncating division, as encoders before the other filters did.
+    #[default]
+    Box,
+    /// Average of 2x2 pixels rounded to the nearest value.
+    Average,
+    /// Average of 2x2 pixels weighted by luma, so that dark pixels whose color is hardly visible contribute less.
+    LumaWeighted,
+    /// Choose the values that minimize the RGB error after the decoder expands them with nearest-neighbor interpolation.
+    ///
+    /// This is the slowest filter, but it keeps edges between different colors sharp.
+    Sharp,
+}
+
+/// Method of filling the area outside the image in the blocks on the right and bottom edges.
+///
+/// If the image size is not a multiple of 8, the padding is encoded but never displayed.
+/// It still affects the U and V channels of the edge pixels and the size of the compressed chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgePadding {
+    /// Repeat the last column and the last row of the image.
+    Replicate,
+    /// Mirror the image across the right and bottom edges.
+    Mirror,
+    /// Fill with the top-left pixel of the block, except for the pixels that share U and V with the edge pixels.
+    ///
+    /// This is the behavior of the previous versions, and the default so that their output does not change.
+    #[default]
+    Flat,
+    /// Try all of the above and choose the one with the smallest compressed size.
+    Smallest,
+}
+
+/// Dithering applied when reducing the colors to 6 bits per channel.
+///
+/// Dithering reduces the banding in gradients such as skies, at the cost of a slightly larger file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// No dithering, the colors are truncated.
+    #[default]
+    None,
+    /// Ordered dithering with an 8x8 Bayer matrix.
+    Ordered,
+    /// Floyd-Steinberg error diffusion across the whole image, including the block boundaries.
+    ///
+    /// This requires the `alloc` feature, otherwise it falls back to [`Dither::Ordered`].
+    FloydSteinberg,
+}
+
+/// A frame of an animation.
+///
+/// See [`Encoder::encode_animation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Pixels of the frame, in the format given by the options
+    pub data: &'a [u8],
+    /// Time to show the frame, in milliseconds
+    pub duration: u16,
+}
+
 /// Encoder for MPIC format.
 pub struct Encoder;
 
 impl Encoder {
-    #[deprecated(note = "Use `encode2` instead, which allows specifying the compression level.")]
+    #[deprecated(note = "Use `encode_with_options` instead.")]
     #[cfg(feature = "alloc")]
     #[inline]
     pub fn encode(
@@ -15,16 +368,10 @@ impl Encoder {
         width: u32,
         height: u32,
     ) -> Result<alloc::vec::Vec<u8>, EncodeError> {
-        let mut vec = alloc::vec::Vec::new();
-        Self::encode_to_writer2(data, width, height, CompressionLevel::Default, |v| {
-            vec.extend_from_slice(v)
-        })
-        .map(|_| vec)
+        Self::encode_with_options(data, width, height, &EncoderOptions::new())
     }
 
-    #[deprecated(
-        note = "Use `encode_to_writer2` instead, which allows specifying the compression level."
-    )]
+    #[deprecated(note = "Use `encode_to_writer_with_options` instead.")]
     #[inline]
     pub fn encode_to_writer<F>(
         data: &[u8],
@@ -35,12 +382,13 @@ impl Encoder {
     where
         F: FnMut(&[u8]),
     {
-        Self::encode_to_writer2(data, width, height, CompressionLevel::Default, writer)
+        Self::encode_to_writer_with_options(data, width, height, &EncoderOptions::new(), writer)
     }
 
     /// Encode the image data to a vector of bytes.
     ///
     /// The input data should be in RGB888 format (3 bytes per pixel).
+    #[deprecated(note = "Use `encode_with_options` instead.")]
     #[cfg(feature = "alloc")]
     #[inline]
     pub fn encode2(
@@ -49,175 +397,491 @@ impl Encoder {
         height: u32,
         level: CompressionLevel,
     ) -> Result<alloc::vec::Vec<u8>, EncodeError> {
-        let mut vec = alloc::vec::Vec::new();
-        Self::encode_to_writer2(data, width, height, level, |v| vec.extend_from_slice(v))
-            .map(|_| vec)
+        Self::encode_with_options(
+            data,
+            width,
+            height,
+            &EncoderOptions::new().with_level(level),
+        )
     }
 
     /// Encode the image data to a writer function.
     ///
     /// The input data should be in RGB888 format (3 bytes per pixel).
+    #[deprecated(note = "Use `encode_to_writer_with_options` instead.")]
+    #[inline]
     pub fn encode_to_writer2<F>(
         data: &[u8],
         width: u32,
         height: u32,
         level: CompressionLevel,
-        mut writer: F,
+        writer: F,
+    ) -> Result<(), EncodeError>
+    where
+        F: FnMut(&[u8]),
+    {
+        Self::encode_to_writer_with_options(
+            data,
+            width,
+            height,
+            &EncoderOptions::new().with_level(level),
+            writer,
+        )
+    }
+
+    /// Encode the image data to a vector of bytes with the given options.
+    ///
+    /// The format of the input data is given by [`EncoderOptions::with_pixel_format`] and [`EncoderOptions::with_stride`].
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn encode_with_options(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: &EncoderOptions,
+    ) -> Result<alloc::vec::Vec<u8>, EncodeError> {
+        let mut vec = alloc::vec::Vec::new();
+        let deferred = Self::_encode_to_writer(data, width, height, options, true, |v| {
+            vec.extend_from_slice(v)
+        })?;
+        deferred.fill(&mut vec, width, height);
+        Ok(vec)
+    }
+
+    /// Encode the image data to a writer function with the given options.
+    ///
+    /// The format of the input data is given by [`EncoderOptions::with_pixel_format`] and [`EncoderOptions::with_stride`].
+    ///
+    /// The row offset table and the checksums precede the image data, and the written bytes cannot be revisited,
+    /// so [`EncoderOptions::with_row_offsets`] and [`EncoderOptions::with_checksum`] encode the whole image
+    /// one more time before it is written, once for both.
+    /// With [`Dither::FloydSteinberg`], that encode also allocates its own buffers of 8 rows of pixels.
+    /// [`Self::encode_with_options`] encodes the image only once, as it fills them in afterwards.
+    ///
+    /// Without the `alloc` feature, writing both returns [`EncodeError::RequiresAlloc`],
+    /// as the checksums of the rows cannot be kept while the row offset table is written.
+    #[inline]
+    pub fn encode_to_writer_with_options<F>(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: &EncoderOptions,
+        writer: F,
     ) -> Result<(), EncodeError>
     where
         F: FnMut(&[u8]),
     {
-        if data.len() < (width as usize * height as usize * 3) {
+        Self::_encode_to_writer(data, width, height, options, false, writer).map(|_| ())
+    }
+
+    /// Encode the image data to a writer function, see [`Self::_write_header`] for `deferred`.
+    fn _encode_to_writer<F>(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: &EncoderOptions,
+        deferred: bool,
+        mut writer: F,
+    ) -> Result<DeferredRecords, EncodeError>
+    where
+        F: FnMut(&[u8]),
+    {
+        let (source, records) =
+            Self::_write_header(data, width, height, options, &[], deferred, &mut writer)?;
+        Self::_encode_blocks(source, width, height, options, |block| {
+            writer(&[block.chunk.len() as u8]);
+            writer(block.chunk.as_slice());
+        });
+        Ok(records)
+    }
+
+    /// Encode an animation to a vector of bytes.
+    ///
+    /// Every frame has the given size and the format given by the options.
+    /// Blocks that are identical to the same block in the previous frame are stored as a single byte.
+    /// `loop_count` is the number of times to play the animation, or 0 to repeat it forever.
+    ///
+    /// The first frame is stored as the regular image data, so decoders that do not know about animations show the first frame.
+    #[cfg(feature = "alloc")]
+    pub fn encode_animation(
+        frames: &[Frame],
+        width: u32,
+        height: u32,
+        loop_count: u16,
+        options: &EncoderOptions,
+    ) -> Result<alloc::vec::Vec<u8>, EncodeError> {
+        if frames.is_empty() || frames.len() > u16::MAX as usize {
             return Err(EncodeError::InvalidInput);
         }
-        let header = FileHeader::new(width, height).ok_or(EncodeError::InvalidInput)?;
-        writer(header.bytes());
-
-        let w8 = width & !7;
-        let h8 = height & !7;
-        let w7 = width & 7;
-        let h7 = height & 7;
-
-        for y8 in (0..h8).step_by(8) {
-            for x8 in (0..w8).step_by(8) {
-                let mut buf_y = [0u8; 64];
-                let mut buf_u = [0u8; 64];
-                let mut buf_v = [0u8; 64];
-                for index in 0..64 {
-                    let x7 = index & 7;
-                    let y7 = index >> 3;
-                    let offset = ((y8 as usize + y7) * width as usize + x8 as usize + x7) * 3;
-
-                    let r = data[offset + 0];
-                    let g = data[offset + 1];
-                    let b = data[offset + 2];
-                    let yuv = MpicYuv666::from_rgb(MpicRgb888::new(r, g, b));
-                    buf_y[index] = yuv.y;
-                    buf_u[index] = yuv.u;
-                    buf_v[index] = yuv.v;
+
+        let mut table = alloc::vec::Vec::with_capacity(2 + frames.len() * 6);
+        table.extend_from_slice(&loop_count.to_le_bytes());
+        let mut body = alloc::vec::Vec::new();
+        // The planes of each block in the previous frame, and whether it is stored there as a regular chunk
+        let mut previous = alloc::vec::Vec::<([u8; UNCOMPRESSED_SIZE], bool)>::new();
+        for (index, frame) in frames.iter().enumerate() {
+            let source = PixelSource::new(frame.data, width, height, options)?;
+            let start = body.len();
+            let mut block_index = 0;
+            Self::_encode_blocks(source, width, height, options, |block| {
+                if index == 0 {
+                    body.push(block.chunk.len() as u8);
+                    body.extend_from_slice(&block.chunk);
+                    previous.push((block.planes, true));
+                    return;
+                }
+                let (planes, regular) = &mut previous[block_index];
+                block_index += 1;
+                if *planes == block.planes {
+                    body.push(0);
+                    *regular = false;
+                    return;
+                }
+                let mut delta = Vec::<u8, 128>::new();
+                if *regular {
+                    chunk::compress_delta(&block.planes, planes, &mut delta, options.level());
+                }
+                *regular = delta.is_empty() || delta.len() >= block.chunk.len();
+                if *regular {
+                    body.push(block.chunk.len() as u8);
+                    body.extend_from_slice(&block.chunk);
+                } else {
+                    body.push(chunk::DELTA_FLAG | delta.len() as u8);
+                    body.extend_from_slice(&delta);
+                }
+                *planes = block.planes;
+            });
+            let size = u32::try_from(body.len() - start).map_err(|_| EncodeError::InvalidInput)?;
+            table.extend_from_slice(&frame.duration.to_le_bytes());
+            table.extend_from_slice(&size.to_le_bytes());
+        }
+
+        let mut vec = alloc::vec::Vec::new();
+        let (_, records) = Self::_write_header(
+            frames[0].data,
+            width,
+            height,
+            options,
+            &[Metadata::Animation(&table)],
+            true,
+            &mut |v: &[u8]| vec.extend_from_slice(v),
+        )?;
+        vec.extend_from_slice(&body);
+        records.fill(&mut vec, width, height);
+        Ok(vec)
+    }
+
+    /// Write the file header and the metadata section with the given extra records,
+    /// and return the source of the pixels and the position of the records to fill in.
+    ///
+    /// If `deferred`, the row offset table and the checksums are written as zeros to be filled in by [`DeferredRecords::fill`]
+    /// once the whole file is in memory, instead of encoding the image an extra time to compute them.
+    fn _write_header<'a, F>(
+        data: &'a [u8],
+        width: u32,
+        height: u32,
+        options: &EncoderOptions,
+        extra: &[Metadata],
+        deferred: bool,
+        writer: &mut F,
+    ) -> Result<(PixelSource<'a>, DeferredRecords), EncodeError>
+    where
+        F: FnMut(&[u8]),
+    {
+        let header =
+            FileHeader::new(width, height).ok_or(EncodeError::InvalidSize { width, height })?;
+        if options.row_offsets() && !recovery::fits_row_offsets(width, height) {
+            return Err(EncodeError::InvalidSize { width, height });
+        }
+        // There is no memory to keep the checksums of the rows while the row offset table is written.
+        if cfg!(not(feature = "alloc")) && !deferred && options.row_offsets() && options.checksum()
+        {
+            return Err(EncodeError::RequiresAlloc);
+        }
+        let has_metadata = !options.metadata().is_empty()
+            || options.thumbnail()
+            || options.row_offsets()
+            || options.checksum()
+            || !extra.is_empty();
+        let version = match options.version() {
+            Some(version) => version,
+            None if has_metadata => Version::V2,
+            None => header.version(),
+        };
+        if version < Version::V2 && has_metadata {
+            return Err(EncodeError::UnsupportedVersion(version));
+        }
+        let header = FileHeader::with_version(width, height, version)
+            .ok_or(EncodeError::UnsupportedVersion(version))?;
+        let source = PixelSource::new(data, width, height, options)?;
+        let thumbnail_len = if options.thumbnail() {
+            width.div_ceil(8) * height.div_ceil(8) * 3
+        } else {
+            0
+        };
+        if options.metadata().iter().any(Metadata::is_reserved) {
+            return Err(EncodeError::InvalidMetadata);
+        }
+        let mut section_len = metadata::section_len(options.metadata())?;
+        section_len = section_len
+            .checked_add(metadata::section_len(extra)?)
+            .ok_or(EncodeError::InvalidMetadata)?;
+        if options.thumbnail() {
+            section_len = section_len
+                .checked_add(metadata::RECORD_HEADER_SIZE as u32 + thumbnail_len)
+                .ok_or(EncodeError::InvalidMetadata)?;
+        }
+        let row_offsets_len = recovery::payload_len(height) as u32;
+        if options.row_offsets() {
+            section_len = section_len
+                .checked_add(metadata::RECORD_HEADER_SIZE as u32 + row_offsets_len)
+                .ok_or(EncodeError::InvalidMetadata)?;
+        }
+        let checksum_len = checksum::payload_len(height) as u32;
+        if options.checksum() {
+            section_len = section_len
+                .checked_add(metadata::RECORD_HEADER_SIZE as u32 + checksum_len)
+                .ok_or(EncodeError::InvalidMetadata)?;
+        }
+        // The checksum of the header covers everything written before the checksum record.
+        let crc = Cell::new(Crc32::new());
+        let position = Cell::new(0);
+        let writer = &mut |bytes: &[u8]| {
+            let mut value = crc.get();
+            value.update(bytes);
+            crc.set(value);
+            position.set(position.get() + bytes.len());
+            writer(bytes);
+        };
+        let mut records = DeferredRecords::default();
+        writer(&header.to_bytes());
+        if version >= Version::V2 {
+            writer(&section_len.to_le_bytes());
+            metadata::write_records(options.metadata(), writer);
+            if options.thumbnail() {
+                writer(&Metadata::TAG_THUMBNAIL);
+                writer(&thumbnail_len.to_le_bytes());
+                for y8 in (0..height).step_by(8) {
+                    let h7 = (height - y8).min(8);
+                    for x8 in (0..width).step_by(8) {
+                        let w7 = (width - x8).min(8);
+                        let yuv = source.average(x8, y8, w7, h7);
+                        writer(&[yuv.y, yuv.u, yuv.v]);
+                    }
                 }
-                let block = Self::encode_chunk(&buf_y, &buf_u, &buf_v, level);
-                writer(&[block.len() as u8]);
-                writer(block.as_slice());
             }
-            if w7 > 0 {
-                let block = Self::_encode_edge(data, width, w8, y8, w7, 8, level);
-                writer(&[block.len() as u8]);
-                writer(block.as_slice());
+            metadata::write_records(extra, writer);
+            // When both records are written, the checksums of the rows are kept from the pass for the row offsets.
+            #[cfg(feature = "alloc")]
+            let mut row_crcs = alloc::vec::Vec::new();
+            #[cfg(not(feature = "alloc"))]
+            let row_crcs: [u32; 0] = [];
+            if options.row_offsets() && deferred {
+                records.row_offsets = Some(position.get());
+                writer(&Metadata::TAG_ROW_OFFSETS);
+                writer(&row_offsets_len.to_le_bytes());
+                for _ in 0..row_offsets_len / 4 {
+                    writer(&[0; 4]);
+                }
+            } else if options.row_offsets() {
+                writer(&Metadata::TAG_ROW_OFFSETS);
+                writer(&row_offsets_len.to_le_bytes());
+                Self::_scan_rows(source, width, height, options, |offset, row_crc| {
+                    writer(&offset.to_le_bytes());
+                    #[cfg(feature = "alloc")]
+                    if options.checksum() {
+                        row_crcs.push(row_crc);
+                    }
+                    #[cfg(not(feature = "alloc"))]
+                    let _ = row_crc;
+                });
+            }
+            if options.checksum() && deferred {
+                records.checksum = Some(position.get());
+                writer(&Metadata::TAG_CHECKSUM);
+                writer(&checksum_len.to_le_bytes());
+                for _ in 0..checksum_len / 4 {
+                    writer(&[0; 4]);
+                }
+            } else if options.checksum() {
+                let header_crc = crc.get().finish();
+                writer(&Metadata::TAG_CHECKSUM);
+                writer(&checksum_len.to_le_bytes());
+                writer(&header_crc.to_le_bytes());
+                if row_crcs.is_empty() {
+                    Self::_scan_rows(source, width, height, options, |_, row_crc| {
+                        writer(&row_crc.to_le_bytes());
+                    });
+                }
+                for row_crc in row_crcs {
+                    writer(&row_crc.to_le_bytes());
+                }
             }
         }
-        if h7 > 0 {
-            for x8 in (0..w8).step_by(8) {
-                let block = Self::_encode_edge(data, width, x8, h8, 8, h7, level);
-                writer(&[block.len() as u8]);
-                writer(block.as_slice());
+        records.data_offset = position.get();
+        Ok((source, records))
+    }
+
+    /// Encode all blocks in order to compute the row offset table and the checksums before the image data is written,
+    /// calling `f` with the offset of the first chunk of each row of blocks from the start of the image data
+    /// and the CRC-32 of the chunks of the row.
+    fn _scan_rows<F>(
+        source: PixelSource,
+        width: u32,
+        height: u32,
+        options: &EncoderOptions,
+        mut f: F,
+    ) where
+        F: FnMut(u32, u32),
+    {
+        let cols = width.div_ceil(8) as usize;
+        let mut offset = 0u32;
+        let mut row_offset = 0;
+        let mut row_crc = Crc32::new();
+        let mut col = 0;
+        Self::_encode_blocks(source, width, height, options, |block| {
+            if col == 0 {
+                row_offset = offset;
             }
-            if w7 > 0 {
-                let block = Self::_encode_edge(data, width, w8, h8, w7, h7, level);
-                writer(&[block.len() as u8]);
-                writer(block.as_slice());
+            offset = offset.saturating_add(1 + block.chunk.len() as u32);
+            row_crc.update(&[block.chunk.len() as u8]);
+            row_crc.update(&block.chunk);
+            col += 1;
+            if col == cols {
+                f(row_offset, row_crc.finish());
+                row_crc = Crc32::new();
+                col = 0;
             }
-        }
+        });
+    }
 
-        Ok(())
+    /// Encode all blocks in order, calling `f` with each block.
+    fn _encode_blocks<F>(
+        source: PixelSource,
+        width: u32,
+        height: u32,
+        options: &EncoderOptions,
+        mut f: F,
+    ) where
+        F: FnMut(EncodedBlock),
+    {
+        let mut converter = PixelConverter::new(source, width, options.dither());
+        for y8 in (0..height).step_by(8) {
+            let h7 = (height - y8).min(8);
+            converter.begin_stripe(y8, h7);
+            for x8 in (0..width).step_by(8) {
+                let w7 = (width - x8).min(8);
+                f(Self::_encode_block(&converter, x8, y8, w7, h7, options));
+            }
+        }
     }
 
     #[inline]
-    fn _encode_edge(
-        data: &[u8],
-        width: u32,
+    fn _encode_block(
+        converter: &PixelConverter,
         x8: u32,
         y8: u32,
         w7: u32,
         h7: u32,
-        level: CompressionLevel,
-    ) -> Vec<u8, 128> {
+        options: &EncoderOptions,
+    ) -> EncodedBlock {
+        let x8 = x8 as usize;
+        let y8 = y8 as usize;
         let w7 = w7 as usize;
         let h7 = h7 as usize;
         assert!(w7 > 0 && w7 <= 8);
         assert!(h7 > 0 && h7 <= 8);
 
-        let w1 = w7 & 1;
-        let h1 = h7 & 1;
-
         let mut buf_y = [0; 64];
         let mut buf_u = [0; 64];
         let mut buf_v = [0; 64];
         for y7 in 0..h7 {
             for x7 in 0..w7 {
                 let index = y7 * 8 + x7;
-                let offset = ((y8 as usize + y7) * width as usize + x8 as usize + x7) * 3;
-                let r = data[offset + 0];
-                let g = data[offset + 1];
-                let b = data[offset + 2];
-                let yuv = MpicYuv666::from_rgb(MpicRgb888::new(r, g, b));
+                let yuv = converter.get(x8 + x7, y8 + y7);
                 buf_y[index] = yuv.y;
                 buf_u[index] = yuv.u;
                 buf_v[index] = yuv.v;
             }
-            if w1 > 0 {
-                let index = y7 * 8 + w7;
-                buf_y[index] = buf_y[index - 1];
-                buf_u[index] = buf_u[index - 1];
-                buf_v[index] = buf_v[index - 1];
-            }
-            for x7 in w7 + w1..8 {
-                let index = y7 * 8 + x7;
-                buf_y[index] = buf_y[0];
-                buf_u[index] = buf_u[0];
-                buf_v[index] = buf_v[0];
-            }
         }
-        if h1 > 0 {
-            for x7 in 0..8 {
-                let index_l = h7 * 8 + x7;
-                let index_r = index_l - 8;
-                buf_y[index_l] = buf_y[index_r];
-                buf_u[index_l] = buf_u[index_r];
-                buf_v[index_l] = buf_v[index_r];
-            }
-        }
-        for y7 in h7 + h1..8 {
-            for x7 in 0..8 {
-                let index = y7 * 8 + x7;
-                buf_y[index] = buf_y[0];
-                buf_u[index] = buf_u[0];
-                buf_v[index] = buf_v[0];
-            }
+        if w7 == 8 && h7 == 8 {
+            return Self::_encode_chunk(&buf_y, &buf_u, &buf_v, options);
         }
 
-        Self::encode_chunk(&buf_y, &buf_u, &buf_v, level)
+        let encode_padded = |padding| {
+            let mut buf_y = buf_y;
+            let mut buf_u = buf_u;
+            let mut buf_v = buf_v;
+            pad_edge(padding, &mut buf_y, w7, h7);
+            pad_edge(padding, &mut buf_u, w7, h7);
+            pad_edge(padding, &mut buf_v, w7, h7);
+            Self::_encode_chunk(&buf_y, &buf_u, &buf_v, options)
+        };
+
+        match options.edge_padding() {
+            EdgePadding::Smallest => [
+                EdgePadding::Replicate,
+                EdgePadding::Mirror,
+                EdgePadding::Flat,
+            ]
+            .into_iter()
+            .map(encode_padded)
+            .min_by_key(|block| block.chunk.len())
+            .unwrap(),
+            padding => encode_padded(padding),
+        }
     }
 
     /// Encode a single chunk of YUV data to MPIC format. (intend for internal use)
+    #[inline]
     pub fn encode_chunk(
         buf_y: &[u8; 64],
         buf_u: &[u8; 64],
         buf_v: &[u8; 64],
         level: CompressionLevel,
     ) -> Vec<u8, 128> {
+        Self::_encode_chunk(
+            buf_y,
+            buf_u,
+            buf_v,
+            &EncoderOptions::new().with_level(level),
+        )
+        .chunk
+    }
+
+    fn _encode_chunk(
+        buf_y: &[u8; 64],
+        buf_u: &[u8; 64],
+        buf_v: &[u8; 64],
+        options: &EncoderOptions,
+    ) -> EncodedBlock {
         let mut buf = [0; UNCOMPRESSED_SIZE];
-        for i in 0..64 {
-            buf[i] = buf_y[i];
-        }
+        buf[0..64].copy_from_slice(buf_y);
 
-        let (buf_u, buf_v) = mosaic_uv(buf_u, buf_v);
+        let (buf_u, buf_v) = mosaic_uv_with(options.chroma_filter(), buf_y, buf_u, buf_v);
 
-        for i in 0..16 {
-            buf[64 + i] = buf_u[i];
-        }
-        for i in 0..16 {
-            buf[80 + i] = buf_v[i];
+        buf[64..80].copy_from_slice(&buf_u);
+        buf[80..96].copy_from_slice(&buf_v);
+
+        let step = options.quantization_step();
+        if step > 1 {
+            // Y is quantized around black and U and V around the neutral value, so that gray stays gray.
+            for value in buf[0..64].iter_mut() {
+                *value = quantize_step(*value, step, 4);
+            }
+            for value in buf[64..96].iter_mut() {
+                *value = quantize_step(*value, step, 32);
+            }
         }
 
         let mut vec = Vec::<u8, 128>::new();
-        chunk::compress(&buf, &mut vec, level);
+        chunk::compress(&buf, &mut vec, options.level());
 
         #[cfg(test)]
         {
             let mut unpacked = Vec::<u8, UNCOMPRESSED_SIZE>::new();
             let result = chunk::decompress(vec.as_slice(), &mut unpacked);
-            if result.is_none() || unpacked.as_slice() != buf.as_slice() {
+            if result.is_err() || unpacked.as_slice() != buf.as_slice() {
                 panic!(
                     "DECODE FAILED.\nEXPECTED:\n{:02x?}\nPACKED:\n{:02x?}\nUNPACKED:\n{:02x?}\n",
                     buf.as_slice(),
@@ -227,34 +891,393 @@ impl Encoder {
             }
         }
 
-        vec
+        EncodedBlock {
+            planes: buf,
+            chunk: vec,
+        }
+    }
+}
+
+/// Byte offsets of the row offset table and the checksums written as zeros, to be filled in once the whole file is in memory.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DeferredRecords {
+    /// Offset of the `rofs` record, if any
+    pub row_offsets: Option<usize>,
+    /// Offset of the `crc ` record, if any
+    pub checksum: Option<usize>,
+    /// Offset of the image data
+    pub data_offset: usize,
+}
+
+impl DeferredRecords {
+    /// Fill in the records from the chunks of the first frame in `output`.
+    #[cfg(feature = "alloc")]
+    pub fn fill(&self, output: &mut [u8], width: u32, height: u32) {
+        if let Some(record) = self.row_offsets {
+            recovery::write_row_offsets(output, record, self.data_offset, width, height);
+        }
+        if let Some(record) = self.checksum {
+            checksum::write_checksums(output, record, self.data_offset, width, height);
+        }
+    }
+}
+
+/// A block encoded as a regular chunk.
+struct EncodedBlock {
+    /// Y, U and V planes as stored in the chunk
+    #[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+    planes: [u8; UNCOMPRESSED_SIZE],
+    /// Compressed data, excluding the size
+    chunk: Vec<u8, 128>,
+}
+
+/// Input data with its layout.
+#[derive(Clone, Copy)]
+struct PixelSource<'a> {
+    data: &'a [u8],
+    pixel_format: PixelFormat,
+    stride: usize,
+}
+
+impl<'a> PixelSource<'a> {
+    #[inline]
+    fn new(
+        data: &'a [u8],
+        width: u32,
+        height: u32,
+        options: &EncoderOptions,
+    ) -> Result<Self, EncodeError> {
+        let pixel_format = options.pixel_format();
+        let stride = options.stride(width);
+        let row_size = width as usize * pixel_format.bytes_per_pixel();
+        if stride < row_size {
+            return Err(EncodeError::InvalidStride {
+                stride,
+                min: row_size,
+            });
+        }
+        let required = stride
+            .checked_mul((height as usize).saturating_sub(1))
+            .and_then(|len| len.checked_add(row_size))
+            .ok_or(EncodeError::InvalidStride {
+                stride,
+                min: row_size,
+            })?;
+        if data.len() < required {
+            return Err(EncodeError::InsufficientData {
+                len: data.len(),
+                required,
+            });
+        }
+        Ok(Self {
+            data,
+            pixel_format,
+            stride,
+        })
+    }
+
+    #[inline]
+    fn rgb(&self, x: usize, y: usize) -> MpicRgb888 {
+        let offset = y * self.stride + x * self.pixel_format.bytes_per_pixel();
+        self.pixel_format.read(&self.data[offset..])
+    }
+
+    /// Average color of the visible pixels in the block.
+    fn average(&self, x8: u32, y8: u32, w7: u32, h7: u32) -> MpicYuv666 {
+        let mut sum = [0u32; 3];
+        for y in y8..y8 + h7 {
+            for x in x8..x8 + w7 {
+                let rgb = self.rgb(x as usize, y as usize);
+                sum[0] += rgb.r as u32;
+                sum[1] += rgb.g as u32;
+                sum[2] += rgb.b as u32;
+            }
+        }
+        let count = w7 * h7;
+        let [r, g, b] = sum.map(|v| ((v + count / 2) / count) as u8);
+        MpicYuv666::from_rgb(MpicRgb888::new(r, g, b))
+    }
+}
+
+/// Converts the input pixels to YUV666, applying the dithering.
+struct PixelConverter<'a> {
+    source: PixelSource<'a>,
+    dither: Dither,
+    #[cfg(feature = "alloc")]
+    diffusion: Option<ErrorDiffusion>,
+}
+
+impl<'a> PixelConverter<'a> {
+    #[inline]
+    fn new(source: PixelSource<'a>, width: u32, dither: Dither) -> Self {
+        #[cfg(feature = "alloc")]
+        let width = width as usize;
+        #[cfg(not(feature = "alloc"))]
+        let _ = width;
+        Self {
+            source,
+            dither,
+            #[cfg(feature = "alloc")]
+            diffusion: (dither == Dither::FloydSteinberg).then(|| ErrorDiffusion::new(width)),
+        }
+    }
+
+    /// Prepare the rows from `y8` to `y8 + h7`, which must be called before `get` for each row of blocks.
+    #[inline]
+    fn begin_stripe(&mut self, y8: u32, h7: u32) {
+        #[cfg(feature = "alloc")]
+        if let Some(diffusion) = self.diffusion.as_mut() {
+            let source = self.source;
+            diffusion.process(y8 as usize, h7 as usize, |x, y| source.rgb(x, y));
+        }
+        #[cfg(not(feature = "alloc"))]
+        let _ = (y8, h7);
+    }
+
+    #[inline]
+    fn get(&self, x: usize, y: usize) -> MpicYuv666 {
+        #[cfg(feature = "alloc")]
+        if let Some(diffusion) = self.diffusion.as_ref() {
+            return diffusion.get(x, y);
+        }
+        let rgb = self.source.rgb(x, y);
+        match self.dither {
+            Dither::None => MpicYuv666::from_rgb(rgb),
+            Dither::Ordered | Dither::FloydSteinberg => {
+                let bias = BAYER_8X8[y & 7][x & 7] as i32 * 4 + 2;
+                let [y, u, v] = rgb_to_yuv_fine(rgb).map(|v| quantize_fine(v + bias));
+                MpicYuv666::new(y, u, v)
+            }
+        }
+    }
+}
+
+/// Floyd-Steinberg error diffusion over a stripe of up to 8 rows at a time.
+#[cfg(feature = "alloc")]
+struct ErrorDiffusion {
+    width: usize,
+    /// Errors carried over to the current row, with a margin of 1 pixel on both sides
+    errors: alloc::vec::Vec<[i32; 3]>,
+    /// Errors carried over to the next row, with a margin of 1 pixel on both sides
+    next_errors: alloc::vec::Vec<[i32; 3]>,
+    stripe: alloc::vec::Vec<MpicYuv666>,
+}
+
+#[cfg(feature = "alloc")]
+impl ErrorDiffusion {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            errors: alloc::vec![[0; 3]; width + 2],
+            next_errors: alloc::vec![[0; 3]; width + 2],
+            stripe: alloc::vec![MpicYuv666::new(0, 0, 0); width * 8],
+        }
+    }
+
+    fn process<F>(&mut self, y8: usize, h7: usize, rgb: F)
+    where
+        F: Fn(usize, usize) -> MpicRgb888,
+    {
+        for y7 in 0..h7 {
+            self.next_errors.fill([0; 3]);
+            let mut carry = [0; 3];
+            for x in 0..self.width {
+                let fine = rgb_to_yuv_fine(rgb(x, y8 + y7));
+                let mut yuv = [0; 3];
+                for channel in 0..3 {
+                    let value = fine[channel] + self.errors[x + 1][channel] + carry[channel];
+                    let quantized = quantize_fine(value + 128);
+                    let error = value - ((quantized as i32) << 8);
+                    yuv[channel] = quantized;
+                    carry[channel] = error * 7 / 16;
+                    self.next_errors[x][channel] += error * 3 / 16;
+                    self.next_errors[x + 1][channel] += error * 5 / 16;
+                    self.next_errors[x + 2][channel] += error / 16;
+                }
+                self.stripe[y7 * self.width + x] = MpicYuv666::new(yuv[0], yuv[1], yuv[2]);
+            }
+            core::mem::swap(&mut self.errors, &mut self.next_errors);
+        }
+    }
+
+    #[inline]
+    fn get(&self, x: usize, y: usize) -> MpicYuv666 {
+        self.stripe[(y & 7) * self.width + x]
+    }
+}
+
+/// Round a 6-bit value to the nearest value that differs from `center` by a multiple of `step`.
+#[inline]
+fn quantize_step(value: u8, step: u8, center: u8) -> u8 {
+    let offset = value as i32 - center as i32;
+    let step = step as i32;
+    let offset = (offset.abs() + step / 2) / step * step * offset.signum();
+    (center as i32 + offset).clamp(0, 63) as u8
+}
+
+/// Fill the area outside `w7` x `h7` pixels of the block.
+fn pad_edge(padding: EdgePadding, buf: &mut [u8; 64], w7: usize, h7: usize) {
+    if padding == EdgePadding::Flat {
+        let w1 = w7 & 1;
+        let h1 = h7 & 1;
+        for y7 in 0..h7 {
+            if w1 > 0 {
+                let index = y7 * 8 + w7;
+                buf[index] = buf[index - 1];
+            }
+            for x7 in w7 + w1..8 {
+                buf[y7 * 8 + x7] = buf[0];
+            }
+        }
+        if h1 > 0 {
+            for x7 in 0..8 {
+                let index = h7 * 8 + x7;
+                buf[index] = buf[index - 8];
+            }
+        }
+        for y7 in h7 + h1..8 {
+            for x7 in 0..8 {
+                buf[y7 * 8 + x7] = buf[0];
+            }
+        }
+        return;
+    }
+
+    let source = |v: usize, len: usize| match padding {
+        EdgePadding::Mirror => {
+            let v = v % (len * 2);
+            if v < len { v } else { len * 2 - 1 - v }
+        }
+        _ => v.min(len - 1),
+    };
+    for y7 in 0..8 {
+        for x7 in 0..8 {
+            if x7 < w7 && y7 < h7 {
+                continue;
+            }
+            buf[y7 * 8 + x7] = buf[source(y7, h7) * 8 + source(x7, w7)];
+        }
     }
 }
 
 /// Mosaic the U and V channels.
+#[cfg(test)]
 pub(crate) fn mosaic_uv(buf_u: &[u8; 64], buf_v: &[u8; 64]) -> ([u8; 16], [u8; 16]) {
+    mosaic_uv_with(ChromaFilter::Box, &[0; 64], buf_u, buf_v)
+}
+
+/// Mosaic the U and V channels with the given filter.
+pub(crate) fn mosaic_uv_with(
+    filter: ChromaFilter,
+    buf_y: &[u8; 64],
+    buf_u: &[u8; 64],
+    buf_v: &[u8; 64],
+) -> ([u8; 16], [u8; 16]) {
     let mut out_u = [0u8; 16];
     let mut out_v = [0u8; 16];
 
     for y in 0..4 {
         for x in 0..4 {
             let base = y * 16 + x * 2;
+            let indexes = [base, base + 1, base + 8, base + 9];
+            let ys = indexes.map(|i| buf_y[i]);
+            let us = indexes.map(|i| buf_u[i]);
+            let vs = indexes.map(|i| buf_v[i]);
 
-            let u0 = buf_u[base] as usize;
-            let u1 = buf_u[base + 1] as usize;
-            let u2 = buf_u[base + 8] as usize;
-            let u3 = buf_u[base + 9] as usize;
-
-            let v0 = buf_v[base] as usize;
-            let v1 = buf_v[base + 1] as usize;
-            let v2 = buf_v[base + 8] as usize;
-            let v3 = buf_v[base + 9] as usize;
+            let (u, v) = match filter {
+                ChromaFilter::Box => (
+                    (us.iter().map(|&v| v as usize).sum::<usize>() / 4) as u8,
+                    (vs.iter().map(|&v| v as usize).sum::<usize>() / 4) as u8,
+                ),
+                ChromaFilter::Average => (rounded_average(&us), rounded_average(&vs)),
+                ChromaFilter::LumaWeighted => (
+                    luma_weighted_average(&ys, &us),
+                    luma_weighted_average(&ys, &vs),
+                ),
+                ChromaFilter::Sharp => sharp_uv(&ys, &us, &vs),
+            };
 
             let base = y * 4 + x;
-            out_u[base] = ((u0 + u1 + u2 + u3) / 4) as u8;
-            out_v[base] = ((v0 + v1 + v2 + v3) / 4) as u8;
+            out_u[base] = u;
+            out_v[base] = v;
         }
     }
 
     (out_u, out_v)
 }
+
+#[inline]
+fn rounded_average(values: &[u8; 4]) -> u8 {
+    ((values.iter().map(|&v| v as usize).sum::<usize>() + 2) / 4) as u8
+}
+
+#[inline]
+fn luma_weighted_average(ys: &[u8; 4], values: &[u8; 4]) -> u8 {
+    let mut sum = 0;
+    let mut total_weight = 0;
+    for (&y, &value) in ys.iter().zip(values.iter()) {
+        let weight = y as usize + 1;
+        sum += value as usize * weight;
+        total_weight += weight;
+    }
+    ((sum + total_weight / 2) / total_weight) as u8
+}
+
+/// Search around the average for the pair of U and V that minimizes the RGB error of the 2x2 pixels.
+fn sharp_uv(ys: &[u8; 4], us: &[u8; 4], vs: &[u8; 4]) -> (u8, u8) {
+    const SEARCH_RADIUS: u8 = 4;
+
+    let expected = [0, 1, 2, 3].map(|i| MpicRgb666::from_yuv(MpicYuv666::new(ys[i], us[i], vs[i])));
+    let error = |u: u8, v: u8| -> u32 {
+        ys.iter()
+            .zip(expected.iter())
+            .map(|(&y, expected)| {
+                let rgb = MpicRgb666::from_yuv(MpicYuv666::new(y, u, v));
+                let dr = rgb.r8() as i32 - expected.r8() as i32;
+                let dg = rgb.g8() as i32 - expected.g8() as i32;
+                let db = rgb.b8() as i32 - expected.b8() as i32;
+                (dr * dr + dg * dg + db * db) as u32
+            })
+            .sum()
+    };
+
+    let avg_u = rounded_average(us);
+    let avg_v = rounded_average(vs);
+    let min_u = us
+        .iter()
+        .copied()
+        .min()
+        .unwrap_or(avg_u)
+        .max(avg_u.saturating_sub(SEARCH_RADIUS));
+    let max_u = us
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(avg_u)
+        .min(avg_u + SEARCH_RADIUS);
+    let min_v = vs
+        .iter()
+        .copied()
+        .min()
+        .unwrap_or(avg_v)
+        .max(avg_v.saturating_sub(SEARCH_RADIUS));
+    let max_v = vs
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(avg_v)
+        .min(avg_v + SEARCH_RADIUS);
+
+    let mut best = (avg_u, avg_v);
+    let mut best_error = error(avg_u, avg_v);
+    for u in min_u..=max_u {
+        for v in min_v..=max_v {
+            let error = error(u, v);
+            if error < best_error {
+                best = (u, v);
+                best_error = error;
+            }
+        }
+    }
+    best
+}
@@ -30,7 +30,7 @@
 
 #![cfg_attr(not(test), no_std)]
 
-use core::{mem::size_of, slice};
+use core::fmt;
 #[cfg(feature = "embedded")]
 use embedded_graphics::prelude::Size;
 use heapless::Vec;
@@ -44,8 +44,39 @@ pub use decode::*;
 mod encode;
 pub use encode::*;
 
+mod metadata;
+pub use metadata::*;
+
+mod thumbnail;
+pub use thumbnail::*;
+
+mod animation;
+pub use animation::*;
+
+mod checksum;
+mod recovery;
+
+mod atlas;
+pub use atlas::*;
+
+mod scale;
+pub use scale::*;
+
+mod orientation;
+pub use orientation::*;
+
+#[cfg(feature = "alloc")]
+pub mod transform;
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
 mod chunk;
 pub mod color;
+pub mod crc;
 
 #[path = "lz/lz.rs"]
 pub mod lz;
@@ -62,9 +93,49 @@ pub const PREFERRED_FILE_EXT: &str = "mpic";
 pub enum EncodeError {
     /// Invalid input data, such as incorrect length or invalid format.
     InvalidInput,
+    /// The width or height is zero or larger than 65535,
+    /// or the image is too large for a row offset table.
+    InvalidSize { width: u32, height: u32 },
+    /// The stride is smaller than a row of pixels, or too large to address the whole image.
+    InvalidStride { stride: usize, min: usize },
+    /// The input data is shorter than the image requires.
+    InsufficientData { len: usize, required: usize },
+    /// The requested version cannot represent an image of the given size or the metadata.
+    UnsupportedVersion(Version),
+    /// A metadata record is too large, the key of a custom record is longer than 255 bytes,
+    /// or a record is written by the encoder itself.
+    InvalidMetadata,
+    /// Both a row offset table and checksums are written to a writer function without the `alloc` feature.
+    RequiresAlloc,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::InvalidInput => write!(f, "invalid input"),
+            Self::InvalidSize { width, height } => {
+                write!(f, "invalid image size {width}x{height}")
+            }
+            Self::InvalidStride { stride, min } => {
+                write!(f, "invalid stride {stride} for a row of {min} bytes")
+            }
+            Self::InsufficientData { len, required } => {
+                write!(f, "input is {len} bytes but {required} bytes are required")
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "version {} cannot represent this image", version.0)
+            }
+            Self::InvalidMetadata => write!(f, "invalid metadata"),
+            Self::RequiresAlloc => write!(f, "these options require the alloc feature"),
+        }
+    }
 }
 
+impl core::error::Error for EncodeError {}
+
 /// Errors that can occur during decoding.
+///
+/// Offsets are byte offsets from the start of the file.
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DecodeError {
@@ -72,12 +143,210 @@ pub enum DecodeError {
     InvalidInput,
     /// Data is corrupted or cannot be decoded.
     InvalidData,
+    /// The data is shorter than the file header.
+    TruncatedHeader,
+    /// The file does not start with [`FileHeader::MAGIC`].
+    BadMagic,
+    /// The version in the file header is not supported.
+    UnsupportedVersion(Version),
+    /// The width or height in the file header is invalid for its version.
+    InvalidSize { width: u32, height: u32 },
+    /// The metadata section is truncated, or the record at the given byte offset overruns it.
+    InvalidMetadata { offset: usize },
+    /// The data ends in the middle of the chunk at the given index.
+    ///
+    /// `offset` is the byte offset of the size of the chunk, or of the incomplete token.
+    TruncatedChunk { index: usize, offset: usize },
+    /// The size of the chunk at the given index is not a valid chunk size.
+    ///
+    /// `offset` is the byte offset of the size of the chunk.
+    InvalidChunkLength { index: usize, offset: usize },
+    /// A match refers to data before the start of the block.
+    MatchOutOfRange { index: usize, offset: usize },
+    /// A reserved opcode was found.
+    ReservedOpcode { index: usize, offset: usize },
+    /// The chunk decompresses to more than 96 bytes.
+    OutputOverflow { index: usize, offset: usize },
+    /// The chunk decompresses to less than 96 bytes.
+    ///
+    /// `offset` is the byte offset just past the end of the chunk.
+    OutputUnderflow { index: usize, offset: usize },
+    /// Unexpected bytes remain after the last chunk, starting at the given byte offset.
+    TrailingData { offset: usize },
+    /// The chunks of the animation frame at the given index do not match the size in the frame table.
+    ///
+    /// `offset` is the byte offset of the first chunk of the frame.
+    InvalidFrame { index: usize, offset: usize },
+    /// The delta chunk at the given index refers to a block that is not stored as a regular chunk in the previous frame.
+    ///
+    /// `offset` is the byte offset of the size of the chunk.
+    MissingReference { index: usize, offset: usize },
+    /// Verification was requested, but the file has no [`Metadata::Checksum`] record.
+    MissingChecksum,
+    /// The file header or the metadata before the checksum record does not match its checksum.
+    ///
+    /// `offset` is the byte offset of the checksum record.
+    HeaderChecksumMismatch { offset: usize },
+    /// The chunks of the row of blocks at the given index do not match their checksum.
+    ///
+    /// `offset` is the byte offset of the size of the first chunk of the row.
+    ChecksumMismatch { row: usize, offset: usize },
+}
+
+impl DecodeError {
+    /// Returns the index of the chunk where the error was found, if any.
+    pub const fn chunk_index(&self) -> Option<usize> {
+        match *self {
+            Self::TruncatedChunk { index, .. }
+            | Self::InvalidChunkLength { index, .. }
+            | Self::MatchOutOfRange { index, .. }
+            | Self::ReservedOpcode { index, .. }
+            | Self::OutputOverflow { index, .. }
+            | Self::OutputUnderflow { index, .. }
+            | Self::MissingReference { index, .. } => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the byte offset where the error was found, if any.
+    pub const fn offset(&self) -> Option<usize> {
+        match *self {
+            Self::TruncatedChunk { offset, .. }
+            | Self::InvalidChunkLength { offset, .. }
+            | Self::MatchOutOfRange { offset, .. }
+            | Self::ReservedOpcode { offset, .. }
+            | Self::OutputOverflow { offset, .. }
+            | Self::OutputUnderflow { offset, .. }
+            | Self::InvalidMetadata { offset }
+            | Self::TrailingData { offset }
+            | Self::InvalidFrame { offset, .. }
+            | Self::MissingReference { offset, .. }
+            | Self::HeaderChecksumMismatch { offset }
+            | Self::ChecksumMismatch { offset, .. } => Some(offset),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::InvalidInput => write!(f, "invalid input"),
+            Self::InvalidData => write!(f, "invalid data"),
+            Self::TruncatedHeader => write!(f, "truncated file header"),
+            Self::BadMagic => write!(f, "bad magic number"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported version {}", version.0),
+            Self::InvalidSize { width, height } => {
+                write!(f, "invalid image size {width}x{height}")
+            }
+            Self::InvalidMetadata { offset } => write!(f, "invalid metadata at offset {offset}"),
+            Self::TruncatedChunk { index, offset } => {
+                write!(f, "chunk {index} is truncated at offset {offset}")
+            }
+            Self::InvalidChunkLength { index, offset } => {
+                write!(f, "chunk {index} has an invalid length at offset {offset}")
+            }
+            Self::MatchOutOfRange { index, offset } => {
+                write!(
+                    f,
+                    "chunk {index} has a match out of range at offset {offset}"
+                )
+            }
+            Self::ReservedOpcode { index, offset } => {
+                write!(f, "chunk {index} has a reserved opcode at offset {offset}")
+            }
+            Self::OutputOverflow { index, offset } => {
+                write!(f, "chunk {index} overflows the block at offset {offset}")
+            }
+            Self::OutputUnderflow { index, offset } => {
+                write!(
+                    f,
+                    "chunk {index} ends before filling the block at offset {offset}"
+                )
+            }
+            Self::TrailingData { offset } => write!(f, "trailing data at offset {offset}"),
+            Self::InvalidFrame { index, offset } => {
+                write!(
+                    f,
+                    "frame {index} does not match its size at offset {offset}"
+                )
+            }
+            Self::MissingReference { index, offset } => write!(
+                f,
+                "chunk {index} refers to a missing block of the previous frame at offset {offset}"
+            ),
+            Self::MissingChecksum => write!(f, "no checksum"),
+            Self::HeaderChecksumMismatch { offset } => {
+                write!(f, "header checksum mismatch at offset {offset}")
+            }
+            Self::ChecksumMismatch { row, offset } => {
+                write!(f, "row {row} checksum mismatch at offset {offset}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+/// Errors that can occur while drawing to a draw target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawError<E> {
+    /// The image data cannot be decoded.
+    ///
+    /// The chunk index and the byte offset of the damaged chunk are given by [`DecodeError::chunk_index`] and [`DecodeError::offset`].
+    Decode(DecodeError),
+    /// The draw target returned an error.
+    Target(E),
+}
+
+impl<E> DrawError<E> {
+    /// Returns the result of drawing with the decoding errors discarded, as [`ImageDrawable::draw`] stops silently at them.
+    ///
+    /// [`ImageDrawable::draw`]: embedded_graphics::image::ImageDrawable::draw
+    #[cfg(feature = "embedded")]
+    #[inline]
+    pub(crate) fn ignore_decode(result: Result<(), Self>) -> Result<(), E> {
+        match result {
+            Err(Self::Target(err)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl DrawError<core::convert::Infallible> {
+    /// Returns the decoding error, as nothing else can fail.
+    #[inline]
+    pub(crate) fn into_decode(self) -> DecodeError {
+        match self {
+            Self::Decode(err) => err,
+            Self::Target(never) => match never {},
+        }
+    }
 }
 
+impl<E> From<DecodeError> for DrawError<E> {
+    #[inline]
+    fn from(err: DecodeError) -> Self {
+        Self::Decode(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for DrawError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(err) => err.fmt(f),
+            Self::Target(err) => write!(f, "draw target error: {err}"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> core::error::Error for DrawError<E> {}
+
 /// File header for MPIC format.
-#[repr(C, packed)]
+///
+/// Stored as 9 bytes: the magic number, the width and the height as 16-bit little-endian integers, and the version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FileHeader {
-    magic: [u8; 4],
     width: u16,
     height: u16,
     version: Version,
@@ -93,15 +362,26 @@ pub struct ImageInfo {
 impl ImageInfo {
     /// Return the width of the image in pixels.
     #[inline]
-    pub fn width(&self) -> u32 {
+    pub const fn width(&self) -> u32 {
         self.width as u32
     }
 
     /// Return the height of the image in pixels.
     #[inline]
-    pub fn height(&self) -> u32 {
+    pub const fn height(&self) -> u32 {
         self.height as u32
     }
+
+    /// Return the size of a buffer for the whole image with the given number of elements per pixel.
+    ///
+    /// Fails with [`DecodeError::InvalidInput`] if it does not fit in `usize`, which can happen on 32-bit targets.
+    #[inline]
+    pub(crate) fn buffer_len(&self, per_pixel: usize) -> Result<usize, DecodeError> {
+        (self.width as usize)
+            .checked_mul(self.height as usize)
+            .and_then(|len| len.checked_mul(per_pixel))
+            .ok_or(DecodeError::InvalidInput)
+    }
 }
 
 #[cfg(feature = "embedded")]
@@ -114,7 +394,7 @@ impl From<ImageInfo> for Size {
 
 impl FileHeader {
     /// Minimal valid file size
-    pub const MINIMAL_SIZE: usize = size_of::<Self>();
+    pub const MINIMAL_SIZE: usize = 9;
 
     /// Magic number (`b"\x00mpi"`)
     pub const MAGIC: [u8; 4] = *b"\x00mpi";
@@ -131,46 +411,124 @@ impl FileHeader {
             Version::V1
         };
         Some(Self {
-            magic: Self::MAGIC,
             version,
-            width: (width as u16).to_le(),
-            height: (height as u16).to_le(),
+            width: width as u16,
+            height: height as u16,
         })
     }
 
+    /// Create a new file header with the given width, height and version.
+    ///
+    /// Returns `None` if the version cannot represent an image of the given size.
+    #[inline]
+    pub const fn with_version(width: u32, height: u32, version: Version) -> Option<Self> {
+        let Some(mut header) = Self::new(width, height) else {
+            return None;
+        };
+        if version.0 < header.version.0 || version.0 > Version::CURRENT.0 {
+            return None;
+        }
+        header.version = version;
+        Some(header)
+    }
+
     /// Returns true if the file header is valid, false otherwise.
     #[inline]
-    pub fn is_valid(&self) -> bool {
-        let width = self.width.to_le();
-        let height = self.height.to_le();
-        self.magic == Self::MAGIC
-            && (Version::V0..=Version::CURRENT).contains(&self.version)
-            && width > 0
-            && height > 0
+    pub const fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Checks the file header and reports why it is invalid.
+    ///
+    /// A header made by [`FileHeader::new`] or [`FileHeader::with_version`] is always valid.
+    /// Like the previous versions, this accepts a version `0` header whose size is not a multiple of 8,
+    /// which only [`Decoder::validate`](crate::Decoder::validate) rejects.
+    pub const fn validate(&self) -> Result<(), DecodeError> {
+        let (width, height, version) = (self.width, self.height, self.version);
+        if version.0 > Version::CURRENT.0 {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        if width == 0 || height == 0 {
+            return Err(DecodeError::InvalidSize {
+                width: width as u32,
+                height: height as u32,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the size of a version `0` header is a multiple of 8.
+    pub(crate) const fn validate_alignment(&self) -> Result<(), DecodeError> {
+        if self.version.0 == Version::V0.0 && ((self.width | self.height) & 7) != 0 {
+            return Err(DecodeError::InvalidSize {
+                width: self.width as u32,
+                height: self.height as u32,
+            });
+        }
+        Ok(())
+    }
+
+    /// Parses the file header at the start of a byte slice and reports why it is invalid.
+    pub const fn parse(blob: &[u8]) -> Result<Self, DecodeError> {
+        let Some((bytes, _)) = blob.split_first_chunk::<{ Self::MINIMAL_SIZE }>() else {
+            return Err(DecodeError::TruncatedHeader);
+        };
+        let [m0, m1, m2, m3, w0, w1, h0, h1, version] = *bytes;
+        if u32::from_le_bytes([m0, m1, m2, m3]) != u32::from_le_bytes(Self::MAGIC) {
+            return Err(DecodeError::BadMagic);
+        }
+        let header = Self {
+            width: u16::from_le_bytes([w0, w1]),
+            height: u16::from_le_bytes([h0, h1]),
+            version: Version(version),
+        };
+        match header.validate() {
+            Ok(()) => Ok(header),
+            Err(err) => Err(err),
+        }
     }
 
     /// Create a file header from a byte slice.
     #[inline]
-    pub fn from_bytes<'a>(blob: &'a [u8]) -> Option<&'a Self> {
-        if blob.len() < Self::MINIMAL_SIZE {
-            return None;
+    pub const fn from_bytes(blob: &[u8]) -> Option<Self> {
+        match Self::parse(blob) {
+            Ok(header) => Some(header),
+            Err(_) => None,
         }
-        let header = unsafe { &*(blob.as_ptr() as *const FileHeader) };
-        header.is_valid().then(|| header)
     }
 
-    /// Return the raw bytes of the file header.
+    /// Write the file header to a buffer in the byte order of the file.
+    #[inline]
+    pub const fn write_to(&self, buf: &mut [u8; Self::MINIMAL_SIZE]) {
+        let [m0, m1, m2, m3] = Self::MAGIC;
+        let [w0, w1] = self.width.to_le_bytes();
+        let [h0, h1] = self.height.to_le_bytes();
+        *buf = [m0, m1, m2, m3, w0, w1, h0, h1, self.version.0];
+    }
+
+    /// Return the file header as bytes in the byte order of the file.
+    #[inline]
+    pub const fn to_bytes(&self) -> [u8; Self::MINIMAL_SIZE] {
+        let mut buf = [0; Self::MINIMAL_SIZE];
+        self.write_to(&mut buf);
+        buf
+    }
+
+    /// Same as [`Self::to_bytes`].
+    #[deprecated(
+        note = "Use `to_bytes` instead. The file header is no longer stored in the byte order of the file, so the bytes are returned by value."
+    )]
     #[inline]
-    pub fn bytes<'a>(&'a self) -> &'a [u8] {
-        unsafe { slice::from_raw_parts(self as *const _ as *const u8, size_of::<Self>()) }
+    pub const fn bytes(&self) -> [u8; Self::MINIMAL_SIZE] {
+        self.to_bytes()
     }
 
     /// Return the image information from the file header.
     #[inline]
-    pub fn info(&self) -> ImageInfo {
+    pub const fn info(&self) -> ImageInfo {
         ImageInfo {
-            width: self.width.to_le(),
-            height: self.height.to_le(),
+            width: self.width,
+            height: self.height,
         }
     }
 
@@ -188,10 +546,14 @@ pub struct Version(pub u8);
 
 impl Version {
     /// Current version
-    pub const CURRENT: Self = Self::V1;
+    pub const CURRENT: Self = Self::V2;
 
     /// Version 0: Only supports images with width and height that are multiples of 8.
     pub const V0: Self = Self(0);
-    /// Version 1: Current version
+    /// Version 1: Supports images of any size.
     pub const V1: Self = Self(1);
+    /// Version 2: Adds the metadata section between the file header and the image data.
+    ///
+    /// Decoders that only support version 1 reject these files, so it is only written when the file has metadata.
+    pub const V2: Self = Self(2);
 }
@@ -71,13 +71,13 @@ impl LazyMatchSolver {
                     LazyLzssItem::Literal(_) => None,
                     LazyLzssItem::Match(m) => Some(m),
                 });
-                if let Some(next_matches) = next_matches {
-                    if next_matches.weight() > matches.weight() {
-                        emitter(LZSS::Literal(matches.literal()))?;
-                        emitter(LZSS::Match(next_matches.matches()))?;
-                        current += 1 + next_matches.matches().len.get();
-                        continue;
-                    }
+                if let Some(next_matches) = next_matches
+                    && next_matches.weight() > matches.weight()
+                {
+                    emitter(LZSS::Literal(matches.literal()))?;
+                    emitter(LZSS::Match(next_matches.matches()))?;
+                    current += 1 + next_matches.matches().len.get();
+                    continue;
                 }
                 emitter(LZSS::Match(matches.matches()))?;
                 current += matches.matches().len.get();
@@ -207,6 +207,13 @@ impl LazyMatchSolver {
     }
 }
 
+impl Default for LazyMatchSolver {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Represents an item in the lazy matching queue, which can be either a literal byte or a match with its associated weight.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LazyLzssItem {
@@ -1,5 +1,6 @@
 //! Lempel-Ziv (LZ) compression algorithm supports
 use core::num::NonZero;
+#[cfg(feature = "alloc")]
 pub mod lazy;
 
 /// Computes the length of the longest match between `src[lhs..]` and `src[rhs..]`, up to `max_len`.
@@ -66,6 +67,7 @@ pub enum BestMatch {
     Found(Match),
 }
 
+#[allow(clippy::len_without_is_empty)]
 impl BestMatch {
     /// Create a new `BestMatch` from the given length and distance, which may be zero (no match found).
     #[inline]
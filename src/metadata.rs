@@ -0,0 +1,232 @@
+use crate::*;
+
+/// Metadata record stored between the file header and the image data.
+///
+/// Metadata requires [`Version::V2`] or later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metadata<'a> {
+    /// Title of the image. (`b"titl"`)
+    Title(&'a str),
+    /// Author of the image. (`b"auth"`)
+    Author(&'a str),
+    /// ICC color profile. (`b"iccp"`)
+    IccProfile(&'a [u8]),
+    /// Application-defined data identified by a key of up to 255 bytes. (`b"kval"`)
+    Custom { key: &'a str, value: &'a [u8] },
+    /// Preview image with one YUV666 triplet per block, in the same order as the chunks. (`b"thmb"`)
+    ///
+    /// See [`EncoderOptions::with_thumbnail`] and [`Decoder::thumbnail`].
+    Thumbnail(&'a [u8]),
+    /// Loop count and frame table of an animation. (`b"anim"`)
+    ///
+    /// See [`Encoder::encode_animation`] and [`Decoder::animation`].
+    Animation(&'a [u8]),
+    /// Byte offset of the first chunk of each row of blocks from the start of the image data. (`b"rofs"`)
+    ///
+    /// See [`EncoderOptions::with_row_offsets`] and [`Decoder::with_lenient`].
+    RowOffsets(&'a [u8]),
+    /// CRC-32 of the file before this record, followed by the CRC-32 of the chunks of each row of blocks. (`b"crc "`)
+    ///
+    /// See [`EncoderOptions::with_checksum`] and [`Decoder::verify`].
+    Checksum(&'a [u8]),
+    /// Any other record, or a known record with a malformed payload.
+    Other { tag: [u8; 4], data: &'a [u8] },
+}
+
+impl<'a> Metadata<'a> {
+    /// Tag of [`Metadata::Title`]
+    pub const TAG_TITLE: [u8; 4] = *b"titl";
+    /// Tag of [`Metadata::Author`]
+    pub const TAG_AUTHOR: [u8; 4] = *b"auth";
+    /// Tag of [`Metadata::IccProfile`]
+    pub const TAG_ICC_PROFILE: [u8; 4] = *b"iccp";
+    /// Tag of [`Metadata::Custom`]
+    pub const TAG_CUSTOM: [u8; 4] = *b"kval";
+    /// Tag of [`Metadata::Thumbnail`]
+    pub const TAG_THUMBNAIL: [u8; 4] = *b"thmb";
+    /// Tag of [`Metadata::Animation`]
+    pub const TAG_ANIMATION: [u8; 4] = *b"anim";
+    /// Tag of [`Metadata::RowOffsets`]
+    pub const TAG_ROW_OFFSETS: [u8; 4] = *b"rofs";
+    /// Tag of [`Metadata::Checksum`]
+    pub const TAG_CHECKSUM: [u8; 4] = *b"crc ";
+
+    /// Parse a record from its tag and payload.
+    pub fn from_raw(tag: [u8; 4], data: &'a [u8]) -> Self {
+        let other = Self::Other { tag, data };
+        match tag {
+            Self::TAG_TITLE => core::str::from_utf8(data).map_or(other, Self::Title),
+            Self::TAG_AUTHOR => core::str::from_utf8(data).map_or(other, Self::Author),
+            Self::TAG_ICC_PROFILE => Self::IccProfile(data),
+            Self::TAG_CUSTOM => {
+                let Some((&key_len, rest)) = data.split_first() else {
+                    return other;
+                };
+                let Some((key, value)) = rest.split_at_checked(key_len as usize) else {
+                    return other;
+                };
+                core::str::from_utf8(key).map_or(other, |key| Self::Custom { key, value })
+            }
+            Self::TAG_THUMBNAIL => Self::Thumbnail(data),
+            Self::TAG_ANIMATION => Self::Animation(data),
+            Self::TAG_ROW_OFFSETS => Self::RowOffsets(data),
+            Self::TAG_CHECKSUM => Self::Checksum(data),
+            _ => other,
+        }
+    }
+
+    /// Return the tag of the record.
+    #[inline]
+    pub const fn tag(&self) -> [u8; 4] {
+        match self {
+            Self::Title(_) => Self::TAG_TITLE,
+            Self::Author(_) => Self::TAG_AUTHOR,
+            Self::IccProfile(_) => Self::TAG_ICC_PROFILE,
+            Self::Custom { .. } => Self::TAG_CUSTOM,
+            Self::Thumbnail(_) => Self::TAG_THUMBNAIL,
+            Self::Animation(_) => Self::TAG_ANIMATION,
+            Self::RowOffsets(_) => Self::TAG_ROW_OFFSETS,
+            Self::Checksum(_) => Self::TAG_CHECKSUM,
+            Self::Other { tag, .. } => *tag,
+        }
+    }
+
+    /// Returns true if the record is written by the encoder itself, and cannot be given in [`EncoderOptions::with_metadata`].
+    ///
+    /// This is checked by tag, so [`Metadata::Other`] with one of these tags is reserved as well.
+    #[inline]
+    pub const fn is_reserved(&self) -> bool {
+        matches!(
+            self.tag(),
+            Self::TAG_THUMBNAIL | Self::TAG_ANIMATION | Self::TAG_ROW_OFFSETS | Self::TAG_CHECKSUM
+        )
+    }
+
+    /// Size of the payload, excluding the tag and the size.
+    #[inline]
+    fn payload_len(&self) -> usize {
+        match self {
+            Self::Title(s) | Self::Author(s) => s.len(),
+            Self::IccProfile(data)
+            | Self::Thumbnail(data)
+            | Self::Animation(data)
+            | Self::RowOffsets(data)
+            | Self::Checksum(data)
+            | Self::Other { data, .. } => data.len(),
+            Self::Custom { key, value } => 1 + key.len() + value.len(),
+        }
+    }
+
+    #[inline]
+    fn write_payload<F>(&self, writer: &mut F)
+    where
+        F: FnMut(&[u8]),
+    {
+        match self {
+            Self::Title(s) | Self::Author(s) => writer(s.as_bytes()),
+            Self::IccProfile(data)
+            | Self::Thumbnail(data)
+            | Self::Animation(data)
+            | Self::RowOffsets(data)
+            | Self::Checksum(data)
+            | Self::Other { data, .. } => writer(data),
+            Self::Custom { key, value } => {
+                writer(&[key.len() as u8]);
+                writer(key.as_bytes());
+                writer(value);
+            }
+        }
+    }
+}
+
+/// Size of the tag and the size of a record
+pub(crate) const RECORD_HEADER_SIZE: usize = 8;
+/// Size of the size of the metadata section
+pub(crate) const SECTION_HEADER_SIZE: usize = 4;
+
+/// Return the size of the metadata section that holds the given records, excluding its own size.
+pub(crate) fn section_len(records: &[Metadata]) -> Result<u32, EncodeError> {
+    let mut total = 0u32;
+    for record in records {
+        if let Metadata::Custom { key, .. } = record
+            && key.len() > u8::MAX as usize
+        {
+            return Err(EncodeError::InvalidMetadata);
+        }
+        total = u32::try_from(record.payload_len())
+            .ok()
+            .and_then(|len| total.checked_add(len))
+            .and_then(|total| total.checked_add(RECORD_HEADER_SIZE as u32))
+            .ok_or(EncodeError::InvalidMetadata)?;
+    }
+    Ok(total)
+}
+
+/// Write the given records, without the size of the section.
+pub(crate) fn write_records<F>(records: &[Metadata], writer: &mut F)
+where
+    F: FnMut(&[u8]),
+{
+    for record in records {
+        writer(&record.tag());
+        writer(&(record.payload_len() as u32).to_le_bytes());
+        record.write_payload(writer);
+    }
+}
+
+/// Locate the metadata section after the file header.
+///
+/// Returns the records and the byte offset of the image data.
+pub(crate) fn read_section(blob: &[u8], version: Version) -> Result<(&[u8], usize), DecodeError> {
+    let start = FileHeader::MINIMAL_SIZE;
+    if version < Version::V2 {
+        return Ok((&[], start));
+    }
+    let invalid = |offset| DecodeError::InvalidMetadata { offset };
+    let len = blob
+        .get(start..start + SECTION_HEADER_SIZE)
+        .ok_or(invalid(start))?;
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    let records_start = start + SECTION_HEADER_SIZE;
+    let records = records_start
+        .checked_add(len)
+        .and_then(|end| blob.get(records_start..end))
+        .ok_or(invalid(start))?;
+
+    let mut cursor = 0;
+    while cursor < records.len() {
+        let record_len = records
+            .get(cursor + 4..cursor + RECORD_HEADER_SIZE)
+            .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+            .filter(|&len| len <= records.len() - cursor - RECORD_HEADER_SIZE)
+            .ok_or(invalid(records_start + cursor))?;
+        cursor += RECORD_HEADER_SIZE + record_len;
+    }
+    Ok((records, records_start + len))
+}
+
+/// Iterator over the metadata records.
+#[derive(Debug, Clone)]
+pub struct MetadataIter<'a> {
+    records: &'a [u8],
+}
+
+impl<'a> MetadataIter<'a> {
+    #[inline]
+    pub(crate) const fn new(records: &'a [u8]) -> Self {
+        Self { records }
+    }
+}
+
+impl<'a> Iterator for MetadataIter<'a> {
+    type Item = Metadata<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (header, rest) = self.records.split_at_checked(RECORD_HEADER_SIZE)?;
+        let tag = header[..4].try_into().unwrap();
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let (data, rest) = rest.split_at_checked(len)?;
+        self.records = rest;
+        Some(Metadata::from_raw(tag, data))
+    }
+}
@@ -0,0 +1,293 @@
+#[cfg(feature = "embedded")]
+use crate::{color::*, *};
+#[cfg(feature = "embedded")]
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Clockwise rotation of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    /// No rotation.
+    #[default]
+    Deg0,
+    /// 90 degrees clockwise.
+    Deg90,
+    /// 180 degrees.
+    Deg180,
+    /// 270 degrees clockwise, or 90 degrees counterclockwise.
+    Deg270,
+}
+
+impl Rotation {
+    /// Returns true if the rotation swaps the width and height.
+    #[inline]
+    pub const fn swaps_axes(&self) -> bool {
+        matches!(self, Self::Deg90 | Self::Deg270)
+    }
+}
+
+/// Flips followed by a rotation, mapping the pixel positions of an image of the given size.
+#[cfg(any(feature = "embedded", feature = "alloc"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Orientation {
+    pub rotation: Rotation,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+#[cfg(any(feature = "embedded", feature = "alloc"))]
+impl Orientation {
+    /// Size of the image after the transformation.
+    #[inline]
+    pub const fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.rotation.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Position after the transformation of the pixel at the given position of the source image.
+    #[inline]
+    pub const fn map(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let x = if self.flip_horizontal {
+            width - 1 - x
+        } else {
+            x
+        };
+        let y = if self.flip_vertical {
+            height - 1 - y
+        } else {
+            y
+        };
+        match self.rotation {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (height - 1 - y, x),
+            Rotation::Deg180 => (width - 1 - x, height - 1 - y),
+            Rotation::Deg270 => (y, width - 1 - x),
+        }
+    }
+
+    /// Position in the source image of the pixel at the given position after the transformation.
+    #[inline]
+    pub const fn unmap(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let (x, y) = match self.rotation {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (y, height - 1 - x),
+            Rotation::Deg180 => (width - 1 - x, height - 1 - y),
+            Rotation::Deg270 => (width - 1 - y, x),
+        };
+        let x = if self.flip_horizontal {
+            width - 1 - x
+        } else {
+            x
+        };
+        let y = if self.flip_vertical {
+            height - 1 - y
+        } else {
+            y
+        };
+        (x, y)
+    }
+}
+
+/// A decoder that draws the image flipped, rotated and enlarged by an integer factor.
+///
+/// The flips are applied first, then the rotation, and then the enlargement.
+/// Each block is transformed as it is decoded, so no buffer for the whole image is needed.
+#[cfg(feature = "embedded")]
+pub struct TransformedDecoder<'a, 'b, T> {
+    decoder: &'b Decoder<'a, T>,
+    orientation: Orientation,
+    zoom: u32,
+}
+
+#[cfg(feature = "embedded")]
+impl<'a, T> Decoder<'a, T> {
+    /// Return a wrapper that draws this image transformed.
+    ///
+    /// The wrapper draws the image as it is until the transformation is set.
+    #[inline]
+    pub fn transformed(&self) -> TransformedDecoder<'a, '_, T> {
+        TransformedDecoder {
+            decoder: self,
+            orientation: Orientation {
+                rotation: Rotation::Deg0,
+                flip_horizontal: false,
+                flip_vertical: false,
+            },
+            zoom: 1,
+        }
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> TransformedDecoder<'_, '_, T> {
+    /// Set the clockwise rotation.
+    #[inline]
+    pub const fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.orientation.rotation = rotation;
+        self
+    }
+
+    /// Set whether to flip the image horizontally before the rotation.
+    #[inline]
+    pub const fn with_flip_horizontal(mut self, flip: bool) -> Self {
+        self.orientation.flip_horizontal = flip;
+        self
+    }
+
+    /// Set whether to flip the image vertically before the rotation.
+    #[inline]
+    pub const fn with_flip_vertical(mut self, flip: bool) -> Self {
+        self.orientation.flip_vertical = flip;
+        self
+    }
+
+    /// Set the factor to enlarge the image with nearest-neighbor interpolation.
+    ///
+    /// Zero is treated as 1, and the factor is limited so that the enlarged size fits in `i32`.
+    #[inline]
+    pub const fn with_zoom(mut self, zoom: u32) -> Self {
+        let info = self.decoder.info();
+        let side = if info.width() > info.height() {
+            info.width()
+        } else {
+            info.height()
+        };
+        let max_zoom = i32::MAX as u32 / if side > 0 { side } else { 1 };
+        self.zoom = if zoom == 0 {
+            1
+        } else if zoom > max_zoom {
+            max_zoom
+        } else {
+            zoom
+        };
+        self
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> OriginDimensions for TransformedDecoder<'_, '_, T> {
+    #[inline]
+    fn size(&self) -> Size {
+        let info = self.decoder.info();
+        let (width, height) = self.orientation.size(info.width(), info.height());
+        Size::new(
+            width.saturating_mul(self.zoom),
+            height.saturating_mul(self.zoom),
+        )
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> TransformedDecoder<'_, '_, T> {
+    /// Draw the image to the target like [`ImageDrawable::draw`], but report the errors in the image data.
+    ///
+    /// Returns [`DrawError::Decode`] with the index and the byte offset of the first chunk that cannot be decoded.
+    pub fn try_draw<D>(&self, target: &mut D) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        let info = self.decoder.info();
+        let (width, height) = (info.width(), info.height());
+        let orientation = self.orientation;
+        let zoom = self.zoom;
+
+        // The area of the source image that covers the target.
+        let visible = target.bounding_box().intersection(&self.bounding_box());
+        let Some(bottom_right) = visible.bottom_right() else {
+            return Ok(());
+        };
+        let top_left = visible.top_left;
+        let (x0, y0) = orientation.unmap(
+            top_left.x as u32 / zoom,
+            top_left.y as u32 / zoom,
+            width,
+            height,
+        );
+        let (x1, y1) = orientation.unmap(
+            bottom_right.x as u32 / zoom,
+            bottom_right.y as u32 / zoom,
+            width,
+            height,
+        );
+        let (left, right) = (x0.min(x1), x0.max(x1));
+        let (top, bottom) = (y0.min(y1), y0.max(y1));
+
+        self.decoder.decode_sub_image(
+            left as i32,
+            top as i32,
+            right - left + 1,
+            bottom - top + 1,
+            |x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+                let mut colors = heapless::Vec::<T, 64>::new();
+                for index in 0..64 {
+                    let rgb =
+                        MpicRgb666::from(MpicYuv666::new(buf_y[index], buf_u[index], buf_v[index]));
+                    let _ = colors.push(rgb.into());
+                }
+
+                let (ax, ay) = orientation.map(x8, y8, width, height);
+                let (bx, by) = orientation.map(x8 + w7 - 1, y8 + h7 - 1, width, height);
+                let (dx, dy) = (ax.min(bx), ay.min(by));
+                let (dw, dh) = orientation.size(w7, h7);
+                // Only the visible part of the enlarged block is filled, which can be much smaller at a large zoom.
+                let area = Rectangle::new(
+                    Point::new((dx * zoom) as i32, (dy * zoom) as i32),
+                    Size::new(dw * zoom, dh * zoom),
+                )
+                .intersection(&visible);
+                let (left, top) = (area.top_left.x as u32, area.top_left.y as u32);
+                target.fill_contiguous(
+                    &area,
+                    (0..area.size.height).flat_map(|ry| {
+                        let colors = &colors;
+                        (0..area.size.width).map(move |rx| {
+                            let (x, y) = orientation.unmap(
+                                (left + rx) / zoom,
+                                (top + ry) / zoom,
+                                width,
+                                height,
+                            );
+                            colors[((y - y8) * 8 + (x - x8)) as usize]
+                        })
+                    }),
+                )
+            },
+        )
+    }
+
+    /// Draw the given area of the image like [`ImageDrawable::draw_sub_image`], but report the errors in the image data.
+    pub fn try_draw_sub_image<D>(
+        &self,
+        target: &mut D,
+        area: &Rectangle,
+    ) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        self.try_draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> ImageDrawable for TransformedDecoder<'_, '_, T> {
+    type Color = T;
+
+    /// Stops silently at the first chunk that cannot be decoded, see [`Self::try_draw`].
+    #[inline]
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw(target))
+    }
+
+    #[inline]
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw_sub_image(target, area))
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{chunk::UNCOMPRESSED_SIZE, *};
+
+#[cfg(feature = "alloc")]
+use crate::metadata::RECORD_HEADER_SIZE;
+
+/// Size of an entry of the row offset table
+const ROW_OFFSET_SIZE: usize = 4;
+
+/// Planes of a mid-gray block, substituted for a damaged block in lenient mode
+pub(crate) const NEUTRAL_BLOCK: [u8; UNCOMPRESSED_SIZE] = [32; UNCOMPRESSED_SIZE];
+
+/// Returns true if the error is confined to the image data, so that lenient mode can skip the damaged blocks.
+///
+/// A missing or malformed checksum record and a damaged file header are not, since nothing can be trusted then.
+#[inline]
+pub(crate) fn is_recoverable(err: &DecodeError) -> bool {
+    !matches!(
+        err,
+        DecodeError::MissingChecksum
+            | DecodeError::HeaderChecksumMismatch { .. }
+            | DecodeError::InvalidMetadata { .. }
+    )
+}
+
+/// Return the size of the payload of the row offset table for an image of the given height.
+#[inline]
+pub(crate) fn payload_len(height: u32) -> usize {
+    ROW_OFFSET_SIZE * height.div_ceil(8) as usize
+}
+
+/// Returns true if every offset of the row offset table of an image of the given size fits in 32 bits.
+#[inline]
+pub(crate) fn fits_row_offsets(width: u32, height: u32) -> bool {
+    let cols = width.div_ceil(8) as u64;
+    let rows = height.div_ceil(8) as u64;
+    rows.saturating_sub(1) * cols * (1 + UNCOMPRESSED_SIZE as u64) <= u32::MAX as u64
+}
+
+impl<'a, T> Decoder<'a, T> {
+    /// Returns true if the file has a [`Metadata::RowOffsets`] record.
+    #[inline]
+    pub fn has_row_offsets(&self) -> bool {
+        self.row_offsets().is_some()
+    }
+
+    /// Find the row offset table, ignoring it if its size does not match the image.
+    pub(crate) fn row_offsets(&self) -> Option<&'a [u8]> {
+        self.metadata()
+            .find_map(|record| match record {
+                Metadata::RowOffsets(data) => Some(data),
+                _ => None,
+            })
+            .filter(|data| data.len() == payload_len(self.info().height()))
+    }
+}
+
+/// Return the byte offset of the first chunk of the given row, relative to the start of the image data.
+#[inline]
+pub(crate) fn row_offset(table: &[u8], row: usize) -> Option<usize> {
+    table
+        .get(row * ROW_OFFSET_SIZE..(row + 1) * ROW_OFFSET_SIZE)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+/// Fill in the row offset table at the given byte offset of a file whose chunks have all been written.
+#[cfg(feature = "alloc")]
+pub(crate) fn write_row_offsets(
+    output: &mut [u8],
+    record: usize,
+    data_offset: usize,
+    width: u32,
+    height: u32,
+) {
+    let cols = width.div_ceil(8) as usize;
+    let rows = height.div_ceil(8) as usize;
+    let payload = record + RECORD_HEADER_SIZE;
+    let mut cursor = data_offset;
+    for row in 0..rows {
+        let at = payload + ROW_OFFSET_SIZE * row;
+        let offset = (cursor - data_offset) as u32;
+        output[at..at + ROW_OFFSET_SIZE].copy_from_slice(&offset.to_le_bytes());
+        for _ in 0..cols {
+            cursor += 1 + output[cursor] as usize;
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{color::*, *};
+
+#[cfg(feature = "embedded")]
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Scale of the decoded image.
+///
+/// Scaled decoding averages the pixels of each block before the color conversion,
+/// so it is faster than decoding the whole image and scaling it down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Scale {
+    /// Original size.
+    #[default]
+    Full,
+    /// 1/2 of the width and height.
+    Half,
+    /// 1/4 of the width and height.
+    Quarter,
+    /// 1/8 of the width and height, one pixel per block.
+    Eighth,
+}
+
+impl Scale {
+    /// Return the divisor of the width and height.
+    #[inline]
+    pub const fn factor(&self) -> u32 {
+        match self {
+            Self::Full => 1,
+            Self::Half => 2,
+            Self::Quarter => 4,
+            Self::Eighth => 8,
+        }
+    }
+
+    /// Return the size of a scaled image, rounded up.
+    #[inline]
+    pub const fn apply(&self, info: ImageInfo) -> ImageInfo {
+        let factor = self.factor() as u16;
+        ImageInfo {
+            width: info.width.div_ceil(factor),
+            height: info.height.div_ceil(factor),
+        }
+    }
+}
+
+impl<'a, T> Decoder<'a, T> {
+    /// Decode the MPIC data at the given scale to a vector of bytes in RGB888 format (3 bytes per pixel).
+    ///
+    /// The size of the output image is given by [`Scale::apply`].
+    #[cfg(feature = "alloc")]
+    pub fn decode_scaled(&self, scale: Scale) -> Result<alloc::vec::Vec<u8>, DecodeError> {
+        let mut vec = alloc::vec![0; scale.apply(self.info()).buffer_len(3)?];
+        self.decode_scaled_to_slice(scale, vec.as_mut())
+            .map(|_| vec)
+    }
+
+    /// Decode the MPIC data at the given scale to the given output buffer in RGB888 format (3 bytes per pixel).
+    ///
+    /// The output buffer should have a length of at least `width * height * 3` bytes, where `width` and `height` are given by [`Scale::apply`].
+    ///
+    /// Returns an error if the data is invalid or if the output buffer is too small.
+    pub fn decode_scaled_to_slice(
+        &self,
+        scale: Scale,
+        output: &mut [u8],
+    ) -> Result<(), DecodeError> {
+        let info = scale.apply(self.info());
+        let width = info.width() as usize;
+        if output.len() < info.buffer_len(3)? {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self._decode_scaled(scale, |x, y, w, h, colors| {
+            for (y7, row) in colors.chunks_exact(w).take(h).enumerate() {
+                for (x7, rgb) in row.iter().enumerate() {
+                    let index = (x + x7 + (y + y7) * width) * 3;
+                    output[index] = rgb.r8();
+                    output[index + 1] = rgb.g8();
+                    output[index + 2] = rgb.b8();
+                }
+            }
+        })
+    }
+
+    /// Decode the MPIC data at the given scale to a vector of RGB565 pixels.
+    ///
+    /// If `dither` is true, ordered dithering is applied when reducing the red and blue channels to 5 bits.
+    #[cfg(feature = "alloc")]
+    pub fn decode_rgb565_scaled(
+        &self,
+        scale: Scale,
+        dither: bool,
+    ) -> Result<alloc::vec::Vec<u16>, DecodeError> {
+        let mut vec = alloc::vec![0; scale.apply(self.info()).buffer_len(1)?];
+        self.decode_rgb565_scaled_to_slice(scale, vec.as_mut(), dither)
+            .map(|_| vec)
+    }
+
+    /// Decode the MPIC data at the given scale to the given output buffer of RGB565 pixels.
+    ///
+    /// The output buffer should have a length of at least `width * height` pixels, where `width` and `height` are given by [`Scale::apply`].
+    /// If `dither` is true, ordered dithering is applied when reducing the red and blue channels to 5 bits.
+    ///
+    /// Returns an error if the data is invalid or if the output buffer is too small.
+    pub fn decode_rgb565_scaled_to_slice(
+        &self,
+        scale: Scale,
+        output: &mut [u16],
+        dither: bool,
+    ) -> Result<(), DecodeError> {
+        let info = scale.apply(self.info());
+        let width = info.width() as usize;
+        if output.len() < info.buffer_len(1)? {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self._decode_scaled(scale, |x, y, w, h, colors| {
+            for (y7, row) in colors.chunks_exact(w).take(h).enumerate() {
+                for (x7, rgb) in row.iter().enumerate() {
+                    let (x, y) = (x + x7, y + y7);
+                    output[x + y * width] = if dither {
+                        rgb.into_rgb565_dithered(x, y)
+                    } else {
+                        rgb.into_rgb565()
+                    };
+                }
+            }
+        })
+    }
+
+    /// Decode all blocks in order at the given scale, calling `f` with the position and the size of the scaled block
+    /// and its colors in row-major order.
+    fn _decode_scaled<F>(&self, scale: Scale, mut f: F) -> Result<(), DecodeError>
+    where
+        F: FnMut(usize, usize, usize, usize, &[MpicRgb666]),
+    {
+        let info = self.info();
+        self._decode_scaled_in(
+            scale,
+            0,
+            0,
+            info.width(),
+            info.height(),
+            |x, y, w, h, colors| {
+                f(x, y, w, h, colors);
+                Ok::<_, core::convert::Infallible>(())
+            },
+        )
+        .map_err(DrawError::into_decode)
+    }
+
+    /// Decode the blocks that overlap the area from (`left`, `top`) to (`right`, `bottom`) of the original image
+    /// in order at the given scale, calling `f` with the position and the size of the scaled block and its colors in row-major order.
+    ///
+    /// Stops at the first error, whether it is in the data or returned by `f`.
+    fn _decode_scaled_in<F, E>(
+        &self,
+        scale: Scale,
+        left: u32,
+        top: u32,
+        right: u32,
+        bottom: u32,
+        mut f: F,
+    ) -> Result<(), DrawError<E>>
+    where
+        F: FnMut(usize, usize, usize, usize, &[MpicRgb666]) -> Result<(), E>,
+    {
+        let factor = scale.factor() as usize;
+        let mut colors = [MpicRgb666::new(0, 0, 0); 64];
+        // Whole blocks are decoded, so that the pixels at the edges of the area average all of their source pixels.
+        self._decode_planes_in(
+            left,
+            top,
+            right.saturating_add(7) & !7,
+            bottom.saturating_add(7) & !7,
+            |x8, y8, w7, h7, buf_y, buf_u, buf_v| {
+                let (x8, y8, w7, h7) = (x8 as usize, y8 as usize, w7 as usize, h7 as usize);
+                let w = w7.div_ceil(factor);
+                let h = h7.div_ceil(factor);
+                for oy in 0..h {
+                    let top = oy * factor;
+                    let bottom = (top + factor).min(h7);
+                    for ox in 0..w {
+                        let left = ox * factor;
+                        let right = (left + factor).min(w7);
+                        let mut sum = [0u32; 3];
+                        for y7 in top..bottom {
+                            for x7 in left..right {
+                                let uv = (y7 / 2) * 4 + x7 / 2;
+                                sum[0] += buf_y[y7 * 8 + x7] as u32;
+                                sum[1] += buf_u[uv] as u32;
+                                sum[2] += buf_v[uv] as u32;
+                            }
+                        }
+                        let count = ((bottom - top) * (right - left)) as u32;
+                        let [y, u, v] = sum.map(|v| ((v + count / 2) / count) as u8);
+                        colors[oy * w + ox] = MpicRgb666::from_yuv(MpicYuv666::new(y, u, v));
+                    }
+                }
+                f(x8 / factor, y8 / factor, w, h, &colors[..w * h])
+            },
+        )
+    }
+}
+
+/// A decoder that draws the image at a reduced scale.
+#[cfg(feature = "embedded")]
+pub struct ScaledDecoder<'a, 'b, T> {
+    decoder: &'b Decoder<'a, T>,
+    scale: Scale,
+}
+
+#[cfg(feature = "embedded")]
+impl<'a, T> Decoder<'a, T> {
+    /// Return a wrapper that draws this image at the given scale.
+    #[inline]
+    pub fn scaled(&self, scale: Scale) -> ScaledDecoder<'a, '_, T> {
+        ScaledDecoder {
+            decoder: self,
+            scale,
+        }
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> OriginDimensions for ScaledDecoder<'_, '_, T> {
+    #[inline]
+    fn size(&self) -> Size {
+        self.scale.apply(self.decoder.info()).into()
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> ScaledDecoder<'_, '_, T> {
+    /// Draw the image to the target like [`ImageDrawable::draw`], but report the errors in the image data.
+    ///
+    /// Returns [`DrawError::Decode`] with the index and the byte offset of the first chunk that cannot be decoded.
+    pub fn try_draw<D>(&self, target: &mut D) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        // The area of the scaled image that covers the target.
+        let visible = target.bounding_box().intersection(&self.bounding_box());
+        let Some(bottom_right) = visible.bottom_right() else {
+            return Ok(());
+        };
+        let factor = self.scale.factor();
+        self.decoder._decode_scaled_in(
+            self.scale,
+            visible.top_left.x as u32 * factor,
+            visible.top_left.y as u32 * factor,
+            (bottom_right.x as u32 + 1) * factor,
+            (bottom_right.y as u32 + 1) * factor,
+            |x, y, w, h, colors| {
+                target.fill_contiguous(
+                    &Rectangle::new(
+                        Point::new(x as i32, y as i32),
+                        Size::new(w as u32, h as u32),
+                    ),
+                    colors.iter().map(|&rgb| rgb.into()),
+                )
+            },
+        )
+    }
+
+    /// Draw the given area of the image like [`ImageDrawable::draw_sub_image`], but report the errors in the image data.
+    pub fn try_draw_sub_image<D>(
+        &self,
+        target: &mut D,
+        area: &Rectangle,
+    ) -> Result<(), DrawError<D::Error>>
+    where
+        D: DrawTarget<Color = T>,
+    {
+        self.try_draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> ImageDrawable for ScaledDecoder<'_, '_, T> {
+    type Color = T;
+
+    /// Stops silently at the first chunk that cannot be decoded, see [`Self::try_draw`].
+    #[inline]
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw(target))
+    }
+
+    #[inline]
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        DrawError::ignore_decode(self.try_draw_sub_image(target, area))
+    }
+}
@@ -1,5 +1,12 @@
+use crate::color::{MpicRgb666, MpicYuv666};
+use crate::crc::{Crc32, crc32};
 use crate::lz::CompressionLevel;
-use crate::{chunk, demosaic_uv, mosaic_uv};
+use crate::{
+    Atlas, AtlasBuilder, ChromaFilter, DecodeError, Decoder, Dither, EdgePadding, EncodeError,
+    Encoder, EncoderOptions, FileHeader, Frame, ImageInfo, Metadata, PixelFormat, Rotation, Scale,
+    Version, chunk, demosaic_uv, metadata, mosaic_uv, mosaic_uv_with,
+    transform::{self, TransformError},
+};
 use alloc::vec::Vec as VecA;
 use core::mem::swap;
 use heapless::Vec as VecH;
@@ -49,6 +56,2166 @@ fn mosaic() {
     assert_eq!(right2, original_right);
 }
 
+#[test]
+fn chroma_filters() {
+    let mut next = lcg(0x1234_5678);
+    let mut random = || (next() >> 8) as u8;
+    let buf_y: [u8; 64] = core::array::from_fn(|_| 4 + random() % 55);
+    let buf_u: [u8; 64] = core::array::from_fn(|_| 4 + random() % 57);
+    let buf_v: [u8; 64] = core::array::from_fn(|_| 4 + random() % 57);
+
+    // Sum of squared RGB errors after the decoder expands the U and V channels.
+    let rgb_error = |out_u: &[u8; 16], out_v: &[u8; 16]| -> u64 {
+        let out_u = demosaic_uv(out_u);
+        let out_v = demosaic_uv(out_v);
+        (0..64)
+            .map(|i| {
+                let expected = MpicRgb666::from_yuv(MpicYuv666::new(buf_y[i], buf_u[i], buf_v[i]));
+                let actual = MpicRgb666::from_yuv(MpicYuv666::new(buf_y[i], out_u[i], out_v[i]));
+                let dr = expected.r8() as i64 - actual.r8() as i64;
+                let dg = expected.g8() as i64 - actual.g8() as i64;
+                let db = expected.b8() as i64 - actual.b8() as i64;
+                (dr * dr + dg * dg + db * db) as u64
+            })
+            .sum()
+    };
+
+    for filter in [
+        ChromaFilter::Box,
+        ChromaFilter::Average,
+        ChromaFilter::LumaWeighted,
+        ChromaFilter::Sharp,
+    ] {
+        let (out_u, out_v) = mosaic_uv_with(filter, &buf_y, &buf_u, &buf_v);
+        for y in 0..4 {
+            for x in 0..4 {
+                let base = y * 16 + x * 2;
+                let indexes = [base, base + 1, base + 8, base + 9];
+                for (input, output) in [(&buf_u, out_u[y * 4 + x]), (&buf_v, out_v[y * 4 + x])] {
+                    let min = indexes.iter().map(|&i| input[i]).min().unwrap();
+                    let max = indexes.iter().map(|&i| input[i]).max().unwrap();
+                    assert!(
+                        (min..=max).contains(&output),
+                        "{:?}: {} is out of range {}..={}",
+                        filter,
+                        output,
+                        min,
+                        max
+                    );
+                }
+            }
+        }
+    }
+
+    let (box_u, box_v) = mosaic_uv_with(ChromaFilter::Box, &buf_y, &buf_u, &buf_v);
+    assert_eq!((box_u, box_v), mosaic_uv(&buf_u, &buf_v));
+    // The default keeps the output of the existing encoder functions unchanged.
+    assert_eq!(EncoderOptions::new().chroma_filter(), ChromaFilter::Box);
+
+    let (avg_u, avg_v) = mosaic_uv_with(ChromaFilter::Average, &buf_y, &buf_u, &buf_v);
+    let (sharp_u, sharp_v) = mosaic_uv_with(ChromaFilter::Sharp, &buf_y, &buf_u, &buf_v);
+    assert!(rgb_error(&sharp_u, &sharp_v) <= rgb_error(&avg_u, &avg_v));
+
+    let mut buf_u = [0x20; 64];
+    buf_u[0] = 0x21;
+    buf_u[1] = 0x21;
+    buf_u[8] = 0x21;
+    let (box_u, _) = mosaic_uv_with(ChromaFilter::Box, &buf_y, &buf_u, &buf_u);
+    let (avg_u, _) = mosaic_uv_with(ChromaFilter::Average, &buf_y, &buf_u, &buf_u);
+    assert_eq!((box_u[0], avg_u[0]), (0x20, 0x21));
+}
+
+/// Return a linear congruential generator of pseudo-random 24-bit values, starting from the given seed.
+pub(crate) fn lcg(mut seed: u32) -> impl FnMut() -> u32 {
+    move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        seed >> 8
+    }
+}
+
+/// A smooth gradient image in RGB888 format for round trip tests.
+pub(crate) fn gradient_image(width: u32, height: u32) -> VecA<u8> {
+    let mut data = VecA::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height {
+        for x in 0..width {
+            data.push((64 + x * 8) as u8);
+            data.push((64 + y * 8) as u8);
+            data.push((128 + (x + y) * 4) as u8);
+        }
+    }
+    data
+}
+
+#[test]
+fn edge_padding_round_trip() {
+    let allowed_error = 16;
+
+    for height in 1..=17 {
+        for width in 1..=17 {
+            let data = gradient_image(width, height);
+            let mut sizes = [0; 4];
+            for (padding, size) in [
+                EdgePadding::Replicate,
+                EdgePadding::Mirror,
+                EdgePadding::Flat,
+                EdgePadding::Smallest,
+            ]
+            .into_iter()
+            .zip(sizes.iter_mut())
+            {
+                let options = EncoderOptions::new().with_edge_padding(padding);
+                let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+                *size = encoded.len();
+
+                let decoder = Decoder::<()>::new(&encoded).unwrap();
+                assert_eq!(
+                    (decoder.info().width(), decoder.info().height()),
+                    (width, height)
+                );
+                let rgb = decoder.decode().unwrap();
+                let rgba = decoder.decode_rgba().unwrap();
+                assert_eq!(rgb.len(), data.len());
+                for (index, (expected, actual)) in data.chunks(3).zip(rgb.chunks(3)).enumerate() {
+                    let error = expected
+                        .iter()
+                        .zip(actual.iter())
+                        .map(|(&lhs, &rhs)| (lhs as isize - rhs as isize).abs())
+                        .max()
+                        .unwrap();
+                    assert!(
+                        error <= allowed_error,
+                        "{:?} {}x{} at ({}, {}): {:?} => {:?}",
+                        padding,
+                        width,
+                        height,
+                        index as u32 % width,
+                        index as u32 / width,
+                        expected,
+                        actual,
+                    );
+                    assert_eq!(actual, &rgba[index * 4..index * 4 + 3]);
+                }
+            }
+            assert!(sizes[3] <= sizes[0] && sizes[3] <= sizes[1] && sizes[3] <= sizes[2]);
+        }
+    }
+}
+
+#[test]
+fn default_output_unchanged() {
+    // The length and the CRC-32 of the files written by the encoder before the edge padding options,
+    // for sizes that are not a multiple of 8.
+    let stripes = |width: u32, height: u32| {
+        (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                let value = ((x / 3 + y / 2) % 3 * 100) as u8;
+                [value, 255 - value, value / 2]
+            })
+            .collect::<VecA<u8>>()
+    };
+    for (width, height, data, expected) in [
+        (13, 11, gradient_image(13, 11), (195, 0x7238_5DDA)),
+        (13, 11, stripes(13, 11), (162, 0x97F6_FA89)),
+        (21, 13, gradient_image(21, 13), (319, 0x4410_51EA)),
+        (21, 13, stripes(21, 13), (269, 0x3B70_EE08)),
+        (7, 9, gradient_image(7, 9), (88, 0xA7D0_732F)),
+        (7, 9, stripes(7, 9), (78, 0x8DF0_EA8D)),
+    ] {
+        #[allow(deprecated)]
+        let encoded = Encoder::encode(&data, width, height).unwrap();
+        assert_eq!(
+            (encoded.len(), crc32(&encoded)),
+            expected,
+            "{width}x{height}"
+        );
+        let options = EncoderOptions::new();
+        assert_eq!(options.edge_padding(), EdgePadding::Flat);
+        assert_eq!(
+            Encoder::encode_with_options(&data, width, height, &options).unwrap(),
+            encoded
+        );
+    }
+}
+
+#[test]
+fn dither_round_trip() {
+    // A horizontal gradient that changes less than a 6-bit step per 8 pixels.
+    let width = 64;
+    let height = 16;
+    let mut data = VecA::new();
+    for _ in 0..height {
+        for x in 0..width {
+            let value = (96 + x / 8) as u8;
+            data.extend_from_slice(&[value, value, value]);
+        }
+    }
+
+    // Mean error of the 8x8 blocks, which is what the eye sees as banding.
+    let block_error = |dither| -> f64 {
+        let options = EncoderOptions::new().with_dither(dither);
+        let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+        let decoded = Decoder::<()>::new(&encoded).unwrap().decode().unwrap();
+        let mut total = 0.0;
+        for y8 in (0..height).step_by(8) {
+            for x8 in (0..width).step_by(8) {
+                let mut diff = 0.0;
+                for y7 in 0..8 {
+                    for x7 in 0..8 {
+                        let index = (((y8 + y7) * width + x8 + x7) * 3 + 1) as usize;
+                        diff += decoded[index] as f64 - data[index] as f64;
+                    }
+                }
+                total += (diff / 64.0).abs();
+            }
+        }
+        total / ((width / 8) * (height / 8)) as f64
+    };
+
+    let none = block_error(Dither::None);
+    let ordered = block_error(Dither::Ordered);
+    let floyd_steinberg = block_error(Dither::FloydSteinberg);
+    assert!(ordered < none, "{} >= {}", ordered, none);
+    assert!(floyd_steinberg < none, "{} >= {}", floyd_steinberg, none);
+}
+
+#[test]
+fn decode_rgb565() {
+    let width = 24;
+    let height = 13;
+    let data = gradient_image(width, height);
+    let encoded =
+        Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    let decoder = Decoder::<()>::new(&encoded).unwrap();
+    let rgb = decoder.decode().unwrap();
+    let plain = decoder.decode_rgb565(false).unwrap();
+    let dithered = decoder.decode_rgb565(true).unwrap();
+    assert_eq!(plain.len(), (width * height) as usize);
+
+    for ((rgb, &plain), &dithered) in rgb.chunks(3).zip(plain.iter()).zip(dithered.iter()) {
+        let expected =
+            ((rgb[0] as u16 >> 3) << 11) | ((rgb[1] as u16 >> 2) << 5) | (rgb[2] as u16 >> 3);
+        assert_eq!(plain, expected);
+
+        // Dithering only rounds up the red and blue channels, and never touches the green channel.
+        assert_eq!(dithered & 0x07E0, plain & 0x07E0);
+        for shift in [0, 11] {
+            let plain = (plain >> shift) & 0x1F;
+            let dithered = (dithered >> shift) & 0x1F;
+            assert!(dithered == plain || dithered == plain + 1);
+        }
+    }
+}
+
+#[test]
+fn encoder_options() {
+    let width = 13;
+    let height = 11;
+    let data = gradient_image(width, height);
+    let expected =
+        Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+
+    #[allow(deprecated)]
+    {
+        let encoded = Encoder::encode2(&data, width, height, CompressionLevel::Default).unwrap();
+        assert_eq!(encoded, expected);
+    }
+
+    // The same image in other pixel formats with padding at the end of each row.
+    for pixel_format in [
+        PixelFormat::Rgb888,
+        PixelFormat::Bgr888,
+        PixelFormat::Rgba8888,
+        PixelFormat::Bgra8888,
+    ] {
+        let stride = width as usize * pixel_format.bytes_per_pixel() + 5;
+        let mut input = VecA::new();
+        for row in data.chunks(width as usize * 3) {
+            for rgb in row.chunks(3) {
+                match pixel_format {
+                    PixelFormat::Rgb888 => input.extend_from_slice(rgb),
+                    PixelFormat::Bgr888 => input.extend_from_slice(&[rgb[2], rgb[1], rgb[0]]),
+                    PixelFormat::Rgba8888 => input.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 0]),
+                    PixelFormat::Bgra8888 => input.extend_from_slice(&[rgb[2], rgb[1], rgb[0], 0]),
+                    _ => unreachable!(),
+                }
+            }
+            input.extend_from_slice(&[0xFF; 5]);
+        }
+        let options = EncoderOptions::new()
+            .with_pixel_format(pixel_format)
+            .with_stride(stride);
+        let encoded = Encoder::encode_with_options(&input, width, height, &options).unwrap();
+        assert_eq!(encoded, expected, "{:?}", pixel_format);
+
+        // The padding after the last row may be omitted, but the pixels may not.
+        let last = input.len() - 5;
+        assert!(Encoder::encode_with_options(&input[..last], width, height, &options).is_ok());
+        assert_eq!(
+            Encoder::encode_with_options(&input[..last - 1], width, height, &options),
+            Err(EncodeError::InsufficientData {
+                len: last - 1,
+                required: last
+            })
+        );
+    }
+
+    let options = EncoderOptions::new().with_stride(width as usize * 3 - 1);
+    assert_eq!(
+        Encoder::encode_with_options(&data, width, height, &options),
+        Err(EncodeError::InvalidStride {
+            stride: width as usize * 3 - 1,
+            min: width as usize * 3
+        })
+    );
+    let options = EncoderOptions::new().with_stride(usize::MAX / 4);
+    assert_eq!(
+        Encoder::encode_with_options(&data, width, height, &options),
+        Err(EncodeError::InvalidStride {
+            stride: usize::MAX / 4,
+            min: width as usize * 3
+        })
+    );
+    assert_eq!(
+        Encoder::encode_with_options(&data, 0, height, &EncoderOptions::new()),
+        Err(EncodeError::InvalidSize { width: 0, height })
+    );
+
+    // Lower quality never makes the file larger, and still decodes.
+    let mut last_size = expected.len();
+    for quality in [75, 50, 25, 0] {
+        let options = EncoderOptions::new().with_quality(quality);
+        let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+        assert!(encoded.len() <= last_size, "quality {}", quality);
+        last_size = encoded.len();
+        let decoded = Decoder::<()>::new(&encoded).unwrap().decode().unwrap();
+        assert_eq!(decoded.len(), data.len());
+    }
+
+    // Version 0 cannot represent sizes that are not multiples of 8.
+    let options = EncoderOptions::new().with_version(Version::V0);
+    assert_eq!(
+        Encoder::encode_with_options(&data, width, height, &options),
+        Err(EncodeError::UnsupportedVersion(Version::V0))
+    );
+    let data = gradient_image(16, 8);
+    let encoded = Encoder::encode_with_options(&data, 16, 8, &options).unwrap();
+    assert_eq!(encoded[8], Version::V0.0);
+    let options = EncoderOptions::new().with_version(Version::V1);
+    let encoded = Encoder::encode_with_options(&data, 16, 8, &options).unwrap();
+    assert_eq!(encoded[8], Version::V1.0);
+}
+
+#[test]
+fn quality_keeps_gray() {
+    // Largest difference between the channels of a decoded pixel.
+    let spread = |gray: u8, quality: u8| {
+        let data = alloc::vec![gray; 16 * 16 * 3];
+        let options = EncoderOptions::new().with_quality(quality);
+        let encoded = Encoder::encode_with_options(&data, 16, 16, &options).unwrap();
+        let decoded = Decoder::<()>::new(&encoded).unwrap().decode().unwrap();
+        decoded
+            .chunks(3)
+            .map(|rgb| rgb.iter().max().unwrap() - rgb.iter().min().unwrap())
+            .max()
+            .unwrap()
+    };
+
+    // The conversion to 6-bit YUV alone leaves a slight cast, which lower quality must not increase.
+    let allowed = (0..=255)
+        .step_by(5)
+        .map(|gray| spread(gray, 100))
+        .max()
+        .unwrap();
+    for gray in (0..=255).step_by(5) {
+        for quality in [75, 50, 40, 25, 10, 0] {
+            let spread = spread(gray, quality);
+            assert!(
+                spread <= allowed,
+                "gray {} at quality {}: {} > {}",
+                gray,
+                quality,
+                spread,
+                allowed
+            );
+        }
+    }
+}
+
+#[test]
+fn pixel_formats_rgb565_and_l8() {
+    let width = 13;
+    let height = 11;
+    let data = gradient_image(width, height);
+    let round_trip = |input: &[u8], pixel_format, rgb: &[u8]| {
+        let options = EncoderOptions::new().with_pixel_format(pixel_format);
+        let encoded = Encoder::encode_with_options(input, width, height, &options).unwrap();
+        // The pixels are expanded to RGB888 exactly as given in `rgb`.
+        let expected =
+            Encoder::encode_with_options(rgb, width, height, &EncoderOptions::new()).unwrap();
+        assert_eq!(encoded, expected, "{:?}", pixel_format);
+        let decoded = Decoder::<()>::new(&encoded).unwrap().decode().unwrap();
+        let max_error = decoded
+            .iter()
+            .zip(rgb)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap();
+        assert!(max_error <= 24, "{:?}: {}", pixel_format, max_error);
+    };
+
+    // RGB565 in little-endian, whose 5 and 6 bits are expanded by repeating the upper bits.
+    let mut input = VecA::new();
+    let mut rgb = VecA::new();
+    for pixel in data.chunks(3) {
+        let (r, g, b) = (pixel[0] >> 3, pixel[1] >> 2, pixel[2] >> 3);
+        let raw = ((r as u16) << 11) | ((g as u16) << 5) | b as u16;
+        input.extend_from_slice(&raw.to_le_bytes());
+        rgb.extend_from_slice(&[
+            (r << 3) | (r >> 2),
+            (g << 2) | (g >> 4),
+            (b << 3) | (b >> 2),
+        ]);
+    }
+    round_trip(&input, PixelFormat::Rgb565, &rgb);
+
+    // Grayscale, which is the same value in the red, green and blue channels.
+    let input = data.chunks(3).map(|pixel| pixel[1]).collect::<VecA<_>>();
+    let rgb = input.iter().flat_map(|&l| [l, l, l]).collect::<VecA<_>>();
+    round_trip(&input, PixelFormat::L8, &rgb);
+}
+
+#[test]
+fn validate() {
+    let width = 20;
+    let height = 12;
+    let data = gradient_image(width, height);
+    let encoded =
+        Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    Decoder::<()>::new_strict(&encoded).unwrap();
+
+    // Offsets of the chunks: 3 x 2 blocks
+    let mut offsets = VecA::new();
+    let mut cursor = 9;
+    while cursor < encoded.len() {
+        offsets.push(cursor);
+        cursor += encoded[cursor] as usize + 1;
+    }
+    assert_eq!(offsets.len(), 6);
+
+    // Trailing data
+    let mut blob = encoded.clone();
+    blob.push(0);
+    assert!(Decoder::<()>::new(&blob).unwrap().decode().is_ok());
+    assert_eq!(
+        Decoder::<()>::new_strict(&blob).err(),
+        Some(DecodeError::TrailingData {
+            offset: encoded.len()
+        })
+    );
+
+    // Truncated data
+    for (index, &offset) in offsets.iter().enumerate() {
+        for len in [offset, offset + 1] {
+            let decoder = Decoder::<()>::new(&encoded[..len]).unwrap();
+            let expected = Some(DecodeError::TruncatedChunk { index, offset });
+            assert_eq!(decoder.validate().err(), expected);
+            assert_eq!(decoder.decode().err(), expected);
+        }
+    }
+
+    // Invalid size of chunks
+    for size in [0, 4, 73, 95, 97, 255] {
+        let mut blob = encoded.clone();
+        blob[offsets[4]] = size;
+        assert_eq!(
+            Decoder::<()>::new_strict(&blob).err(),
+            Some(DecodeError::InvalidChunkLength {
+                index: 4,
+                offset: offsets[4]
+            })
+        );
+    }
+
+    // A chunk that decompresses to less than 96 bytes
+    let mut blob = encoded[..offsets[2]].to_vec();
+    blob.extend_from_slice(&[5, 0x01, 0x02, 0x03, 0x04, 0x05]);
+    blob.extend_from_slice(&encoded[offsets[3]..]);
+    let expected = Some(DecodeError::OutputUnderflow {
+        index: 2,
+        offset: offsets[2] + 6,
+    });
+    assert_eq!(Decoder::<()>::new_strict(&blob).err(), expected);
+    assert_eq!(Decoder::<()>::new(&blob).unwrap().decode().err(), expected);
+
+    assert_eq!(
+        Decoder::<()>::new_strict(&encoded[..8]).err(),
+        Some(DecodeError::TruncatedHeader)
+    );
+}
+
+#[test]
+fn decode_errors() {
+    let width = 16;
+    let height = 8;
+    let data = gradient_image(width, height);
+    let encoded =
+        Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    let second = 9 + encoded[9] as usize + 1;
+
+    // Replaces the second chunk with the given payload.
+    let with_chunk = |payload: &[u8]| {
+        let mut blob = encoded[..second].to_vec();
+        blob.push(payload.len() as u8);
+        blob.extend_from_slice(payload);
+        Decoder::<()>::new_strict(&blob).err()
+    };
+    let payload = second + 1;
+
+    let mut literals = [0x01; 64];
+    literals[10] = 0x8A;
+    assert_eq!(
+        with_chunk(&literals),
+        Some(DecodeError::MatchOutOfRange {
+            index: 1,
+            offset: payload + 10
+        })
+    );
+    let mut literals = [0x01; 64];
+    literals[40] = 0x45;
+    literals[41] = 0x40;
+    assert_eq!(
+        with_chunk(&literals),
+        Some(DecodeError::ReservedOpcode {
+            index: 1,
+            offset: payload + 40
+        })
+    );
+    let mut literals = [0x01; 64];
+    literals[63] = 0x40;
+    assert_eq!(
+        with_chunk(&literals),
+        Some(DecodeError::TruncatedChunk {
+            index: 1,
+            offset: payload + 63
+        })
+    );
+    let mut literals = [0x01; 64];
+    literals[1] = 0x7F;
+    literals[2] = 0x00;
+    assert_eq!(
+        with_chunk(&literals[..5]),
+        Some(DecodeError::OutputUnderflow {
+            index: 1,
+            offset: payload + 5
+        })
+    );
+    let mut overflow = [0x01; 8];
+    overflow[1] = 0x7F;
+    overflow[2] = 0x00;
+    overflow[3] = 0x7F;
+    overflow[4] = 0x00;
+    assert_eq!(
+        with_chunk(&overflow),
+        Some(DecodeError::OutputOverflow {
+            index: 1,
+            offset: payload + 3
+        })
+    );
+
+    // File header
+    let mut blob = encoded.clone();
+    blob[1] = b'M';
+    assert_eq!(
+        Decoder::<()>::new_strict(&blob).err(),
+        Some(DecodeError::BadMagic)
+    );
+    let mut blob = encoded.clone();
+    blob[8] = 0x7F;
+    assert_eq!(
+        Decoder::<()>::new_strict(&blob).err(),
+        Some(DecodeError::UnsupportedVersion(Version(0x7F)))
+    );
+    let mut blob = encoded.clone();
+    blob[4] = 0;
+    assert_eq!(
+        Decoder::<()>::new_strict(&blob).err(),
+        Some(DecodeError::InvalidSize { width: 0, height })
+    );
+    assert!(Decoder::<()>::new(&blob).is_none());
+
+    let error = DecodeError::MatchOutOfRange {
+        index: 1,
+        offset: payload + 10,
+    };
+    assert_eq!(error.chunk_index(), Some(1));
+    assert_eq!(error.offset(), Some(payload + 10));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "chunk 1 has a match out of range at offset {}",
+            payload + 10
+        )
+    );
+    assert_eq!(
+        EncodeError::UnsupportedVersion(Version::V0).to_string(),
+        "version 0 cannot represent this image"
+    );
+}
+
+/// Directory of the conformance corpus
+const CONFORMANCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/conformance/");
+
+/// Build a file of the given version from the payloads of its chunks, without a metadata section.
+fn conformance_file(width: u32, height: u32, version: Version, chunks: &[&[u8]]) -> VecA<u8> {
+    let mut blob = FileHeader::with_version(width, height, version)
+        .unwrap()
+        .to_bytes()
+        .to_vec();
+    for chunk in chunks {
+        blob.push(chunk.len() as u8);
+        blob.extend_from_slice(chunk);
+    }
+    blob
+}
+
+/// Build an animation of version 2 from the duration and the chunks of each frame, including the size of each chunk.
+fn conformance_animation(
+    width: u32,
+    height: u32,
+    loop_count: u16,
+    frames: &[(u16, VecA<u8>)],
+) -> VecA<u8> {
+    let mut table = loop_count.to_le_bytes().to_vec();
+    for (duration, chunks) in frames {
+        table.extend_from_slice(&duration.to_le_bytes());
+        table.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    }
+    let records = [Metadata::Animation(&table)];
+    let mut blob = FileHeader::with_version(width, height, Version::V2)
+        .unwrap()
+        .to_bytes()
+        .to_vec();
+    blob.extend_from_slice(&metadata::section_len(&records).unwrap().to_le_bytes());
+    metadata::write_records(&records, &mut |bytes: &[u8]| blob.extend_from_slice(bytes));
+    for (_, chunks) in frames {
+        blob.extend_from_slice(chunks);
+    }
+    blob
+}
+
+/// A regular chunk with its size
+fn sized_chunk(payload: &[u8]) -> VecA<u8> {
+    [&[payload.len() as u8], payload].concat()
+}
+
+/// A delta chunk with its size
+fn sized_delta_chunk(payload: &[u8]) -> VecA<u8> {
+    [&[chunk::DELTA_FLAG | payload.len() as u8], payload].concat()
+}
+
+/// LZ token of a short match
+fn short_match(len: u8, distance: u8) -> u8 {
+    0x80 | (len - 2) << 5 | (distance - 1)
+}
+
+/// LZ tokens of a match
+fn long_match(len: u8, distance: u8) -> [u8; 2] {
+    [0x40 | (len - 3), distance - 1]
+}
+
+/// LZ tokens of a far match, which reaches into the previous frame in a delta chunk
+fn far_match(len: u8, distance: u8) -> [u8; 2] {
+    [0x40 | (len - 3), 0x40 | (distance - 65)]
+}
+
+/// The valid files of the conformance corpus.
+fn conformance_valid() -> VecA<(&'static str, VecA<u8>)> {
+    let planes = |y: &dyn Fn(u8) -> u8, uv: &dyn Fn(u8) -> [u8; 2]| {
+        let mut block = [0; 96];
+        for (i, value) in block[..64].iter_mut().enumerate() {
+            *value = y(i as u8) & 0x3F;
+        }
+        for i in 0..16 {
+            let [u, v] = uv(i as u8);
+            block[64 + i] = u & 0x3F;
+            block[80 + i] = v & 0x3F;
+        }
+        block
+    };
+    let compacted = |block: &[u8; 96]| {
+        let mut output = VecH::new();
+        chunk::compact(block, &mut output);
+        output.to_vec()
+    };
+    let uncompressed = planes(&|i| i, &|i| [i * 4, 63 - i * 4]);
+    let left = planes(&|i| 63 - i, &|i| [i * 3, i * 2 + 20]);
+    let right = planes(&|i| (i * 3) ^ 0x15, &|i| [40 - i, 20 + i]);
+
+    let mut lz_short = VecA::from([0, 8, 16, 24]);
+    lz_short.extend([short_match(4, 4); 15]);
+    lz_short.push(short_match(2, 32));
+    lz_short.extend([short_match(5, 2), short_match(5, 2), short_match(4, 2)]);
+    lz_short.push(33);
+    lz_short.extend([short_match(5, 1); 3]);
+
+    let mut lz_long = (0..16).map(|i| i * 4).collect::<VecA<u8>>();
+    lz_long.extend(long_match(48, 16));
+    lz_long.extend(long_match(16, 64));
+    lz_long.push(10);
+    lz_long.extend(long_match(15, 1));
+
+    // Matches in a delta chunk reach into the previous frame when they go before the start of the block.
+    let mut delta = VecA::from([1, 2, short_match(5, 32)]);
+    delta.extend(long_match(66, 64));
+    delta.extend(long_match(23, 1));
+
+    let mut far = VecA::from([5, 6]);
+    far.extend(far_match(66, 96));
+    far.extend(far_match(28, 128));
+
+    let mut random = lcg(0x5EED_0045);
+    let noise = (0..24 * 16 * 3)
+        .map(|_| random() as u8)
+        .collect::<VecA<u8>>();
+    let encode = |data: &[u8], width, height, options: &EncoderOptions| {
+        Encoder::encode_with_options(data, width, height, options).unwrap()
+    };
+    // The files are fixed, so they do not follow changes of the default options.
+    let options = EncoderOptions::new()
+        .with_chroma_filter(ChromaFilter::Average)
+        .with_edge_padding(EdgePadding::Replicate);
+    let v1 = options.with_version(Version::V1);
+
+    alloc::vec![
+        (
+            "v0-uncompressed",
+            conformance_file(8, 8, Version::V0, &[&uncompressed]),
+        ),
+        (
+            "v0-compacted",
+            conformance_file(16, 8, Version::V0, &[&compacted(&left), &compacted(&right)]),
+        ),
+        (
+            "v1-lz-short",
+            conformance_file(8, 8, Version::V1, &[&lz_short]),
+        ),
+        (
+            "v1-lz-long",
+            conformance_file(8, 8, Version::V1, &[&lz_long]),
+        ),
+        ("v1-1x1", encode(&[200, 100, 50], 1, 1, &v1)),
+        ("v1-edges", encode(&gradient_image(13, 11), 13, 11, &v1)),
+        (
+            "v1-noise",
+            encode(&noise, 24, 16, &v1.with_level(CompressionLevel::Best)),
+        ),
+        (
+            "v2-metadata",
+            encode(
+                &gradient_image(16, 16),
+                16,
+                16,
+                &options
+                    .with_metadata(&[
+                        Metadata::Title("Conformance"),
+                        Metadata::Author("mpic"),
+                        Metadata::Custom {
+                            key: "Software",
+                            value: b"mpic",
+                        },
+                        Metadata::Other {
+                            tag: *b"zzzz",
+                            data: b"unknown records are skipped",
+                        },
+                    ])
+                    .with_thumbnail(true),
+            ),
+        ),
+        (
+            "v2-checksum",
+            encode(
+                &gradient_image(20, 20),
+                20,
+                20,
+                &options.with_row_offsets(true).with_checksum(true),
+            ),
+        ),
+        (
+            "v2-row-offsets",
+            encode(
+                &gradient_image(24, 24),
+                24,
+                24,
+                &options.with_row_offsets(true),
+            ),
+        ),
+        (
+            "v2-animation",
+            conformance_animation(
+                16,
+                8,
+                2,
+                &[
+                    (
+                        100,
+                        [sized_chunk(&uncompressed), sized_chunk(&compacted(&left))].concat(),
+                    ),
+                    (
+                        50,
+                        [sized_chunk(&compacted(&right)), sized_chunk(&lz_short)].concat(),
+                    ),
+                    (
+                        250,
+                        [sized_chunk(&lz_long), sized_chunk(&uncompressed)].concat(),
+                    ),
+                ],
+            ),
+        ),
+        (
+            "v2-unchanged",
+            conformance_animation(
+                16,
+                8,
+                0,
+                &[
+                    (
+                        100,
+                        [sized_chunk(&uncompressed), sized_chunk(&compacted(&left))].concat(),
+                    ),
+                    (
+                        100,
+                        [sized_chunk(&[]), sized_chunk(&compacted(&right))].concat()
+                    ),
+                    (100, [sized_chunk(&lz_short), sized_chunk(&[])].concat()),
+                ],
+            ),
+        ),
+        (
+            "v2-delta",
+            conformance_animation(
+                8,
+                8,
+                0,
+                &[
+                    (100, sized_chunk(&uncompressed)),
+                    (100, sized_delta_chunk(&delta)),
+                ],
+            ),
+        ),
+        (
+            "v2-far-match",
+            conformance_animation(
+                8,
+                8,
+                0,
+                &[
+                    (100, sized_chunk(&compacted(&left))),
+                    (100, sized_delta_chunk(&far)),
+                ],
+            ),
+        ),
+    ]
+}
+
+/// The invalid files of the conformance corpus, derived from the valid files.
+fn conformance_invalid() -> VecA<(&'static str, VecA<u8>)> {
+    let valid = conformance_valid();
+    let find = |name: &str| {
+        valid
+            .iter()
+            .find(|(valid, _)| *valid == name)
+            .unwrap()
+            .1
+            .clone()
+    };
+    let modified = |name: &str, f: &dyn Fn(&mut VecA<u8>)| {
+        let mut blob = find(name);
+        f(&mut blob);
+        blob
+    };
+    let lz = |payload: &[u8]| conformance_file(8, 8, Version::V1, &[payload]);
+    let crc = {
+        let blob = find("v2-checksum");
+        let decoder = Decoder::<()>::new(&blob).unwrap();
+        decoder
+            .metadata()
+            .find_map(|record| match record {
+                Metadata::Checksum(data) => Some(decoder.offset_of(data)),
+                _ => None,
+            })
+            .unwrap()
+    };
+
+    alloc::vec![
+        ("truncated-header", find("v0-uncompressed")[..5].to_vec()),
+        (
+            "bad-magic",
+            modified("v0-uncompressed", &|blob| blob[1] = b'M'),
+        ),
+        (
+            "unsupported-version",
+            modified("v0-uncompressed", &|blob| blob[8] = 3),
+        ),
+        (
+            "v0-unaligned-size",
+            modified("v0-compacted", &|blob| blob[4] = 12),
+        ),
+        ("zero-height", modified("v0-compacted", &|blob| blob[6] = 0)),
+        (
+            "invalid-chunk-length",
+            modified("v0-uncompressed", &|blob| blob[9] = 80),
+        ),
+        (
+            "truncated-chunk",
+            modified("v0-uncompressed", &|blob| blob.truncate(100)),
+        ),
+        (
+            "trailing-data",
+            modified("v0-uncompressed", &|blob| blob.push(0)),
+        ),
+        (
+            "match-out-of-range",
+            lz(&[1, short_match(2, 2), 0x7F, 0, 0x7F, 0]),
+        ),
+        ("reserved-opcode", lz(&[1, 0x7F, 0x80, 0, 0])),
+        ("output-overflow", lz(&[1, 0x7F, 0, 0x7F, 0]),),
+        ("output-underflow", lz(&[1, 0x7F, 0, 2, 3])),
+        (
+            "metadata-overrun",
+            modified("v2-metadata", &|blob| blob[9..13].fill(0xFF)),
+        ),
+        (
+            "header-checksum-mismatch",
+            modified("v2-checksum", &|blob| blob[crc] ^= 1),
+        ),
+        (
+            "row-checksum-mismatch",
+            modified("v2-checksum", &|blob| blob[crc + 8] ^= 1),
+        ),
+        (
+            "missing-reference",
+            conformance_animation(
+                8,
+                8,
+                0,
+                &[
+                    (100, find("v0-uncompressed")[9..].to_vec()),
+                    (100, sized_chunk(&[])),
+                    (
+                        100,
+                        sized_delta_chunk(&[far_match(66, 96), far_match(30, 96)].concat()),
+                    ),
+                ],
+            ),
+        ),
+    ]
+}
+
+/// Decode a file of the conformance corpus, verifying its checksums if it has any.
+///
+/// Every frame of an animation is decoded, one after the other.
+fn conformance_decode(blob: &[u8]) -> Result<VecA<u8>, DecodeError> {
+    let decoder = Decoder::<()>::new_strict(blob)?;
+    if decoder.has_checksum() {
+        decoder.verify()?;
+    }
+    match decoder.animation() {
+        Some(animation) => (0..animation.frame_count())
+            .map(|index| animation.decode_frame(index))
+            .collect::<Result<VecA<_>, _>>()
+            .map(|frames| frames.concat()),
+        None => decoder.decode(),
+    }
+}
+
+/// Regenerate the conformance corpus.
+///
+/// The corpus is the reference for other implementations, so existing files must not change.
+#[test]
+#[ignore]
+fn write_conformance_corpus() {
+    for (name, blob) in conformance_valid() {
+        let rgb = conformance_decode(&blob).unwrap();
+        std::fs::write(format!("{CONFORMANCE_DIR}valid/{name}.mpic"), &blob).unwrap();
+        std::fs::write(format!("{CONFORMANCE_DIR}valid/{name}.rgb"), &rgb).unwrap();
+    }
+    for (name, blob) in conformance_invalid() {
+        conformance_decode(&blob).unwrap_err();
+        std::fs::write(format!("{CONFORMANCE_DIR}invalid/{name}.mpic"), &blob).unwrap();
+    }
+}
+
+#[test]
+fn conformance() {
+    macro_rules! valid {
+        ($name:literal) => {
+            (
+                $name,
+                include_bytes!(concat!("../conformance/valid/", $name, ".mpic")).as_slice(),
+                include_bytes!(concat!("../conformance/valid/", $name, ".rgb")).as_slice(),
+            )
+        };
+    }
+    macro_rules! invalid {
+        ($name:literal, $error:expr) => {
+            (
+                $name,
+                include_bytes!(concat!("../conformance/invalid/", $name, ".mpic")).as_slice(),
+                $error,
+            )
+        };
+    }
+
+    for (name, blob, expected) in [
+        valid!("v0-uncompressed"),
+        valid!("v0-compacted"),
+        valid!("v1-lz-short"),
+        valid!("v1-lz-long"),
+        valid!("v1-1x1"),
+        valid!("v1-edges"),
+        valid!("v1-noise"),
+        valid!("v2-metadata"),
+        valid!("v2-checksum"),
+        valid!("v2-row-offsets"),
+        valid!("v2-animation"),
+        valid!("v2-unchanged"),
+        valid!("v2-delta"),
+        valid!("v2-far-match"),
+    ] {
+        assert_eq!(conformance_decode(blob).as_deref(), Ok(expected), "{name}");
+    }
+
+    for (name, blob, expected) in [
+        invalid!("truncated-header", DecodeError::TruncatedHeader),
+        invalid!("bad-magic", DecodeError::BadMagic),
+        invalid!(
+            "unsupported-version",
+            DecodeError::UnsupportedVersion(Version(3))
+        ),
+        invalid!(
+            "v0-unaligned-size",
+            DecodeError::InvalidSize {
+                width: 12,
+                height: 8
+            }
+        ),
+        invalid!(
+            "zero-height",
+            DecodeError::InvalidSize {
+                width: 16,
+                height: 0
+            }
+        ),
+        invalid!(
+            "invalid-chunk-length",
+            DecodeError::InvalidChunkLength {
+                index: 0,
+                offset: 9
+            }
+        ),
+        invalid!(
+            "truncated-chunk",
+            DecodeError::TruncatedChunk {
+                index: 0,
+                offset: 9
+            }
+        ),
+        invalid!("trailing-data", DecodeError::TrailingData { offset: 106 }),
+        invalid!(
+            "match-out-of-range",
+            DecodeError::MatchOutOfRange {
+                index: 0,
+                offset: 11
+            }
+        ),
+        invalid!(
+            "reserved-opcode",
+            DecodeError::ReservedOpcode {
+                index: 0,
+                offset: 11
+            }
+        ),
+        invalid!(
+            "output-overflow",
+            DecodeError::OutputOverflow {
+                index: 0,
+                offset: 13
+            }
+        ),
+        invalid!(
+            "output-underflow",
+            DecodeError::OutputUnderflow {
+                index: 0,
+                offset: 15
+            }
+        ),
+        invalid!(
+            "metadata-overrun",
+            DecodeError::InvalidMetadata { offset: 9 }
+        ),
+        invalid!(
+            "header-checksum-mismatch",
+            DecodeError::HeaderChecksumMismatch { offset: 33 }
+        ),
+        invalid!(
+            "row-checksum-mismatch",
+            DecodeError::ChecksumMismatch {
+                row: 1,
+                offset: 213
+            }
+        ),
+        invalid!(
+            "missing-reference",
+            DecodeError::MissingReference {
+                index: 2,
+                offset: 139
+            }
+        ),
+    ] {
+        assert_eq!(conformance_decode(blob).err(), Some(expected), "{name}");
+    }
+}
+
+#[test]
+fn file_header() {
+    const HEADER: FileHeader = match FileHeader::parse(b"\x00mpi\x02\x01\x10\x00\x01") {
+        Ok(header) => header,
+        Err(_) => panic!(),
+    };
+    const BYTES: [u8; FileHeader::MINIMAL_SIZE] = HEADER.to_bytes();
+    assert_eq!((HEADER.info().width(), HEADER.info().height()), (0x102, 16));
+    assert_eq!(HEADER.version(), Version::V1);
+    assert_eq!(&BYTES, b"\x00mpi\x02\x01\x10\x00\x01");
+    #[allow(deprecated)]
+    {
+        assert_eq!(HEADER.bytes(), BYTES);
+    }
+
+    for (width, height) in [(1, 1), (8, 8), (0x1234, 0x5678), (0xFFFF, 0xFFFF)] {
+        for version in [Version::V0, Version::V1, Version::V2] {
+            let Some(header) = FileHeader::with_version(width, height, version) else {
+                continue;
+            };
+            let mut buf = [0; FileHeader::MINIMAL_SIZE];
+            header.write_to(&mut buf);
+            assert_eq!(
+                buf[4..8],
+                [
+                    width as u8,
+                    (width >> 8) as u8,
+                    height as u8,
+                    (height >> 8) as u8
+                ]
+            );
+            assert_eq!(FileHeader::parse(&buf), Ok(header));
+            assert_eq!(FileHeader::from_bytes(&buf[..8]), None);
+        }
+    }
+
+    assert_eq!(
+        FileHeader::parse(b"\x00mpi\x08\x00\x08"),
+        Err(DecodeError::TruncatedHeader)
+    );
+    assert_eq!(
+        FileHeader::parse(b"\x00mpj\x08\x00\x08\x00\x00"),
+        Err(DecodeError::BadMagic)
+    );
+    assert_eq!(
+        FileHeader::parse(b"\x00mpi\x08\x00\x08\x00\x03"),
+        Err(DecodeError::UnsupportedVersion(Version(3)))
+    );
+    assert_eq!(
+        FileHeader::parse(b"\x00mpi\x00\x00\x08\x00\x00"),
+        Err(DecodeError::InvalidSize {
+            width: 0,
+            height: 8
+        })
+    );
+}
+
+#[test]
+fn v0_unaligned_size_only_rejected_by_validate() {
+    // The file header accepts a version 0 size that is not a multiple of 8 as the previous versions did,
+    // and only validating the whole file rejects it.
+    let data = gradient_image(9, 8);
+    let options = EncoderOptions::new().with_version(Version::V1);
+    let mut encoded = Encoder::encode_with_options(&data, 9, 8, &options).unwrap();
+    let expected = Decoder::<()>::new(&encoded).unwrap().decode().unwrap();
+    encoded[8] = Version::V0.0;
+
+    let header = FileHeader::parse(&encoded).unwrap();
+    assert_eq!((header.info().width(), header.info().height()), (9, 8));
+    assert!(header.is_valid());
+    let decoder = Decoder::<()>::new(&encoded).unwrap();
+    assert_eq!(decoder.decode().unwrap(), expected);
+
+    let invalid_size = DecodeError::InvalidSize {
+        width: 9,
+        height: 8,
+    };
+    assert_eq!(decoder.validate(), Err(invalid_size));
+    assert_eq!(
+        Decoder::<()>::new_strict(&encoded).err(),
+        Some(invalid_size)
+    );
+}
+
+#[test]
+fn from_validated() {
+    const DECODER: Decoder<'static, ()> =
+        match Decoder::from_validated(include_bytes!("../conformance/valid/v2-checksum.mpic")) {
+            Some(decoder) => decoder,
+            None => panic!("invalid MPIC file"),
+        };
+    const INFO: ImageInfo = DECODER.info();
+    assert_eq!((INFO.width(), INFO.height()), (20, 20));
+    assert_eq!(DECODER.verify(), Ok(()));
+
+    for (name, blob) in conformance_valid() {
+        let decoder = Decoder::<()>::from_validated(&blob).unwrap();
+        let expected = Decoder::<()>::new_strict(&blob).unwrap();
+        assert_eq!(decoder.info(), expected.info(), "{name}");
+        assert!(decoder.metadata().eq(expected.metadata()), "{name}");
+        assert_eq!(decoder.decode(), expected.decode(), "{name}");
+    }
+
+    // An invalid header or a truncated metadata section is reported instead of panicking.
+    let blob = include_bytes!("../conformance/valid/v2-checksum.mpic");
+    for len in [0, 8, 9, 12, DECODER.data_offset() - 1] {
+        assert!(
+            Decoder::<()>::from_validated(&blob[..len]).is_none(),
+            "{len}"
+        );
+    }
+    assert!(Decoder::<()>::from_validated(&blob[..DECODER.data_offset()]).is_some());
+    assert!(
+        Decoder::<()>::from_validated(include_bytes!("../conformance/invalid/bad-magic.mpic"))
+            .is_none()
+    );
+}
+
+#[test]
+#[cfg(feature = "capi")]
+fn capi() {
+    use crate::capi::*;
+    use core::ptr;
+
+    let width = 13;
+    let height = 11;
+    let data = gradient_image(width, height);
+    let mut written = 0;
+    let mut encoded = alloc::vec![0; 16];
+    unsafe {
+        assert_eq!(
+            mpic_encode(
+                data.as_ptr(),
+                data.len(),
+                width,
+                height,
+                encoded.as_mut_ptr(),
+                encoded.len(),
+                &mut written,
+            ),
+            MpicStatus::BufferTooSmall
+        );
+        encoded.resize(written, 0);
+        assert_eq!(
+            mpic_encode(
+                data.as_ptr(),
+                data.len(),
+                width,
+                height,
+                encoded.as_mut_ptr(),
+                encoded.len(),
+                &mut written,
+            ),
+            MpicStatus::Ok
+        );
+        assert_eq!(
+            mpic_encode(
+                data.as_ptr(),
+                data.len() - 1,
+                width,
+                height,
+                encoded.as_mut_ptr(),
+                encoded.len(),
+                &mut written,
+            ),
+            MpicStatus::InvalidInput
+        );
+    }
+    assert_eq!(
+        encoded,
+        Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap()
+    );
+
+    let mut decoder = core::mem::MaybeUninit::<MpicDecoder>::uninit();
+    let mut info = core::mem::MaybeUninit::<MpicInfo>::uninit();
+    let decoder = unsafe {
+        assert_eq!(
+            mpic_decoder_new(encoded.as_ptr(), encoded.len(), decoder.as_mut_ptr()),
+            MpicStatus::Ok
+        );
+        assert_eq!(
+            mpic_info(decoder.as_ptr(), info.as_mut_ptr()),
+            MpicStatus::Ok
+        );
+        decoder.assume_init()
+    };
+    assert_eq!(
+        unsafe { info.assume_init() },
+        MpicInfo {
+            width,
+            height,
+            version: Version::V1.0
+        }
+    );
+
+    let expected = Decoder::<()>::new(&encoded)
+        .unwrap()
+        .decode_rgb565(true)
+        .unwrap();
+    let mut pixels = alloc::vec![0u16; (width * height) as usize];
+    unsafe {
+        assert_eq!(
+            mpic_decode_rgb565(&decoder, pixels.as_mut_ptr(), pixels.len() - 1, true),
+            MpicStatus::BufferTooSmall
+        );
+        assert_eq!(
+            mpic_decode_rgb565(&decoder, pixels.as_mut_ptr(), pixels.len(), true),
+            MpicStatus::Ok
+        );
+    }
+    assert_eq!(pixels, expected);
+
+    // Errors
+    let mut blob = encoded.clone();
+    blob[1] = b'M';
+    let mut other = decoder;
+    unsafe {
+        assert_eq!(
+            mpic_decoder_new(blob.as_ptr(), blob.len(), &mut other),
+            MpicStatus::BadMagic
+        );
+        assert_eq!(
+            mpic_decoder_new(ptr::null(), 0, &mut other),
+            MpicStatus::TruncatedHeader
+        );
+        assert_eq!(
+            mpic_decoder_new(ptr::null(), 1, &mut other),
+            MpicStatus::InvalidInput
+        );
+        assert_eq!(
+            mpic_decoder_new(encoded.as_ptr(), encoded.len(), ptr::null_mut()),
+            MpicStatus::InvalidInput
+        );
+        assert_eq!(
+            mpic_info(ptr::null(), info.as_mut_ptr()),
+            MpicStatus::InvalidInput
+        );
+    }
+    let mut truncated = decoder;
+    unsafe {
+        assert_eq!(
+            mpic_decoder_new(encoded.as_ptr(), encoded.len() - 3, &mut truncated),
+            MpicStatus::Ok
+        );
+        assert_eq!(
+            mpic_decode_rgb565(&truncated, pixels.as_mut_ptr(), pixels.len(), false),
+            MpicStatus::InvalidChunk
+        );
+    }
+
+    // A single chunk
+    let chunk = include_bytes!("../conformance/valid/v1-lz-long.mpic");
+    let (mut y, mut u, mut v) = ([0; 64], [0; 64], [0; 64]);
+    unsafe {
+        assert_eq!(
+            mpic_decode_chunk(
+                chunk[10..].as_ptr(),
+                chunk.len() - 10,
+                y.as_mut_ptr(),
+                u.as_mut_ptr(),
+                v.as_mut_ptr()
+            ),
+            MpicStatus::Ok
+        );
+        assert_eq!(
+            mpic_decode_chunk(
+                chunk[10..].as_ptr(),
+                chunk.len() - 11,
+                y.as_mut_ptr(),
+                u.as_mut_ptr(),
+                v.as_mut_ptr()
+            ),
+            MpicStatus::InvalidChunk
+        );
+    }
+    assert_eq!(
+        (y, u, v),
+        Decoder::<()>::decode_chunk(&chunk[10..]).unwrap()
+    );
+}
+
+#[test]
+#[cfg(feature = "wasm")]
+fn wasm_bindings() {
+    use crate::wasm;
+
+    let width = 13;
+    let height = 11;
+    let data = gradient_image(width, height);
+    let rgba = data
+        .chunks(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0x80])
+        .collect::<VecA<_>>();
+    let encoded = wasm::encode_rgba(&rgba, width, height).ok().unwrap();
+    assert_eq!(
+        encoded,
+        Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap()
+    );
+
+    let image = wasm::decode_rgba(&encoded).ok().unwrap();
+    assert_eq!((image.width(), image.height()), (width, height));
+    assert_eq!(
+        image.data().0,
+        Decoder::<()>::new(&encoded).unwrap().decode_rgba().unwrap()
+    );
+}
+
+#[test]
+fn metadata() {
+    let width = 12;
+    let height = 10;
+    let data = gradient_image(width, height);
+    let plain = Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    assert_eq!(plain[8], Version::V1.0);
+    assert_eq!(Decoder::<()>::new(&plain).unwrap().metadata().count(), 0);
+
+    let records = [
+        Metadata::Title("Gradient"),
+        Metadata::Author("mpic"),
+        Metadata::IccProfile(&[1, 2, 3, 4]),
+        Metadata::Custom {
+            key: "app.id",
+            value: b"\x00\x01",
+        },
+        Metadata::Other {
+            tag: *b"zzzz",
+            data: &[],
+        },
+    ];
+    let options = EncoderOptions::new().with_metadata(&records);
+    let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+    assert_eq!(encoded[8], Version::V2.0);
+
+    let decoder = Decoder::<()>::new_strict(&encoded).unwrap();
+    assert!(decoder.metadata().eq(records.iter().copied()));
+    assert_eq!(
+        decoder.decode(),
+        Decoder::<()>::new(&plain).unwrap().decode()
+    );
+
+    // The image data is the same as without metadata.
+    assert!(encoded.ends_with(&plain[9..]));
+
+    // Known tags with malformed payloads are reported as they are.
+    let mut blob = encoded.clone();
+    blob[13 + 8] = 0xFF;
+    let first = Decoder::<()>::new_strict(&blob).unwrap().metadata().next();
+    assert_eq!(
+        first,
+        Some(Metadata::Other {
+            tag: Metadata::TAG_TITLE,
+            data: b"\xFFradient"
+        })
+    );
+
+    // The section must hold whole records.
+    let mut blob = encoded.clone();
+    blob[9] += 1;
+    assert!(Decoder::<()>::new_strict(&blob).is_err());
+    let mut blob = encoded.clone();
+    blob[13 + 7] = 0xFF;
+    assert_eq!(
+        Decoder::<()>::new_strict(&blob).err(),
+        Some(DecodeError::InvalidMetadata { offset: 13 })
+    );
+    assert_eq!(
+        Decoder::<()>::new_strict(&encoded[..12]).err(),
+        Some(DecodeError::InvalidMetadata { offset: 9 })
+    );
+
+    // Metadata requires version 2.
+    let options = options.with_version(Version::V1);
+    assert_eq!(
+        Encoder::encode_with_options(&data, width, height, &options),
+        Err(EncodeError::UnsupportedVersion(Version::V1))
+    );
+    let long_key = core::str::from_utf8(&[b'k'; 256]).unwrap();
+    let records = [Metadata::Custom {
+        key: long_key,
+        value: &[],
+    }];
+    let options = EncoderOptions::new().with_metadata(&records);
+    assert_eq!(
+        Encoder::encode_with_options(&data, width, height, &options),
+        Err(EncodeError::InvalidMetadata)
+    );
+
+    // The records written by the encoder cannot be given, as they would be duplicated.
+    let checksums = [0; 8];
+    for record in [
+        Metadata::Thumbnail(&[0; 3]),
+        Metadata::Animation(&[]),
+        Metadata::RowOffsets(&[0; 4]),
+        Metadata::Checksum(&checksums),
+        Metadata::Other {
+            tag: Metadata::TAG_CHECKSUM,
+            data: &checksums,
+        },
+    ] {
+        assert!(record.is_reserved());
+        let records = [Metadata::Title("title"), record];
+        let options = EncoderOptions::new()
+            .with_metadata(&records)
+            .with_checksum(true);
+        assert_eq!(
+            Encoder::encode_with_options(&data, width, height, &options),
+            Err(EncodeError::InvalidMetadata)
+        );
+        let mut written = false;
+        assert_eq!(
+            Encoder::encode_to_writer_with_options(&data, width, height, &options, |_| {
+                written = true
+            }),
+            Err(EncodeError::InvalidMetadata)
+        );
+        assert!(!written);
+    }
+    assert!(!Metadata::Title("title").is_reserved());
+
+    // An empty section is allowed.
+    let options = EncoderOptions::new().with_version(Version::V2);
+    let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+    assert_eq!(&encoded[9..13], &[0; 4]);
+    assert_eq!(
+        Decoder::<()>::new_strict(&encoded)
+            .unwrap()
+            .metadata()
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn thumbnail() {
+    let width = 20;
+    let height = 12;
+    let data = gradient_image(width, height);
+    let plain = Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    assert!(Decoder::<()>::new(&plain).unwrap().thumbnail().is_none());
+
+    let records = [Metadata::Title("Gradient")];
+    let options = EncoderOptions::new()
+        .with_metadata(&records)
+        .with_thumbnail(true);
+    let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+    assert_eq!(encoded[8], Version::V2.0);
+    assert!(encoded.ends_with(&plain[9..]));
+
+    let decoder = Decoder::<()>::new_strict(&encoded).unwrap();
+    assert_eq!(decoder.metadata().count(), 2);
+    let thumbnail = decoder.thumbnail().unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (3, 2));
+    assert_eq!(thumbnail.get(3, 0), None);
+    assert_eq!(thumbnail.get(0, 2), None);
+
+    // Each pixel is close to the average of the decoded block.
+    let decoded = decoder.decode().unwrap();
+    let thumb_rgb = thumbnail.decode();
+    assert_eq!(thumb_rgb.len(), 3 * 2 * 3);
+    for by in 0..2 {
+        for bx in 0..3 {
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+            for y in by * 8..(by * 8 + 8).min(height) {
+                for x in bx * 8..(bx * 8 + 8).min(width) {
+                    let offset = ((y * width + x) * 3) as usize;
+                    for (sum, value) in sum.iter_mut().zip(&decoded[offset..offset + 3]) {
+                        *sum += *value as u32;
+                    }
+                    count += 1;
+                }
+            }
+            let offset = ((by * 3 + bx) * 3) as usize;
+            for (sum, value) in sum.iter().zip(&thumb_rgb[offset..offset + 3]) {
+                let diff = (*sum / count) as i32 - *value as i32;
+                assert!(diff.abs() <= 12, "({}, {}) {}", bx, by, diff);
+            }
+        }
+    }
+
+    let options = options.with_version(Version::V1);
+    assert_eq!(
+        Encoder::encode_with_options(&data, width, height, &options),
+        Err(EncodeError::UnsupportedVersion(Version::V1))
+    );
+}
+
+#[test]
+fn checksum() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    let mut crc = Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), 0xCBF4_3926);
+
+    let width = 24;
+    let height = 20;
+    let data = gradient_image(width, height);
+    let plain = Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    let decoder = Decoder::<()>::new(&plain).unwrap();
+    assert!(!decoder.has_checksum());
+    assert_eq!(decoder.verify(), Err(DecodeError::MissingChecksum));
+    assert_eq!(
+        decoder.with_verification(true).decode(),
+        Err(DecodeError::MissingChecksum)
+    );
+
+    let records = [Metadata::Title("Gradient")];
+    let options = EncoderOptions::new()
+        .with_metadata(&records)
+        .with_checksum(true);
+    let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+    assert!(encoded.ends_with(&plain[9..]));
+    // The checksums and row offsets filled in afterwards are the same as the ones computed while writing.
+    for options in [
+        options,
+        options.with_row_offsets(true),
+        options.with_checksum(false).with_row_offsets(true),
+    ] {
+        let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+        let mut streamed = VecA::new();
+        Encoder::encode_to_writer_with_options(&data, width, height, &options, |v| {
+            streamed.extend_from_slice(v)
+        })
+        .unwrap();
+        assert_eq!(streamed, encoded);
+    }
+    let decoder = Decoder::<()>::new_verified(&encoded).unwrap();
+    assert!(decoder.has_checksum());
+    let expected = decoder.decode().unwrap();
+    let decoder = decoder.with_verification(true);
+    assert_eq!(decoder.decode().unwrap(), expected);
+    let record = decoder.data_offset() - 8 - 4 * 4;
+
+    // A changed byte in the second row is found before the row is decoded.
+    let chunks = decoder.chunks().map(Result::unwrap).collect::<VecA<_>>();
+    let row = chunks[3].offset;
+    let mut blob = encoded.clone();
+    let last = chunks[3].offset + chunks[3].payload.len();
+    blob[last] ^= 0x01;
+    let error = DecodeError::ChecksumMismatch {
+        row: 1,
+        offset: row,
+    };
+    assert_eq!(Decoder::<()>::new(&blob).unwrap().verify(), Err(error));
+    assert!(Decoder::<()>::new_verified(&blob).is_err());
+    let mut output = vec![0; expected.len()];
+    let decoder = Decoder::<()>::new(&blob).unwrap().with_verification(true);
+    assert_eq!(decoder.decode_to_slice(&mut output), Err(error));
+    let first_row = (width * 8 * 3) as usize;
+    assert_eq!(output[..first_row], expected[..first_row]);
+    assert!(output[first_row..].iter().all(|&v| v == 0));
+    assert_eq!(error.offset(), Some(row));
+    assert_eq!(
+        error.to_string(),
+        format!("row 1 checksum mismatch at offset {row}")
+    );
+
+    let mut blob = encoded.clone();
+    blob[record - 1] = b'g';
+    let decoder = Decoder::<()>::new_strict(&blob).unwrap();
+    assert_eq!(
+        decoder.verify(),
+        Err(DecodeError::HeaderChecksumMismatch { offset: record })
+    );
+
+    // Transformations keep the checksums.
+    let flipped = transform::flip_horizontal(&encoded).unwrap();
+    assert!(
+        Decoder::<()>::new_verified(&flipped)
+            .unwrap()
+            .has_checksum()
+    );
+    let cropped = transform::crop(&encoded, 8, 8, 16, 12).unwrap();
+    assert!(Decoder::<()>::new_verified(&cropped).is_ok());
+
+    let frames = [
+        Frame {
+            data: &data,
+            duration: 100,
+        },
+        Frame {
+            data: &gradient_image(width, height)
+                .iter()
+                .map(|v| !v)
+                .collect::<VecA<_>>(),
+            duration: 100,
+        },
+    ];
+    let animation = Encoder::encode_animation(&frames, width, height, 0, &options).unwrap();
+    let decoder = Decoder::<()>::new_verified(&animation).unwrap();
+    assert_eq!(decoder.animation().unwrap().frame_count(), 2);
+    assert_eq!(decoder.with_verification(true).decode().unwrap(), expected);
+}
+
+#[test]
+fn lenient_decode() {
+    let width = 24;
+    let height = 24;
+    let data = gradient_image(width, height);
+    let options = EncoderOptions::new().with_row_offsets(true);
+    let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+    let decoder = Decoder::<()>::new_strict(&encoded).unwrap();
+    assert!(decoder.has_row_offsets());
+    let expected = decoder.decode().unwrap();
+
+    // The offsets of the largest images do not fit in 32 bits, which is checked before the data.
+    assert_eq!(
+        Encoder::encode_with_options(&[], 65535, 65535, &options),
+        Err(EncodeError::InvalidSize {
+            width: 65535,
+            height: 65535
+        })
+    );
+    assert_eq!(
+        Encoder::encode_with_options(&[], 65535, 8, &options),
+        Err(EncodeError::InsufficientData {
+            len: 0,
+            required: 65535 * 8 * 3
+        })
+    );
+    let chunks = decoder.chunks().map(Result::unwrap).collect::<VecA<_>>();
+
+    let row_len = (width * 8 * 3) as usize;
+    let block = |image: &[u8], index: u32| {
+        let (x, y) = (index % 3 * 8, index / 3 * 8);
+        (y..y + 8)
+            .flat_map(|y| {
+                let offset = ((y * width + x) * 3) as usize;
+                image[offset..offset + 24].to_vec()
+            })
+            .collect::<VecA<_>>()
+    };
+    let gray = MpicRgb666::from_yuv(MpicYuv666::new(32, 32, 32));
+    let is_neutral = |image: &[u8], index: u32| {
+        block(image, index)
+            .chunks(3)
+            .all(|p| p == [gray.r8(), gray.g8(), gray.b8()])
+    };
+
+    // A broken chunk size loses the rest of its row only.
+    let mut blob = encoded.clone();
+    blob[chunks[4].offset] = 0xFF;
+    let decoder = Decoder::<()>::new(&blob).unwrap();
+    assert_eq!(
+        decoder.decode().err(),
+        Some(DecodeError::InvalidChunkLength {
+            index: 4,
+            offset: chunks[4].offset
+        })
+    );
+    let decoded = decoder.with_lenient(true).decode().unwrap();
+    assert_eq!(decoded[..row_len], expected[..row_len]);
+    assert_eq!(block(&decoded, 3), block(&expected, 3));
+    assert!(is_neutral(&decoded, 4));
+    assert!(is_neutral(&decoded, 5));
+    assert_eq!(decoded[row_len * 2..], expected[row_len * 2..]);
+
+    // Without the table, all the following blocks are lost.
+    let plain = Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    let mut blob = plain.clone();
+    blob[chunks[4].offset - chunks[0].offset + 9] = 0xFF;
+    let decoded = Decoder::<()>::new(&blob)
+        .unwrap()
+        .with_lenient(true)
+        .decode()
+        .unwrap();
+    assert_eq!(decoded[..row_len], expected[..row_len]);
+    assert!((4..9).all(|index| is_neutral(&decoded, index)));
+    let decoder = Decoder::<()>::new(&blob).unwrap();
+    assert_eq!(
+        decoder.with_verification(true).with_lenient(true).decode(),
+        Err(DecodeError::MissingChecksum)
+    );
+
+    // With checksums, damage that still decodes is found, and the whole row is filled.
+    let options = options.with_checksum(true);
+    let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+    let decoder = Decoder::<()>::new_verified(&encoded).unwrap();
+    let chunks = decoder.chunks().map(Result::unwrap).collect::<VecA<_>>();
+    let mut blob = encoded.clone();
+    blob[chunks[7].offset + 1] ^= 0x01;
+    let decoder = Decoder::<()>::new(&blob)
+        .unwrap()
+        .with_verification(true)
+        .with_lenient(true);
+    let decoded = decoder.decode().unwrap();
+    assert_eq!(decoded[..row_len * 2], expected[..row_len * 2]);
+    assert!((6..9).all(|index| is_neutral(&decoded, index)));
+
+    // Transformations keep the table.
+    let flipped = transform::flip_vertical(&encoded).unwrap();
+    let decoder = Decoder::<()>::new_verified(&flipped).unwrap();
+    assert!(decoder.has_row_offsets());
+    let mut blob = flipped.clone();
+    let chunks = decoder.chunks().map(Result::unwrap).collect::<VecA<_>>();
+    blob[chunks[1].offset] = 0;
+    let decoded = Decoder::<()>::new(&blob)
+        .unwrap()
+        .with_lenient(true)
+        .decode()
+        .unwrap();
+    let expected = decoder.decode().unwrap();
+    assert!(is_neutral(&decoded, 2));
+    assert_eq!(decoded[row_len..], expected[row_len..]);
+}
+
+/// Valid files of every kind, to be broken by [`mutate`].
+pub(crate) fn untrusted_corpus() -> VecA<VecA<u8>> {
+    let (width, height) = (21, 13);
+    let data = gradient_image(width, height);
+    let inverted = data.iter().map(|v| !v).collect::<VecA<_>>();
+    let records = [Metadata::Title("Corpus"), Metadata::Author("mpic")];
+    let full = EncoderOptions::new()
+        .with_metadata(&records)
+        .with_thumbnail(true)
+        .with_row_offsets(true)
+        .with_checksum(true);
+    let plain = Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    let aligned = gradient_image(16, 8);
+    let frames = [
+        Frame {
+            data: &data,
+            duration: 10,
+        },
+        Frame {
+            data: &inverted,
+            duration: 10,
+        },
+        Frame {
+            data: &data,
+            duration: 10,
+        },
+    ];
+    let mut atlas = AtlasBuilder::new();
+    let image = atlas.add_image("image", &plain).unwrap();
+    atlas.add_region("region", image, 8, 0, 8, 8).unwrap();
+    vec![
+        plain.clone(),
+        Encoder::encode_with_options(&data, width, height, &full).unwrap(),
+        Encoder::encode_with_options(&aligned, 16, 8, &EncoderOptions::new()).unwrap(),
+        Encoder::encode_animation(&frames, width, height, 0, &full).unwrap(),
+        atlas.build().unwrap(),
+    ]
+}
+
+/// Break a valid file in the way chosen by `random`.
+pub(crate) fn mutate(blob: &mut VecA<u8>, random: &mut impl FnMut() -> u32) {
+    if blob.is_empty() {
+        return;
+    }
+    let position = |random: &mut dyn FnMut() -> u32, len: usize| random() as usize % len;
+    match random() % 6 {
+        0 => {
+            let at = position(random, blob.len());
+            blob[at] ^= 1 << (random() % 8);
+        }
+        1 => {
+            let at = position(random, blob.len());
+            blob[at] = random() as u8;
+        }
+        2 => {
+            let len = position(random, blob.len());
+            blob.truncate(len);
+        }
+        3 => {
+            let at = position(random, blob.len());
+            blob.insert(at, random() as u8);
+        }
+        4 => {
+            let at = position(random, blob.len());
+            blob.remove(at);
+        }
+        _ => {
+            // Sizes and offsets are the most sensitive.
+            let at = position(random, blob.len().min(64));
+            blob[at] = [0, 0x7F, 0x80, 0xFF][random() as usize % 4];
+        }
+    }
+}
+
+/// Call every decoding function on untrusted data. None of them may panic.
+pub(crate) fn decode_untrusted(blob: &[u8]) {
+    let _ = Decoder::<()>::new_strict(blob);
+    let _ = Decoder::<()>::new_verified(blob);
+    let _ = Decoder::<()>::decode_chunk(blob);
+    if let Some(atlas) = Atlas::<()>::new(blob) {
+        for entry in atlas.entries() {
+            let _ = entry.name();
+            let _ = entry.decode();
+        }
+    }
+    let Some(decoder) = Decoder::<()>::new(blob) else {
+        return;
+    };
+    let info = decoder.info();
+    let _ = decoder.validate();
+    let _ = decoder.verify();
+    let _ = decoder.metadata().count();
+    if let Some(thumbnail) = decoder.thumbnail() {
+        let _ = thumbnail.decode();
+    }
+    // A few bytes can claim an image of 4 gigapixels, so only small images are decoded in full.
+    let mut small = [0; 3];
+    let _ = decoder.decode_to_slice(&mut small);
+    if info.width() * info.height() > 64 * 64 {
+        return;
+    }
+    for (verify, lenient) in [(false, false), (false, true), (true, true)] {
+        let decoder = Decoder::<()>::new(blob)
+            .unwrap()
+            .with_verification(verify)
+            .with_lenient(lenient);
+        let _ = decoder.decode();
+        let _ = decoder.decode_rgba();
+        let _ = decoder.decode_rgb565(true);
+        for scale in [Scale::Half, Scale::Quarter, Scale::Eighth] {
+            let _ = decoder.decode_scaled(scale);
+        }
+    }
+    if let Some(animation) = decoder.animation() {
+        for index in 0..animation.frame_count().min(4) {
+            let _ = animation.decode_frame(index);
+        }
+    }
+}
+
+#[test]
+fn untrusted_input() {
+    let mut random = lcg(0x2468_ACE0);
+    for blob in untrusted_corpus() {
+        decode_untrusted(&blob);
+        for _ in 0..300 {
+            let mut blob = blob.clone();
+            for _ in 0..1 + random() % 3 {
+                mutate(&mut blob, &mut random);
+            }
+            decode_untrusted(&blob);
+        }
+    }
+    for len in 0..200 {
+        let blob = (0..len).map(|_| random() as u8).collect::<VecA<_>>();
+        decode_untrusted(&blob);
+    }
+}
+
+/// A random image in RGB888 format made of a smooth gradient with a little noise, for round trip tests.
+pub(crate) fn random_image(width: u32, height: u32, random: &mut impl FnMut() -> u32) -> VecA<u8> {
+    let base = [0; 3].map(|_| (random() % 256) as i32);
+    let slope = [0; 6].map(|_| (random() % 7) as i32 - 3);
+    let noise = 1 + random() % 8;
+    let mut data = VecA::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            for (channel, base) in base.iter().enumerate() {
+                let value = base
+                    + slope[channel * 2] * (x % 64)
+                    + slope[channel * 2 + 1] * (y % 64)
+                    + (random() % noise) as i32;
+                data.push(value.clamp(0, 255) as u8);
+            }
+        }
+    }
+    data
+}
+
+/// Number of seeds of [`round_trip_image`] to test.
+pub(crate) const ROUND_TRIP_SEEDS: u32 = 48;
+
+/// A random image for round trip tests, which only depends on the seed so that a failure can be reproduced from it.
+///
+/// The first seeds give the edge cases of the size, every 16th seed a line of up to 65535 pixels,
+/// and the others random sizes of up to 40x40.
+pub(crate) fn round_trip_image(seed: u32) -> (u32, u32, VecA<u8>) {
+    const EDGE_CASES: [(u32, u32); 8] = [
+        (1, 1),
+        (7, 9),
+        (8, 8),
+        (8, 15),
+        (9, 1),
+        (1, 17),
+        (65535, 1),
+        (1, 65535),
+    ];
+    let mut random = lcg(seed);
+    let (width, height) = match EDGE_CASES.get(seed as usize) {
+        Some(&size) => size,
+        None if seed.is_multiple_of(16) => {
+            let long = 1 + random() % 65535;
+            if random().is_multiple_of(2) {
+                (long, 1)
+            } else {
+                (1, long)
+            }
+        }
+        None => (1 + random() % 40, 1 + random() % 40),
+    };
+    (width, height, random_image(width, height, &mut random))
+}
+
+#[test]
+fn random_round_trip() {
+    let allowed_error = 24;
+
+    for seed in 0..ROUND_TRIP_SEEDS {
+        let (width, height, data) = round_trip_image(seed);
+        let mut decoded = None;
+        for level in [
+            CompressionLevel::Fast,
+            CompressionLevel::Default,
+            CompressionLevel::Best,
+        ] {
+            let context = format!("seed {seed} {level:?} {width}x{height}");
+            let options = EncoderOptions::new().with_level(level);
+            let encoded = Encoder::encode_with_options(&data, width, height, &options)
+                .unwrap_or_else(|err| panic!("{context}: {err}"));
+            let decoder = Decoder::<()>::new_strict(&encoded)
+                .unwrap_or_else(|err| panic!("{context}: {err}"));
+            assert_eq!(
+                (decoder.info().width(), decoder.info().height()),
+                (width, height),
+                "{context}"
+            );
+            let rgb = decoder
+                .decode()
+                .unwrap_or_else(|err| panic!("{context}: {err}"));
+            let rgba = decoder
+                .decode_rgba()
+                .unwrap_or_else(|err| panic!("{context}: {err}"));
+            assert_eq!(rgb.len(), data.len(), "{context}");
+            assert_eq!(rgba.len(), data.len() / 3 * 4, "{context}");
+            for (index, (expected, actual)) in data.chunks(3).zip(rgb.chunks(3)).enumerate() {
+                let error = expected
+                    .iter()
+                    .zip(actual.iter())
+                    .map(|(&lhs, &rhs)| (lhs as isize - rhs as isize).abs())
+                    .max()
+                    .unwrap();
+                assert!(
+                    error <= allowed_error,
+                    "{context} at ({}, {}): {:?} => {:?}",
+                    index as u32 % width,
+                    index as u32 / width,
+                    expected,
+                    actual,
+                );
+                assert_eq!(
+                    &rgba[index * 4..index * 4 + 4],
+                    &[actual[0], actual[1], actual[2], 0xFF],
+                    "{context}"
+                );
+            }
+
+            // The compression level only changes how the planes are compressed.
+            match &decoded {
+                Some(decoded) => assert_eq!(decoded, &rgb, "{context}"),
+                None => decoded = Some(rgb),
+            }
+        }
+    }
+}
+
+#[test]
+fn scaled_decode() {
+    let width = 21;
+    let height = 13;
+    let data = gradient_image(width, height);
+    let encoded =
+        Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+    let decoder = Decoder::<()>::new(&encoded).unwrap();
+    let full = decoder.decode().unwrap();
+    assert_eq!(decoder.decode_scaled(Scale::Full).unwrap(), full);
+    assert_eq!(
+        decoder.decode_rgb565_scaled(Scale::Full, true).unwrap(),
+        decoder.decode_rgb565(true).unwrap()
+    );
+
+    for scale in [Scale::Half, Scale::Quarter, Scale::Eighth] {
+        let factor = scale.factor();
+        let info = scale.apply(decoder.info());
+        assert_eq!(info.width(), width.div_ceil(factor));
+        assert_eq!(info.height(), height.div_ceil(factor));
+
+        let scaled = decoder.decode_scaled(scale).unwrap();
+        assert_eq!(scaled.len(), (info.width() * info.height() * 3) as usize);
+        let rgb565 = decoder.decode_rgb565_scaled(scale, false).unwrap();
+        assert_eq!(rgb565.len(), (info.width() * info.height()) as usize);
+
+        // Each pixel is close to the average of the pixels it covers.
+        for sy in 0..info.height() {
+            for sx in 0..info.width() {
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for y in sy * factor..((sy + 1) * factor).min(height) {
+                    for x in sx * factor..((sx + 1) * factor).min(width) {
+                        let offset = ((y * width + x) * 3) as usize;
+                        for (sum, value) in sum.iter_mut().zip(&full[offset..offset + 3]) {
+                            *sum += *value as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                let offset = ((sy * info.width() + sx) * 3) as usize;
+                for (sum, value) in sum.iter().zip(&scaled[offset..offset + 3]) {
+                    let diff = (*sum / count) as i32 - *value as i32;
+                    assert!(diff.abs() <= 8, "{:?} ({}, {}) {}", scale, sx, sy, diff);
+                }
+            }
+        }
+
+        let mut short = vec![0; scaled.len() - 1];
+        assert_eq!(
+            decoder.decode_scaled_to_slice(scale, &mut short),
+            Err(DecodeError::InvalidInput)
+        );
+    }
+}
+
+#[test]
+fn decode_partial_bottom_row() {
+    // The partial blocks of the bottom row are placed at the bottom, even if the width and the height differ.
+    for (width, height) in [(24, 13), (13, 24), (17, 9)] {
+        let data = (0..width * height * 3)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<VecA<_>>();
+        let options = crate::EncoderOptions::new();
+        let encoded = crate::Encoder::encode_with_options(&data, width, height, &options).unwrap();
+        let decoder = crate::Decoder::<()>::new(&encoded).unwrap();
+        let rgb = decoder.decode().unwrap();
+        let rgba = decoder.decode_rgba().unwrap();
+        assert!(
+            rgb.chunks(3)
+                .zip(rgba.chunks(4))
+                .all(|(rgb, rgba)| rgb == &rgba[..3]),
+            "{width}x{height}"
+        );
+    }
+}
+
 macro_rules! test_compress {
     ($ident:ident, $source:expr, $size_is_compressed:ident) => {
         #[test]
@@ -58,7 +2225,7 @@ macro_rules! test_compress {
             let source = source.into_array().unwrap();
 
             let mut vec1 = VecH::new();
-            chunk::compress_fast(&source, &mut vec1);
+            chunk::compress_fast(&source, None, &mut vec1);
 
             $size_is_compressed(vec1.len());
 
@@ -72,7 +2239,7 @@ macro_rules! test_compress {
                 CompressionLevel::Best,
             ] {
                 let mut vec3 = VecH::new();
-                chunk::compress_lazy(&source, &mut vec3, level);
+                chunk::compress_lazy(&source, None, &mut vec3, level);
 
                 $size_is_compressed(vec3.len());
 
@@ -334,3 +2501,978 @@ pub(crate) fn fib_str(a: u8, b: u8, limit: usize) -> VecA<u8> {
     x.truncate(limit);
     x
 }
+
+/// Transform RGB888 pixels by mapping each position of the output to the source.
+fn transform_pixels<F>(src: &[u8], width: u32, new_width: u32, new_height: u32, f: F) -> VecA<u8>
+where
+    F: Fn(u32, u32) -> (u32, u32),
+{
+    let mut output = VecA::new();
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let (sx, sy) = f(x, y);
+            let offset = ((sy * width + sx) * 3) as usize;
+            output.extend_from_slice(&src[offset..offset + 3]);
+        }
+    }
+    output
+}
+
+#[test]
+fn lossless_transform() {
+    let width = 20;
+    let height = 16;
+    let data = gradient_image(width, height);
+    let records = [Metadata::Title("Gradient")];
+    let options = EncoderOptions::new()
+        .with_metadata(&records)
+        .with_thumbnail(true);
+    let encoded = Encoder::encode_with_options(&data, width, height, &options).unwrap();
+    let decoder = Decoder::<()>::new(&encoded).unwrap();
+    let pixels = decoder.decode().unwrap();
+    let decode = |blob: &[u8]| {
+        let decoder = Decoder::<()>::new_strict(blob).unwrap();
+        (
+            decoder.info().width(),
+            decoder.info().height(),
+            decoder.decode().unwrap(),
+        )
+    };
+
+    let rotated = transform::rotate(&encoded, Rotation::Deg90).unwrap();
+    let expected = transform_pixels(&pixels, width, height, width, |x, y| (y, height - 1 - x));
+    assert_eq!(decode(&rotated), (height, width, expected));
+
+    let flipped = transform::flip_vertical(&encoded).unwrap();
+    let expected = transform_pixels(&pixels, width, width, height, |x, y| (x, height - 1 - y));
+    assert_eq!(decode(&flipped), (width, height, expected));
+
+    // The width is not a multiple of 8.
+    assert_eq!(
+        transform::rotate(&encoded, Rotation::Deg270),
+        Err(TransformError::Unaligned)
+    );
+    assert_eq!(
+        transform::rotate(&encoded, Rotation::Deg180),
+        Err(TransformError::Unaligned)
+    );
+    assert_eq!(
+        transform::flip_horizontal(&encoded),
+        Err(TransformError::Unaligned)
+    );
+
+    // Rotating back gives the same pixels, with the metadata and the thumbnail.
+    let blob = transform::rotate(&rotated, Rotation::Deg270).unwrap();
+    assert_eq!(decode(&blob), decode(&encoded));
+    let decoder = Decoder::<()>::new(&blob).unwrap();
+    assert_eq!(decoder.metadata().next(), Some(Metadata::Title("Gradient")));
+    assert_eq!(
+        decoder.thumbnail().unwrap().decode(),
+        Decoder::<()>::new(&encoded)
+            .unwrap()
+            .thumbnail()
+            .unwrap()
+            .decode()
+    );
+
+    let cropped = transform::crop(&encoded, 8, 8, 11, 5).unwrap();
+    let expected = transform_pixels(&pixels, width, 11, 5, |x, y| (x + 8, y + 8));
+    assert_eq!(decode(&cropped), (11, 5, expected));
+    assert_eq!(
+        transform::crop(&encoded, 4, 8, 8, 8),
+        Err(TransformError::Unaligned)
+    );
+    assert_eq!(
+        transform::crop(&encoded, 8, 8, 13, 8),
+        Err(TransformError::OutOfBounds)
+    );
+    assert_eq!(
+        transform::crop(&encoded, 0, 0, 0, 8),
+        Err(TransformError::OutOfBounds)
+    );
+
+    // Version 0 is kept if possible.
+    let data = gradient_image(16, 8);
+    let encoded = Encoder::encode_with_options(&data, 16, 8, &EncoderOptions::new()).unwrap();
+    let rotated = transform::rotate(&encoded, Rotation::Deg180).unwrap();
+    assert_eq!(rotated[8], Version::V0.0);
+    let cropped = transform::crop(&encoded, 8, 0, 7, 8).unwrap();
+    assert_eq!(cropped[8], Version::V1.0);
+    assert_eq!(
+        transform::crop(&encoded[..20], 0, 0, 8, 8).err(),
+        Some(TransformError::Decode(DecodeError::TruncatedChunk {
+            index: 0,
+            offset: 9
+        }))
+    );
+}
+
+#[test]
+fn concat_and_split() {
+    let encode = |width: u32, height: u32, thumbnail: bool| {
+        let data = gradient_image(width, height);
+        let options = EncoderOptions::new().with_thumbnail(thumbnail);
+        Encoder::encode_with_options(&data, width, height, &options).unwrap()
+    };
+    let decode = |blob: &[u8]| {
+        let decoder = Decoder::<()>::new_strict(blob).unwrap();
+        (
+            decoder.info().width(),
+            decoder.info().height(),
+            decoder.decode().unwrap(),
+        )
+    };
+
+    let left = encode(16, 13, true);
+    let right = encode(7, 13, true);
+    let (_, _, left_pixels) = decode(&left);
+    let (_, _, right_pixels) = decode(&right);
+
+    let joined = transform::hconcat(&[&left, &right]).unwrap();
+    let expected = left_pixels
+        .chunks(16 * 3)
+        .zip(right_pixels.chunks(7 * 3))
+        .flat_map(|(left, right)| left.iter().chain(right).copied())
+        .collect::<VecA<_>>();
+    assert_eq!(decode(&joined), (23, 13, expected.clone()));
+    let thumbnail = Decoder::<()>::new(&joined).unwrap().thumbnail().unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (3, 2));
+
+    // Splitting gives back the original tiles.
+    let tiles = transform::split(&joined, 16, 8).unwrap();
+    assert_eq!(tiles.len(), 4);
+    let sizes = tiles
+        .iter()
+        .map(|tile| {
+            let (width, height, _) = decode(tile);
+            (width, height)
+        })
+        .collect::<VecA<_>>();
+    assert_eq!(sizes, [(16, 8), (7, 8), (16, 5), (7, 5)]);
+    let top = transform::hconcat(&[&tiles[0], &tiles[1]]).unwrap();
+    let bottom = transform::hconcat(&[&tiles[2], &tiles[3]]).unwrap();
+    let rejoined = transform::vconcat(&[&top, &bottom]).unwrap();
+    assert_eq!(decode(&rejoined), (23, 13, expected));
+    assert_eq!(
+        transform::split(&joined, 12, 8),
+        Err(TransformError::Unaligned)
+    );
+
+    // Partial blocks must stay on the right or bottom edge.
+    assert_eq!(
+        transform::hconcat(&[&right, &left]),
+        Err(TransformError::Unaligned)
+    );
+    assert_eq!(
+        transform::vconcat(&[&top, &bottom, &top]),
+        Err(TransformError::Unaligned)
+    );
+    assert_eq!(
+        transform::vconcat(&[&left, &right]),
+        Err(TransformError::SizeMismatch)
+    );
+    assert_eq!(transform::hconcat(&[]), Err(TransformError::SizeMismatch));
+
+    // Without thumbnails in every image, the result has none.
+    let plain = encode(8, 13, false);
+    let joined = transform::hconcat(&[&left, &plain]).unwrap();
+    assert!(Decoder::<()>::new(&joined).unwrap().thumbnail().is_none());
+    assert_eq!(joined[8], Version::V2.0);
+}
+
+#[test]
+fn animation() {
+    let width = 20;
+    let height = 12;
+    let first = gradient_image(width, height);
+    let mut second = first.clone();
+    for y in 0..4 {
+        for x in 0..4 {
+            let index = ((y + 2) * width as usize + x + 10) * 3;
+            second[index..index + 3].copy_from_slice(&[255, 0, 0]);
+        }
+    }
+    let frames = [
+        Frame {
+            data: &first,
+            duration: 100,
+        },
+        Frame {
+            data: &second,
+            duration: 50,
+        },
+        Frame {
+            data: &second,
+            duration: 200,
+        },
+    ];
+    let options = EncoderOptions::new();
+    let encoded = Encoder::encode_animation(&frames, width, height, 3, &options).unwrap();
+    let decoder = Decoder::<()>::new_strict(&encoded).unwrap();
+    let still = |data: &[u8]| {
+        let encoded = Encoder::encode_with_options(data, width, height, &options).unwrap();
+        Decoder::<()>::new(&encoded).unwrap().decode().unwrap()
+    };
+
+    // Decoders that do not know about animations show the first frame.
+    assert_eq!(decoder.decode().unwrap(), still(&first));
+
+    let animation = decoder.animation().unwrap();
+    assert_eq!(animation.loop_count(), 3);
+    assert_eq!(animation.frame_count(), 3);
+    let durations = animation
+        .frames()
+        .map(|frame| (frame.index(), frame.duration()))
+        .collect::<VecA<_>>();
+    assert_eq!(durations, [(0, 100), (1, 50), (2, 200)]);
+    assert_eq!(animation.decode_frame(0).unwrap(), still(&first));
+    assert_eq!(animation.decode_frame(1).unwrap(), still(&second));
+    assert_eq!(animation.decode_frame(2).unwrap(), still(&second));
+    assert_eq!(animation.decode_frame(3), Err(DecodeError::InvalidInput));
+    assert!(animation.frame(3).is_none());
+
+    // A frame without changes costs one byte per block and an entry in the frame table.
+    let shorter = Encoder::encode_animation(&frames[..2], width, height, 3, &options).unwrap();
+    assert_eq!(encoded.len() - shorter.len(), 6 + 6);
+
+    // Still images have no animation.
+    let plain = Encoder::encode_with_options(&first, width, height, &options).unwrap();
+    assert!(Decoder::<()>::new(&plain).unwrap().animation().is_none());
+
+    // Every frame is validated.
+    let mut truncated = encoded.clone();
+    truncated.pop();
+    assert_eq!(
+        Decoder::<()>::new_strict(&truncated).err(),
+        Some(DecodeError::TruncatedChunk {
+            index: 17,
+            offset: encoded.len() - 1
+        })
+    );
+    let mut trailing = encoded.clone();
+    trailing.push(0);
+    assert_eq!(
+        Decoder::<()>::new_strict(&trailing).err(),
+        Some(DecodeError::TrailingData {
+            offset: encoded.len()
+        })
+    );
+    let table = encoded
+        .windows(4)
+        .position(|tag| tag == Metadata::TAG_ANIMATION)
+        .unwrap()
+        + 8;
+    let mut wrong_size = encoded.clone();
+    wrong_size[table + 4] += 1;
+    assert!(matches!(
+        Decoder::<()>::new_strict(&wrong_size).err(),
+        Some(DecodeError::InvalidFrame { index: 0, .. })
+    ));
+    assert_eq!(
+        Encoder::encode_animation(&[], width, height, 0, &options),
+        Err(EncodeError::InvalidInput)
+    );
+
+    // Lossless transformations keep only the first frame.
+    let cropped = transform::crop(&encoded, 0, 0, 8, 8).unwrap();
+    let decoder = Decoder::<()>::new_strict(&cropped).unwrap();
+    assert!(decoder.animation().is_none());
+}
+
+#[test]
+fn delta_chunk() {
+    let mut random = lcg(12345);
+    let reference: [u8; 96] = core::array::from_fn(|_| (random() >> 8) as u8 & 0x3F);
+    let mut source = reference;
+    source[10] ^= 0x15;
+    source[70] ^= 0x2A;
+
+    let mut regular = VecH::new();
+    chunk::compress(&source, &mut regular, CompressionLevel::Best);
+    for level in [
+        CompressionLevel::Fast,
+        CompressionLevel::Default,
+        CompressionLevel::Best,
+    ] {
+        let mut delta = VecH::new();
+        chunk::compress_delta(&source, &reference, &mut delta, level);
+        assert!(delta.len() < regular.len() / 4, "{:?}", delta);
+        assert!(chunk::is_valid_delta_size(delta.len()));
+
+        let mut output = VecH::new();
+        chunk::decompress_delta(&delta, &reference, &mut output).unwrap();
+        assert_eq!(output.as_slice(), &source);
+    }
+
+    // Far matches are only allowed in delta chunks.
+    let far = [0x00, 0x40 | 63, 0x40, 0x00, 0x00];
+    let mut output = VecH::new();
+    assert_eq!(
+        chunk::decompress(&far, &mut output),
+        Err(chunk::ChunkError::ReservedOpcode(1))
+    );
+    assert_eq!(
+        chunk::decompress_delta(&far, &reference, &mut output),
+        Err(chunk::ChunkError::OutputUnderflow(5))
+    );
+    assert_eq!(
+        chunk::decompress_delta(&[0x40, 0x7F], &reference, &mut output),
+        Err(chunk::ChunkError::MatchOutOfRange(0))
+    );
+}
+
+#[test]
+fn animation_delta() {
+    let width = 16;
+    let height = 16;
+    let options = EncoderOptions::new();
+    // A dot that moves over a gradient
+    let images = (0..12)
+        .map(|step| {
+            let mut data = gradient_image(width, height);
+            for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let index = ((5 + y) * width as usize + step + x) * 3;
+                data[index..index + 3].copy_from_slice(&[255, 255, 255]);
+            }
+            data
+        })
+        .collect::<VecA<_>>();
+    let frames = images
+        .iter()
+        .map(|data| Frame { data, duration: 30 })
+        .collect::<VecA<_>>();
+    let encoded = Encoder::encode_animation(&frames, width, height, 0, &options).unwrap();
+    let decoder = Decoder::<()>::new_strict(&encoded).unwrap();
+    let animation = decoder.animation().unwrap();
+    // Without delta chunks, every changed block would be stored as a regular chunk.
+    let mut regular = 0;
+    let mut previous = VecA::new();
+    for (index, data) in images.iter().enumerate() {
+        let still = Encoder::encode_with_options(data, width, height, &options).unwrap();
+        let chunks = Decoder::<()>::new(&still)
+            .unwrap()
+            .chunks()
+            .map(|chunk| chunk.unwrap().payload.to_vec())
+            .collect::<VecA<_>>();
+        if index > 0 {
+            regular += chunks
+                .iter()
+                .zip(&previous)
+                .map(|(chunk, previous)| {
+                    if chunk == previous {
+                        1
+                    } else {
+                        1 + chunk.len()
+                    }
+                })
+                .sum::<usize>();
+        }
+        previous = chunks;
+        assert_eq!(
+            animation.decode_frame(index).unwrap(),
+            Decoder::<()>::new(&still).unwrap().decode().unwrap(),
+            "frame {}",
+            index
+        );
+    }
+    let inter = animation
+        .frames()
+        .skip(1)
+        .map(|frame| frame.data_len())
+        .sum::<usize>();
+    // A block that changes in every frame alternates between regular and delta chunks.
+    assert!(inter * 4 < regular * 3, "{} {}", inter, regular);
+
+    // A delta chunk must be based on a regular chunk in the previous frame, not an unchanged one.
+    let first = gradient_image(8, 8);
+    let mut last = first.clone();
+    last[0] ^= 0x40;
+    let mut frames = alloc::vec![
+        Frame {
+            data: &first,
+            duration: 30,
+        };
+        11
+    ];
+    frames[10].data = &last;
+    let encoded = Encoder::encode_animation(&frames, 8, 8, 0, &options).unwrap();
+    let table = encoded
+        .windows(4)
+        .position(|tag| tag == Metadata::TAG_ANIMATION)
+        .unwrap()
+        + 8;
+    let entry = table + 2 + 10 * 6 + 2;
+    let size = u32::from_le_bytes(encoded[entry..entry + 4].try_into().unwrap()) as usize;
+    let offset = encoded.len() - size;
+    assert!(encoded[offset] & chunk::DELTA_FLAG == 0);
+
+    let mut buf = VecH::new();
+    let mut planes = VecH::new();
+    Decoder::<()>::new(&encoded)
+        .unwrap()
+        .chunks()
+        .next()
+        .unwrap()
+        .unwrap()
+        .decompress(None, &mut planes)
+        .unwrap();
+    let mut target = VecH::new();
+    chunk::decompress(&encoded[offset + 1..], &mut target).unwrap();
+    chunk::compress_delta(
+        target.as_slice().try_into().unwrap(),
+        planes.as_slice().try_into().unwrap(),
+        &mut buf,
+        CompressionLevel::Best,
+    );
+    let mut forged = encoded[..offset].to_vec();
+    forged.push(chunk::DELTA_FLAG | buf.len() as u8);
+    forged.extend_from_slice(&buf);
+    forged[entry..entry + 4].copy_from_slice(&(1 + buf.len() as u32).to_le_bytes());
+    assert_eq!(
+        Decoder::<()>::new_strict(&forged).err(),
+        Some(DecodeError::MissingReference { index: 10, offset })
+    );
+
+    // Right after the regular chunk, the same change is stored as a delta chunk.
+    let encoded = Encoder::encode_animation(&frames[9..], 8, 8, 0, &options).unwrap();
+    let decoder = Decoder::<()>::new_strict(&encoded).unwrap();
+    let animation = decoder.animation().unwrap();
+    let frame = animation.frame(1).unwrap();
+    let offset = encoded.len() - frame.data_len();
+    assert_eq!(
+        encoded[offset],
+        chunk::DELTA_FLAG | (frame.data_len() - 1) as u8
+    );
+    let still = Encoder::encode_with_options(&last, 8, 8, &options).unwrap();
+    assert_eq!(
+        animation.decode_frame(1).unwrap(),
+        Decoder::<()>::new(&still).unwrap().decode().unwrap()
+    );
+}
+
+#[test]
+fn atlas() {
+    let options = EncoderOptions::new();
+    let sheet_pixels = gradient_image(24, 16);
+    let sheet = Encoder::encode_with_options(&sheet_pixels, 24, 16, &options).unwrap();
+    let icon_pixels = gradient_image(5, 7);
+    let icon = Encoder::encode_with_options(&icon_pixels, 5, 7, &options).unwrap();
+
+    let mut builder = AtlasBuilder::new();
+    assert_eq!(builder.add_image("sheet", &sheet), Ok(0));
+    assert_eq!(builder.add_image("icon", &icon), Ok(1));
+    assert_eq!(builder.add_region("arrow", 0, 8, 4, 10, 6), Ok(2));
+    assert_eq!(
+        builder.add_image("icon", &icon),
+        Err(EncodeError::InvalidInput)
+    );
+    assert_eq!(
+        builder.add_image("broken", &icon[1..]),
+        Err(EncodeError::InvalidInput)
+    );
+    assert_eq!(
+        builder.add_region("outside", 0, 20, 0, 5, 1),
+        Err(EncodeError::InvalidInput)
+    );
+    assert_eq!(
+        builder.add_region("nested", 2, 0, 0, 1, 1),
+        Err(EncodeError::InvalidInput)
+    );
+    let encoded = builder.build().unwrap();
+
+    let atlas = Atlas::<()>::new(&encoded).unwrap();
+    assert_eq!(atlas.len(), 3);
+    let names = atlas
+        .entries()
+        .map(|entry| entry.name())
+        .collect::<VecA<_>>();
+    assert_eq!(names, ["sheet", "icon", "arrow"]);
+    assert_eq!(atlas.position("arrow"), Some(2));
+    assert!(atlas.find("missing").is_none());
+    assert!(atlas.get(3).is_none());
+
+    let entry = atlas.find("icon").unwrap();
+    assert!(!entry.is_region());
+    assert_eq!((entry.width(), entry.height()), (5, 7));
+    let decoded = Decoder::<()>::new(&icon).unwrap().decode().unwrap();
+    assert_eq!(entry.decode().unwrap(), decoded);
+    assert_eq!(entry.decoder().decode().unwrap(), decoded);
+
+    let entry = atlas.get(2).unwrap();
+    assert!(entry.is_region());
+    assert_eq!(
+        (entry.left(), entry.top(), entry.width(), entry.height()),
+        (8, 4, 10, 6)
+    );
+    let sheet_decoded = Decoder::<()>::new(&sheet).unwrap().decode().unwrap();
+    let expected = sheet_decoded
+        .chunks(24 * 3)
+        .skip(4)
+        .take(6)
+        .flat_map(|row| &row[8 * 3..18 * 3])
+        .copied()
+        .collect::<VecA<_>>();
+    assert_eq!(entry.decode().unwrap(), expected);
+
+    // Malformed atlases are rejected.
+    assert!(Atlas::<()>::new(&encoded[..20]).is_none());
+    let mut bad_magic = encoded.clone();
+    bad_magic[1] = b'x';
+    assert!(Atlas::<()>::new(&bad_magic).is_none());
+    let empty = AtlasBuilder::new().build().unwrap();
+    assert!(Atlas::<()>::new(&empty).unwrap().is_empty());
+}
+
+#[cfg(feature = "embedded")]
+mod embedded {
+    use super::{
+        ROUND_TRIP_SEEDS, gradient_image, lcg, mutate, round_trip_image, untrusted_corpus,
+    };
+    use crate::lz::CompressionLevel;
+    use crate::{
+        Atlas, AtlasBuilder, DecodeError, Decoder, DrawError, Encoder, EncoderOptions, Frame,
+        Rotation, Scale,
+    };
+    use embedded_graphics::{
+        image::ImageDrawable,
+        pixelcolor::{Rgb888, RgbColor},
+        prelude::*,
+        primitives::Rectangle,
+    };
+
+    /// Draw target that records the pixels in RGB888 format.
+    struct Framebuffer {
+        width: u32,
+        height: u32,
+        pixels: alloc::vec::Vec<u8>,
+    }
+
+    impl Framebuffer {
+        fn new(width: u32, height: u32) -> Self {
+            Self {
+                width,
+                height,
+                pixels: alloc::vec![0; (width * height * 3) as usize],
+            }
+        }
+    }
+
+    impl OriginDimensions for Framebuffer {
+        fn size(&self) -> Size {
+            Size::new(self.width, self.height)
+        }
+    }
+
+    impl DrawTarget for Framebuffer {
+        type Color = Rgb888;
+        type Error = core::convert::Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            for Pixel(point, color) in pixels {
+                // Pixels outside the target are discarded.
+                let (x, y) = (point.x as u32, point.y as u32);
+                if x >= self.width || y >= self.height {
+                    continue;
+                }
+                let index = ((y * self.width + x) * 3) as usize;
+                self.pixels[index..index + 3].copy_from_slice(&[color.r(), color.g(), color.b()]);
+            }
+            Ok(())
+        }
+    }
+
+    /// Draw target that fails after the given number of fills.
+    struct FailingTarget {
+        fills: usize,
+        limit: usize,
+    }
+
+    impl OriginDimensions for FailingTarget {
+        fn size(&self) -> Size {
+            Size::new(64, 64)
+        }
+    }
+
+    impl DrawTarget for FailingTarget {
+        type Color = Rgb888;
+        type Error = ();
+
+        fn draw_iter<I>(&mut self, _pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            self.fills += 1;
+            if self.fills > self.limit {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Draw untrusted data in every way. None of them may panic.
+    fn draw_untrusted(blob: &[u8]) {
+        let mut target = Framebuffer::new(32, 24);
+        if let Some(atlas) = Atlas::<Rgb888>::new(blob) {
+            for entry in atlas.entries() {
+                let _ = entry.draw(&mut target);
+            }
+        }
+        let Some(decoder) = Decoder::<Rgb888>::new(blob) else {
+            return;
+        };
+        for (verify, lenient) in [(false, false), (false, true), (true, true)] {
+            let decoder = Decoder::<Rgb888>::new(blob)
+                .unwrap()
+                .with_verification(verify)
+                .with_lenient(lenient);
+            let _ = decoder.draw(&mut target);
+            let _ = decoder.draw_sub_image(
+                &mut target,
+                &Rectangle::new(Point::new(-5, 3), Size::new(20, 40)),
+            );
+        }
+        let _ = decoder.dithered().draw(&mut target);
+        let _ = decoder.transformed().draw(&mut target);
+        for scale in [Scale::Half, Scale::Eighth] {
+            let _ = decoder.scaled(scale).draw(&mut target);
+        }
+        if let Some(animation) = decoder.animation() {
+            for frame in animation.frames().take(4) {
+                let _ = frame.draw(&mut target);
+            }
+        }
+    }
+
+    #[test]
+    fn untrusted_draw() {
+        let mut random = lcg(0x1357_9BDF);
+        for blob in untrusted_corpus() {
+            draw_untrusted(&blob);
+            for _ in 0..200 {
+                let mut blob = blob.clone();
+                for _ in 0..1 + random() % 3 {
+                    mutate(&mut blob, &mut random);
+                }
+                draw_untrusted(&blob);
+            }
+        }
+    }
+
+    #[test]
+    fn random_draw() {
+        for seed in 0..ROUND_TRIP_SEEDS {
+            let (width, height, data) = round_trip_image(seed);
+            for level in [
+                CompressionLevel::Fast,
+                CompressionLevel::Default,
+                CompressionLevel::Best,
+            ] {
+                let context = format!("seed {seed} {level:?} {width}x{height}");
+                let options = EncoderOptions::new().with_level(level);
+                let encoded = Encoder::encode_with_options(&data, width, height, &options)
+                    .unwrap_or_else(|err| panic!("{context}: {err}"));
+                let decoder =
+                    Decoder::<Rgb888>::new(&encoded).unwrap_or_else(|| panic!("{context}"));
+                let decoded = decoder
+                    .decode()
+                    .unwrap_or_else(|err| panic!("{context}: {err}"));
+                let mut target = Framebuffer::new(width, height);
+                decoder
+                    .try_draw(&mut target)
+                    .unwrap_or_else(|err| panic!("{context}: {err}"));
+                assert!(target.pixels == decoded, "{context}");
+            }
+        }
+    }
+
+    #[test]
+    fn try_draw_truncated() {
+        let width = 24;
+        let height = 16;
+        let data = gradient_image(width, height);
+        let encoded =
+            Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+        let truncated = &encoded[..encoded.len() - 10];
+        assert!(Decoder::<Rgb888>::new_strict(truncated).is_err());
+
+        let decoder = Decoder::<Rgb888>::new(truncated).unwrap();
+        let err = decoder.validate().unwrap_err();
+        assert!(matches!(err, DecodeError::TruncatedChunk { index: 5, .. }));
+
+        // The fallible path reports the damaged chunk, while `draw` stops silently.
+        let mut target = Framebuffer::new(width, height);
+        assert_eq!(decoder.try_draw(&mut target), Err(DrawError::Decode(err)));
+        assert_eq!(decoder.draw(&mut target), Ok(()));
+        assert_eq!(
+            decoder.dithered().try_draw(&mut target),
+            Err(DrawError::Decode(err))
+        );
+        assert_eq!(
+            decoder.scaled(Scale::Half).try_draw(&mut target),
+            Err(DrawError::Decode(err))
+        );
+        assert_eq!(
+            decoder.transformed().try_draw(&mut target),
+            Err(DrawError::Decode(err))
+        );
+
+        // The area before the damaged chunk can still be drawn.
+        let area = Rectangle::new(Point::zero(), Size::new(width, 8));
+        assert_eq!(decoder.try_draw_sub_image(&mut target, &area), Ok(()));
+    }
+
+    #[test]
+    fn scaled_draw() {
+        let width = 37;
+        let height = 21;
+        let data = gradient_image(width, height);
+        let encoded =
+            Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+        let decoder = Decoder::<Rgb888>::new(&encoded).unwrap();
+
+        for scale in [Scale::Full, Scale::Half, Scale::Quarter, Scale::Eighth] {
+            let expected = decoder.decode_scaled(scale).unwrap();
+            let image = decoder.scaled(scale);
+            let size = image.size();
+            let mut target = Framebuffer::new(size.width, size.height);
+            image.draw(&mut target).unwrap();
+            assert_eq!(target.pixels, expected, "{:?}", scale);
+
+            // Only the blocks in the area are decoded, but the pixels at its edges are the same.
+            let area = Rectangle::new(Point::new(1, 1), Size::new(3, 2));
+            let mut target = Framebuffer::new(3, 2);
+            image.draw_sub_image(&mut target, &area).unwrap();
+            for y in 0..2 {
+                for x in 0..3 {
+                    let actual = (y * 3 + x) * 3;
+                    let expected_index = ((y + 1) * size.width as usize + x + 1) * 3;
+                    assert_eq!(
+                        target.pixels[actual..actual + 3],
+                        expected[expected_index..expected_index + 3],
+                        "{:?} ({}, {})",
+                        scale,
+                        x,
+                        y
+                    );
+                }
+            }
+
+            // Drawing stops at the first error of the target.
+            let mut target = FailingTarget { fills: 0, limit: 0 };
+            assert_eq!(image.try_draw(&mut target), Err(DrawError::Target(())));
+            assert_eq!(target.fills, 1);
+        }
+
+        // The damaged chunks after the area are not read.
+        let truncated = &encoded[..encoded.len() - 10];
+        let decoder = Decoder::<Rgb888>::new(truncated).unwrap();
+        let image = decoder.scaled(Scale::Half);
+        let mut target = Framebuffer::new(8, 4);
+        let area = Rectangle::new(Point::zero(), Size::new(8, 4));
+        assert_eq!(image.try_draw_sub_image(&mut target, &area), Ok(()));
+        let mut target = Framebuffer::new(19, 11);
+        assert!(image.try_draw(&mut target).is_err());
+    }
+
+    #[test]
+    fn transformed_draw() {
+        let width = 19;
+        let height = 11;
+        let data = gradient_image(width, height);
+        let encoded =
+            Encoder::encode_with_options(&data, width, height, &EncoderOptions::new()).unwrap();
+        let decoder = Decoder::<Rgb888>::new(&encoded).unwrap();
+        let decoded = decoder.decode().unwrap();
+
+        for rotation in [
+            Rotation::Deg0,
+            Rotation::Deg90,
+            Rotation::Deg180,
+            Rotation::Deg270,
+        ] {
+            for (flip_h, flip_v) in [(false, false), (true, false), (false, true)] {
+                for zoom in [1, 3] {
+                    let image = decoder
+                        .transformed()
+                        .with_rotation(rotation)
+                        .with_flip_horizontal(flip_h)
+                        .with_flip_vertical(flip_v)
+                        .with_zoom(zoom);
+                    let size = image.size();
+                    let (out_width, out_height) = if rotation.swaps_axes() {
+                        (height * zoom, width * zoom)
+                    } else {
+                        (width * zoom, height * zoom)
+                    };
+                    assert_eq!(size, Size::new(out_width, out_height));
+
+                    // A smaller target clips the image.
+                    for (target_width, target_height) in [(out_width, out_height), (9, 7)] {
+                        let mut target = Framebuffer::new(target_width, target_height);
+                        image.draw(&mut target).unwrap();
+
+                        for dy in 0..target_height {
+                            for dx in 0..target_width {
+                                let (rx, ry) = (dx / zoom, dy / zoom);
+                                let (x, y) = match rotation {
+                                    Rotation::Deg0 => (rx, ry),
+                                    Rotation::Deg90 => (ry, height - 1 - rx),
+                                    Rotation::Deg180 => (width - 1 - rx, height - 1 - ry),
+                                    Rotation::Deg270 => (width - 1 - ry, rx),
+                                };
+                                let x = if flip_h { width - 1 - x } else { x };
+                                let y = if flip_v { height - 1 - y } else { y };
+                                let expected = ((y * width + x) * 3) as usize;
+                                let actual = ((dy * target_width + dx) * 3) as usize;
+                                assert_eq!(
+                                    target.pixels[actual..actual + 3],
+                                    decoded[expected..expected + 3],
+                                    "{:?} {} {} x{} ({}, {})",
+                                    rotation,
+                                    flip_h,
+                                    flip_v,
+                                    zoom,
+                                    dx,
+                                    dy
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_zoom_limit() {
+        let data = gradient_image(16, 16);
+        let encoded = Encoder::encode_with_options(&data, 16, 16, &EncoderOptions::new()).unwrap();
+        let decoder = Decoder::<Rgb888>::new(&encoded).unwrap();
+        let decoded = decoder.decode().unwrap();
+
+        // The zoom is limited so that the size fits in `i32`.
+        for zoom in [u32::MAX / 8, u32::MAX] {
+            let image = decoder
+                .transformed()
+                .with_rotation(Rotation::Deg90)
+                .with_zoom(zoom);
+            let size = image.size();
+            assert_eq!(size.width, size.height);
+            assert!(size.width <= i32::MAX as u32 && size.width > i32::MAX as u32 - 16);
+
+            let mut target = Framebuffer::new(4, 4);
+            image.draw(&mut target).unwrap();
+            assert_eq!(target.pixels[..3], decoded[15 * 16 * 3..15 * 16 * 3 + 3]);
+        }
+    }
+
+    #[test]
+    fn animation_draw() {
+        let width = 13;
+        let height = 9;
+        let first = gradient_image(width, height);
+        let mut second = first.clone();
+        second[..3].copy_from_slice(&[0, 0, 255]);
+        let frames = [
+            Frame {
+                data: &first,
+                duration: 40,
+            },
+            Frame {
+                data: &second,
+                duration: 40,
+            },
+        ];
+        let encoded =
+            Encoder::encode_animation(&frames, width, height, 0, &EncoderOptions::new()).unwrap();
+        let decoder = Decoder::<Rgb888>::new_strict(&encoded).unwrap();
+        let animation = decoder.animation().unwrap();
+
+        // Drawing the frames in order over the same target gives each frame.
+        let mut target = Framebuffer::new(width, height);
+        for frame in animation.frames() {
+            frame.draw(&mut target).unwrap();
+            assert_eq!(
+                target.pixels,
+                animation.decode_frame(frame.index()).unwrap()
+            );
+        }
+
+        // Only the changed block is drawn.
+        let mut target = Framebuffer::new(width, height);
+        animation.frame(1).unwrap().draw(&mut target).unwrap();
+        let drawn = (0..width * height)
+            .filter(|&i| target.pixels[i as usize * 3..i as usize * 3 + 3] != [0, 0, 0])
+            .count();
+        assert!(drawn > 0 && drawn <= 64);
+    }
+
+    #[test]
+    fn atlas_draw() {
+        let sheet_pixels = gradient_image(24, 16);
+        let sheet =
+            Encoder::encode_with_options(&sheet_pixels, 24, 16, &EncoderOptions::new()).unwrap();
+        let mut builder = AtlasBuilder::new();
+        builder.add_image("sheet", &sheet).unwrap();
+        builder.add_region("arrow", 0, 8, 4, 10, 6).unwrap();
+        let encoded = builder.build().unwrap();
+        let atlas = Atlas::<Rgb888>::new(&encoded).unwrap();
+
+        let entry = atlas.find("arrow").unwrap();
+        assert_eq!(entry.size(), Size::new(10, 6));
+        let mut target = Framebuffer::new(10, 6);
+        entry.draw(&mut target).unwrap();
+        assert_eq!(target.pixels, entry.decode().unwrap());
+    }
+}
+
+/// Tests of the WebAssembly bindings, run with `wasm-pack test --node --features wasm`.
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod wasm {
+    use crate::wasm::{decode_rgba, encode_rgba};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn round_trip() {
+        let width = 13;
+        let height = 11;
+        let rgba = super::gradient_image(width, height)
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF])
+            .collect::<alloc::vec::Vec<_>>();
+        let encoded = encode_rgba(&rgba, width, height).ok().unwrap();
+        let image = decode_rgba(&encoded).ok().unwrap();
+        assert_eq!((image.width(), image.height()), (width, height));
+        assert_eq!(image.data().len(), rgba.len());
+    }
+
+    #[wasm_bindgen_test]
+    fn conformance() {
+        let rgb = include_bytes!("../conformance/valid/v1-edges.rgb");
+        let image = decode_rgba(include_bytes!("../conformance/valid/v1-edges.mpic"))
+            .ok()
+            .unwrap();
+        let expected = rgb
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF])
+            .collect::<alloc::vec::Vec<_>>();
+        assert_eq!(image.data().0, expected);
+    }
+
+    #[wasm_bindgen_test]
+    fn errors() {
+        assert!(decode_rgba(include_bytes!("../conformance/invalid/bad-magic.mpic")).is_err());
+        assert!(
+            decode_rgba(include_bytes!(
+                "../conformance/invalid/truncated-chunk.mpic"
+            ))
+            .is_err()
+        );
+        assert!(encode_rgba(&[0; 4], 2, 1).is_err());
+        assert!(encode_rgba(&[], 0, 0).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{color::*, *};
+use core::marker::PhantomData;
+
+#[cfg(feature = "embedded")]
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Thumbnail stored in the file, with one pixel per block of the image.
+///
+/// See [`Decoder::thumbnail`].
+pub struct Thumbnail<'a, T> {
+    data: &'a [u8],
+    width: u16,
+    height: u16,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T> Decoder<'a, T> {
+    /// Return the thumbnail stored in the file, if any.
+    ///
+    /// The thumbnail is 1/8 the size of the image, rounded up, and is read without decoding the image data.
+    pub fn thumbnail(&self) -> Option<Thumbnail<'a, T>> {
+        let width = self.info().width().div_ceil(8);
+        let height = self.info().height().div_ceil(8);
+        self.metadata().find_map(|record| match record {
+            Metadata::Thumbnail(data) if data.len() == (width * height * 3) as usize => {
+                Some(Thumbnail {
+                    data,
+                    width: width as u16,
+                    height: height as u16,
+                    _phantom: PhantomData,
+                })
+            }
+            _ => None,
+        })
+    }
+}
+
+impl<'a, T> Thumbnail<'a, T> {
+    /// Return the YUV666 triplets of all pixels.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub(crate) fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Return the width of the thumbnail in pixels.
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    /// Return the height of the thumbnail in pixels.
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    /// Return the color of the pixel at the given position.
+    #[inline]
+    pub fn get(&self, x: u32, y: u32) -> Option<MpicYuv666> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+        let index = (y * self.width() + x) as usize * 3;
+        let yuv = &self.data[index..index + 3];
+        Some(MpicYuv666::new(yuv[0] & 0x3F, yuv[1] & 0x3F, yuv[2] & 0x3F))
+    }
+
+    /// Decode the thumbnail to a vector of bytes in RGB888 format (3 bytes per pixel).
+    #[cfg(feature = "alloc")]
+    pub fn decode(&self) -> alloc::vec::Vec<u8> {
+        self.data
+            .chunks_exact(3)
+            .flat_map(|yuv| {
+                let rgb = MpicRgb888::from_yuv(MpicYuv666::new(
+                    yuv[0] & 0x3F,
+                    yuv[1] & 0x3F,
+                    yuv[2] & 0x3F,
+                ));
+                [rgb.r, rgb.g, rgb.b]
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T> OriginDimensions for Thumbnail<'_, T> {
+    #[inline]
+    fn size(&self) -> Size {
+        Size::new(self.width(), self.height())
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<T: PixelColor + From<MpicRgb666>> ImageDrawable for Thumbnail<'_, T> {
+    type Color = T;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.fill_contiguous(
+            &Rectangle::new(Point::zero(), self.size()),
+            self.data.chunks_exact(3).map(|yuv| {
+                MpicRgb666::from(MpicYuv666::new(yuv[0] & 0x3F, yuv[1] & 0x3F, yuv[2] & 0x3F))
+                    .into()
+            }),
+        )
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
@@ -0,0 +1,453 @@
+//! Lossless transformations of encoded MPIC data.
+//!
+//! These operations work on the blocks of the encoded data instead of the pixels,
+//! so the image quality does not degrade no matter how many times they are applied.
+//! Cropping, splitting and concatenation copy the chunks as they are.
+//!
+//! Because the blocks are 8x8 pixels, an edge of the image that ends up on the left or the top
+//! must be at a multiple of 8 pixels. For example, flipping horizontally requires the width to be a multiple of 8,
+//! cropping requires the left and top to be multiples of 8,
+//! and concatenating horizontally requires the width of every image except the last to be a multiple of 8.
+//!
+//! Only the first frame of an animation is kept.
+//! The row offset table and the checksums of a file that has them are recomputed for the result.
+
+use crate::{chunk::UNCOMPRESSED_SIZE, lz::CompressionLevel, orientation::Orientation, *};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Errors that can occur during a transformation.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransformError {
+    /// The input is not valid MPIC data.
+    Decode(DecodeError),
+    /// The operation would move a partial block away from the right or bottom edge.
+    Unaligned,
+    /// The crop rectangle or the tile is empty or extends beyond the image.
+    OutOfBounds,
+    /// The images to concatenate do not have the same height or width, or no image is given.
+    SizeMismatch,
+    /// The result would be wider or taller than 65535 pixels.
+    TooLarge,
+}
+
+impl From<DecodeError> for TransformError {
+    #[inline]
+    fn from(err: DecodeError) -> Self {
+        Self::Decode(err)
+    }
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(err) => write!(f, "{err}"),
+            Self::Unaligned => write!(f, "the image edge is not aligned to 8 pixels"),
+            Self::OutOfBounds => write!(f, "the rectangle is out of bounds"),
+            Self::SizeMismatch => write!(f, "the image sizes do not match"),
+            Self::TooLarge => write!(f, "the image is too large"),
+        }
+    }
+}
+
+impl core::error::Error for TransformError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Rotate the image clockwise.
+///
+/// Rotating by 90 degrees requires the height to be a multiple of 8, by 270 degrees the width,
+/// and by 180 degrees both.
+pub fn rotate(src: &[u8], rotation: Rotation) -> Result<Vec<u8>, TransformError> {
+    reorient(
+        src,
+        Orientation {
+            rotation,
+            flip_horizontal: false,
+            flip_vertical: false,
+        },
+    )
+}
+
+/// Flip the image horizontally. The width must be a multiple of 8.
+pub fn flip_horizontal(src: &[u8]) -> Result<Vec<u8>, TransformError> {
+    reorient(
+        src,
+        Orientation {
+            rotation: Rotation::Deg0,
+            flip_horizontal: true,
+            flip_vertical: false,
+        },
+    )
+}
+
+/// Flip the image vertically. The height must be a multiple of 8.
+pub fn flip_vertical(src: &[u8]) -> Result<Vec<u8>, TransformError> {
+    reorient(
+        src,
+        Orientation {
+            rotation: Rotation::Deg0,
+            flip_horizontal: false,
+            flip_vertical: true,
+        },
+    )
+}
+
+/// Crop the image to the given rectangle. The left and top must be multiples of 8.
+///
+/// The chunks are copied as they are, so this is faster than the other transformations.
+pub fn crop(
+    src: &[u8],
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, TransformError> {
+    let source = Source::parse(src)?;
+    if width == 0
+        || height == 0
+        || left
+            .checked_add(width)
+            .is_none_or(|right| right > source.width())
+        || top
+            .checked_add(height)
+            .is_none_or(|bottom| bottom > source.height())
+    {
+        return Err(TransformError::OutOfBounds);
+    }
+    if (left | top) & 7 != 0 {
+        return Err(TransformError::Unaligned);
+    }
+    Ok(source.extract(left, top, width, height))
+}
+
+/// Split the image into tiles of the given size, in order from the top-left. The size must be a multiple of 8.
+///
+/// The tiles on the right and bottom edges are smaller if the image size is not a multiple of the tile size.
+/// The chunks are copied as they are.
+pub fn split(
+    src: &[u8],
+    tile_width: u32,
+    tile_height: u32,
+) -> Result<Vec<Vec<u8>>, TransformError> {
+    let source = Source::parse(src)?;
+    if tile_width == 0 || tile_height == 0 {
+        return Err(TransformError::OutOfBounds);
+    }
+    if (tile_width | tile_height) & 7 != 0 {
+        return Err(TransformError::Unaligned);
+    }
+    let mut tiles = Vec::new();
+    for top in (0..source.height()).step_by(tile_height as usize) {
+        for left in (0..source.width()).step_by(tile_width as usize) {
+            let width = tile_width.min(source.width() - left);
+            let height = tile_height.min(source.height() - top);
+            tiles.push(source.extract(left, top, width, height));
+        }
+    }
+    Ok(tiles)
+}
+
+/// Place the images side by side from left to right. The images must have the same height.
+///
+/// The width of every image except the last must be a multiple of 8.
+/// The metadata of the first image is kept, and the thumbnails are joined if every image has one.
+pub fn hconcat(images: &[&[u8]]) -> Result<Vec<u8>, TransformError> {
+    concat(images, true)
+}
+
+/// Stack the images from top to bottom. The images must have the same width.
+///
+/// The height of every image except the last must be a multiple of 8.
+/// The metadata of the first image is kept, and the thumbnails are joined if every image has one.
+pub fn vconcat(images: &[&[u8]]) -> Result<Vec<u8>, TransformError> {
+    concat(images, false)
+}
+
+fn concat(images: &[&[u8]], horizontal: bool) -> Result<Vec<u8>, TransformError> {
+    let sources = images
+        .iter()
+        .map(|src| Source::parse(src))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = sources.first().ok_or(TransformError::SizeMismatch)?;
+
+    // The size along the direction of concatenation and across it.
+    let size = |source: &Source| {
+        if horizontal {
+            (source.width(), source.height())
+        } else {
+            (source.height(), source.width())
+        }
+    };
+    let across = size(first).1;
+    if sources.iter().any(|source| size(source).1 != across) {
+        return Err(TransformError::SizeMismatch);
+    }
+    if sources[..sources.len() - 1]
+        .iter()
+        .any(|source| size(source).0 & 7 != 0)
+    {
+        return Err(TransformError::Unaligned);
+    }
+    let along = sources
+        .iter()
+        .try_fold(0u32, |sum, source| sum.checked_add(size(source).0))
+        .ok_or(TransformError::TooLarge)?;
+    let (width, height) = if horizontal {
+        (along, across)
+    } else {
+        (across, along)
+    };
+    if width > 0xFFFF || height > 0xFFFF {
+        return Err(TransformError::TooLarge);
+    }
+
+    let mut blocks = Vec::new();
+    if horizontal {
+        for y in 0..first.rows() {
+            for (index, source) in sources.iter().enumerate() {
+                blocks.extend((0..source.cols()).map(|x| (index, y * source.cols() + x)));
+            }
+        }
+    } else {
+        for (index, source) in sources.iter().enumerate() {
+            blocks.extend((0..source.cols() * source.rows()).map(|block| (index, block)));
+        }
+    }
+    let version = sources
+        .iter()
+        .map(|source| source.version)
+        .fold(required_version(width, height), Version::max);
+    Ok(assemble(&sources, width, height, version, &blocks))
+}
+
+fn reorient(src: &[u8], orientation: Orientation) -> Result<Vec<u8>, TransformError> {
+    let source = Source::parse(src)?;
+    let (width, height) = (source.width(), source.height());
+
+    // The pixel that moves to the top-left corner must be at the top-left of a block.
+    let (x0, y0) = orientation.unmap(0, 0, width, height);
+    if (x0 != 0 && width & 7 != 0) || (y0 != 0 && height & 7 != 0) {
+        return Err(TransformError::Unaligned);
+    }
+
+    let (cols, rows) = (width.div_ceil(8), height.div_ceil(8));
+    let (new_width, new_height) = orientation.size(width, height);
+    let (new_cols, new_rows) = orientation.size(cols, rows);
+    let source_index = |x: u32, y: u32| {
+        let (x, y) = orientation.unmap(x, y, cols, rows);
+        (y * cols + x) as usize
+    };
+
+    let thumbnail = source.decoder.thumbnail().map(|thumbnail| {
+        let mut data = Vec::with_capacity((new_cols * new_rows * 3) as usize);
+        for y in 0..new_rows {
+            for x in 0..new_cols {
+                let index = source_index(x, y) * 3;
+                data.extend_from_slice(&thumbnail.data()[index..index + 3]);
+            }
+        }
+        data
+    });
+
+    let mut output = Vec::new();
+    let records = write_header(
+        &mut output,
+        &source.decoder,
+        new_width,
+        new_height,
+        source.version,
+        thumbnail.as_deref(),
+    );
+
+    let mut buf = heapless::Vec::<u8, UNCOMPRESSED_SIZE>::new();
+    for y in 0..new_rows {
+        for x in 0..new_cols {
+            source.chunks[source_index(x, y)].decompress(None, &mut buf)?;
+            let mut planes = [0; UNCOMPRESSED_SIZE];
+            reorient_plane(&buf[0..64], &mut planes[0..64], 8, orientation);
+            reorient_plane(&buf[64..80], &mut planes[64..80], 4, orientation);
+            reorient_plane(&buf[80..96], &mut planes[80..96], 4, orientation);
+
+            let mut packed = heapless::Vec::<u8, 128>::new();
+            chunk::compress(&planes, &mut packed, CompressionLevel::Best);
+            output.push(packed.len() as u8);
+            output.extend_from_slice(&packed);
+        }
+    }
+    records.fill(&mut output, new_width, new_height);
+    Ok(output)
+}
+
+/// Move each value of a square plane to its position after the transformation.
+#[inline]
+fn reorient_plane(src: &[u8], dest: &mut [u8], size: u32, orientation: Orientation) {
+    for y in 0..size {
+        for x in 0..size {
+            let (dx, dy) = orientation.map(x, y, size, size);
+            dest[(dy * size + dx) as usize] = src[(y * size + x) as usize];
+        }
+    }
+}
+
+/// Validated input data with its chunks.
+struct Source<'a> {
+    version: Version,
+    decoder: Decoder<'a, ()>,
+    chunks: Vec<RawChunk<'a>>,
+}
+
+impl<'a> Source<'a> {
+    fn parse(src: &'a [u8]) -> Result<Self, TransformError> {
+        let version = FileHeader::parse(src)?.version();
+        let decoder = Decoder::new_strict(src)?;
+        let chunks = decoder.chunks().collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            version,
+            decoder,
+            chunks,
+        })
+    }
+
+    #[inline]
+    fn width(&self) -> u32 {
+        self.decoder.info().width()
+    }
+
+    #[inline]
+    fn height(&self) -> u32 {
+        self.decoder.info().height()
+    }
+
+    /// Number of blocks in a row
+    #[inline]
+    fn cols(&self) -> usize {
+        self.width().div_ceil(8) as usize
+    }
+
+    /// Number of blocks in a column
+    #[inline]
+    fn rows(&self) -> usize {
+        self.height().div_ceil(8) as usize
+    }
+
+    /// Copy the given rectangle, whose left and top are multiples of 8, to a new file.
+    fn extract(&self, left: u32, top: u32, width: u32, height: u32) -> Vec<u8> {
+        let (bx, by) = ((left / 8) as usize, (top / 8) as usize);
+        let (bw, bh) = (width.div_ceil(8) as usize, height.div_ceil(8) as usize);
+        let blocks = (0..bh)
+            .flat_map(|y| (0..bw).map(move |x| (0, (by + y) * self.cols() + bx + x)))
+            .collect::<Vec<_>>();
+        let version = self.version.max(required_version(width, height));
+        assemble(core::slice::from_ref(self), width, height, version, &blocks)
+    }
+}
+
+/// Make a new file from the given blocks of the sources, copying the chunks as they are.
+///
+/// The metadata of the first source is kept, and the thumbnail is made from the thumbnails of the sources if they all have one.
+fn assemble(
+    sources: &[Source],
+    width: u32,
+    height: u32,
+    version: Version,
+    blocks: &[(usize, usize)],
+) -> Vec<u8> {
+    let thumbnails = sources
+        .iter()
+        .map(|source| source.decoder.thumbnail().map(|thumbnail| thumbnail.data()))
+        .collect::<Option<Vec<_>>>();
+    let thumbnail = thumbnails.map(|thumbnails| {
+        blocks
+            .iter()
+            .flat_map(|&(source, block)| &thumbnails[source][block * 3..block * 3 + 3])
+            .copied()
+            .collect::<Vec<_>>()
+    });
+
+    let mut output = Vec::new();
+    let records = write_header(
+        &mut output,
+        &sources[0].decoder,
+        width,
+        height,
+        version,
+        thumbnail.as_deref(),
+    );
+    for &(source, block) in blocks {
+        let raw = &sources[source].chunks[block];
+        output.push(raw.payload.len() as u8);
+        output.extend_from_slice(raw.payload);
+    }
+    records.fill(&mut output, width, height);
+    output
+}
+
+#[inline]
+fn required_version(width: u32, height: u32) -> Version {
+    FileHeader::new(width, height)
+        .map(|header| header.version())
+        .unwrap_or(Version::CURRENT)
+}
+
+/// Write the file header and the metadata section, replacing the thumbnail.
+///
+/// If the source has a row offset table or checksums, empty records are written last,
+/// to be filled in by [`DeferredRecords::fill`] after the chunks.
+fn write_header(
+    output: &mut Vec<u8>,
+    decoder: &Decoder<()>,
+    width: u32,
+    height: u32,
+    version: Version,
+    thumbnail: Option<&[u8]>,
+) -> DeferredRecords {
+    let header = FileHeader::with_version(width, height, version).unwrap();
+    output.extend_from_slice(&header.to_bytes());
+    let mut deferred = DeferredRecords {
+        data_offset: output.len(),
+        ..Default::default()
+    };
+    if version < Version::V2 {
+        return deferred;
+    }
+    let mut records = decoder
+        .metadata()
+        .filter(|record| !record.is_reserved())
+        .collect::<Vec<_>>();
+    if let Some(thumbnail) = thumbnail {
+        records.push(Metadata::Thumbnail(thumbnail));
+    }
+    let row_offsets = (decoder.has_row_offsets() && recovery::fits_row_offsets(width, height))
+        .then(|| alloc::vec![0; recovery::payload_len(height)]);
+    if let Some(row_offsets) = &row_offsets {
+        records.push(Metadata::RowOffsets(row_offsets));
+    }
+    let checksums = decoder
+        .has_checksum()
+        .then(|| alloc::vec![0; checksum::payload_len(height)]);
+    if let Some(checksums) = &checksums {
+        records.push(Metadata::Checksum(checksums));
+    }
+    // The records have been read from a valid file, so they fit in the section.
+    let section_len = metadata::section_len(&records).unwrap();
+    output.extend_from_slice(&section_len.to_le_bytes());
+    for record in &records {
+        match record {
+            Metadata::RowOffsets(_) => deferred.row_offsets = Some(output.len()),
+            Metadata::Checksum(_) => deferred.checksum = Some(output.len()),
+            _ => {}
+        }
+        metadata::write_records(core::slice::from_ref(record), &mut |bytes: &[u8]| {
+            output.extend_from_slice(bytes)
+        });
+    }
+    deferred.data_offset = output.len();
+    deferred
+}
@@ -0,0 +1,71 @@
+//! WebAssembly bindings for browsers and Node.js
+//!
+//! Enabled by the `wasm` feature. Build with `wasm-pack build --features wasm`, then in JavaScript:
+//!
+//! ```js
+//! const image = decodeRgba(bytes);
+//! context.putImageData(new ImageData(image.data, image.width, image.height), 0, 0);
+//! const file = encodeRgba(context.getImageData(0, 0, width, height).data, width, height);
+//! ```
+
+use crate::{Decoder, Encoder, EncoderOptions, PixelFormat};
+use alloc::{string::ToString, vec::Vec};
+use wasm_bindgen::{Clamped, prelude::*};
+
+/// Image decoded to RGBA pixels, in the layout of `ImageData`.
+#[wasm_bindgen]
+pub struct MpicImage {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl MpicImage {
+    /// Return the width of the image in pixels.
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Return the height of the image in pixels.
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Return the pixels as a `Uint8ClampedArray`, 4 bytes per pixel in the order of red, green, blue and alpha.
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Clamped<Vec<u8>> {
+        Clamped(self.data.clone())
+    }
+}
+
+/// Decode an MPIC file to RGBA pixels, exactly as [`Decoder::decode_rgba`] does.
+///
+/// Throws an `Error` with the description of the [`DecodeError`](crate::DecodeError) if the data is invalid.
+#[wasm_bindgen(js_name = decodeRgba)]
+pub fn decode_rgba(data: &[u8]) -> Result<MpicImage, JsError> {
+    let decoder = Decoder::<()>::new_strict(data).map_err(|err| JsError::new(&err.to_string()))?;
+    Ok(MpicImage {
+        width: decoder.info().width(),
+        height: decoder.info().height(),
+        data: decoder
+            .decode_rgba()
+            .map_err(|err| JsError::new(&err.to_string()))?,
+    })
+}
+
+/// Encode RGBA pixels, such as the data of an `ImageData`, to an MPIC file. Alpha is ignored.
+///
+/// Throws an `Error` with the description of the [`EncodeError`](crate::EncodeError) if the size or the data is invalid.
+#[wasm_bindgen(js_name = encodeRgba)]
+pub fn encode_rgba(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsError> {
+    Encoder::encode_with_options(
+        data,
+        width,
+        height,
+        &EncoderOptions::new().with_pixel_format(PixelFormat::Rgba8888),
+    )
+    .map_err(|err| JsError::new(&err.to_string()))
+}